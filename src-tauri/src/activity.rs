@@ -0,0 +1,196 @@
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::models::ActivitySession;
+
+/// How often the focused window is sampled
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Longest time between two samples that still continues a span. Anything
+/// longer means the sampler was held up, e.g. by the machine sleeping.
+const MAX_SAMPLE_GAP_MS: i64 = 3 * SAMPLE_INTERVAL.as_millis() as i64;
+
+/// Follows which app has focus, as spans of time in `focus_spans`. The time
+/// between two samples goes to the app seen at the second one.
+#[derive(Default)]
+pub struct FocusTracker {
+    current: Option<Span>,
+}
+
+struct Span {
+    app_name: String,
+    end_ms: i64,
+}
+
+/// A change to `focus_spans`, saved by the record writer
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FocusWrite {
+    /// Open a new span, which later `Extend`s apply to
+    Start {
+        app_name: String,
+        start_ms: i64,
+        end_ms: i64,
+    },
+    /// Move the end of the open span
+    Extend { end_ms: i64 },
+}
+
+impl FocusTracker {
+    /// Note that `app` had focus at `now_ms`, returning what to save. `None`
+    /// means nothing should be counted: the user is idle, recording is off
+    /// or the window is excluded.
+    pub fn sample(&mut self, app: Option<&str>, now_ms: i64) -> Option<FocusWrite> {
+        let app = match app {
+            Some(app) => app,
+            None => {
+                self.current = None;
+                return None;
+            }
+        };
+
+        let start_ms = match self.current.as_mut() {
+            Some(span) if now_ms - span.end_ms > MAX_SAMPLE_GAP_MS => now_ms,
+            Some(span) if span.app_name == app => {
+                span.end_ms = now_ms;
+                return Some(FocusWrite::Extend { end_ms: now_ms });
+            }
+            Some(span) => span.end_ms,
+            None => now_ms,
+        };
+
+        self.current = Some(Span {
+            app_name: app.to_string(),
+            end_ms: now_ms,
+        });
+        Some(FocusWrite::Start {
+            app_name: app.to_string(),
+            start_ms,
+            end_ms: now_ms,
+        })
+    }
+}
+
+/// One record's stretch of typing
+pub struct Activity {
+    pub start_ms: i64,
+    pub end_ms: i64,
+    pub app_name: String,
+    pub key_count: i64,
+}
+
+/// Group typing into sessions, starting a new one wherever nothing was typed
+/// for longer than `idle_gap`.
+pub fn group_sessions(mut activity: Vec<Activity>, idle_gap: Duration) -> Vec<ActivitySession> {
+    activity.sort_by_key(|a| a.start_ms);
+    let gap_ms = idle_gap.as_millis() as i64;
+
+    let mut sessions = Vec::new();
+    let mut current: Option<OpenSession> = None;
+    for a in activity {
+        match current.as_mut() {
+            Some(open) if a.start_ms - open.end_ms <= gap_ms => {
+                open.end_ms = open.end_ms.max(a.end_ms);
+                open.records += 1;
+                *open.keys_by_app.entry(a.app_name).or_default() += a.key_count;
+            }
+            _ => {
+                sessions.extend(current.take().map(OpenSession::finish));
+                current = Some(OpenSession {
+                    start_ms: a.start_ms,
+                    end_ms: a.end_ms,
+                    records: 1,
+                    keys_by_app: HashMap::from([(a.app_name, a.key_count)]),
+                });
+            }
+        }
+    }
+    sessions.extend(current.map(OpenSession::finish));
+    sessions
+}
+
+struct OpenSession {
+    start_ms: i64,
+    end_ms: i64,
+    records: i64,
+    keys_by_app: HashMap<String, i64>,
+}
+
+impl OpenSession {
+    fn finish(self) -> ActivitySession {
+        let mut apps: Vec<(String, i64)> = self.keys_by_app.into_iter().collect();
+        apps.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        ActivitySession {
+            start: DateTime::<Utc>::from_timestamp_millis(self.start_ms).unwrap_or_default(),
+            end: DateTime::<Utc>::from_timestamp_millis(self.end_ms).unwrap_or_default(),
+            duration_ms: self.end_ms - self.start_ms,
+            records: self.records,
+            key_count: apps.iter().map(|(_, keys)| keys).sum(),
+            apps: apps.into_iter().map(|(app, _)| app).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn start(app_name: &str, start_ms: i64, end_ms: i64) -> Option<FocusWrite> {
+        Some(FocusWrite::Start {
+            app_name: app_name.to_string(),
+            start_ms,
+            end_ms,
+        })
+    }
+
+    #[test]
+    fn focus_spans_follow_samples() {
+        let mut tracker = FocusTracker::default();
+        assert_eq!(tracker.sample(Some("Editor"), 0), start("Editor", 0, 0));
+        assert_eq!(
+            tracker.sample(Some("Editor"), 5_000),
+            Some(FocusWrite::Extend { end_ms: 5_000 })
+        );
+        // The time since the last sample goes to the new app
+        assert_eq!(
+            tracker.sample(Some("Browser"), 10_000),
+            start("Browser", 5_000, 10_000)
+        );
+        // A long gap starts afresh instead of counting the gap
+        assert_eq!(
+            tracker.sample(Some("Browser"), 60_000),
+            start("Browser", 60_000, 60_000)
+        );
+        assert_eq!(tracker.sample(None, 65_000), None);
+        assert_eq!(
+            tracker.sample(Some("Browser"), 70_000),
+            start("Browser", 70_000, 70_000)
+        );
+    }
+
+    #[test]
+    fn sessions_split_on_idle_gaps() {
+        let activity = |start_ms, end_ms, app: &str, key_count| Activity {
+            start_ms,
+            end_ms,
+            app_name: app.to_string(),
+            key_count,
+        };
+        let sessions = group_sessions(
+            vec![
+                activity(200_000, 210_000, "Browser", 5),
+                activity(0, 10_000, "Editor", 3),
+                activity(20_000, 30_000, "Browser", 1),
+                activity(40_000, 50_000, "Editor", 4),
+            ],
+            Duration::from_secs(60),
+        );
+
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].duration_ms, 50_000);
+        assert_eq!(sessions[0].records, 3);
+        assert_eq!(sessions[0].key_count, 8);
+        assert_eq!(sessions[0].apps, ["Editor", "Browser"]);
+        assert_eq!(sessions[1].apps, ["Browser"]);
+    }
+}
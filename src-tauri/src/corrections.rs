@@ -0,0 +1,234 @@
+use crate::editor::{Edit, TextEditor};
+
+/// Longest run of letters still counted as a word; longer runs are pasted
+/// identifiers or unspaced CJK sentences rather than words
+const MAX_WORD_CHARS: usize = 24;
+
+/// What one record's keystrokes say about corrections
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Analysis {
+    /// Backspace and Delete presses
+    pub corrections: i64,
+    /// Keys that typed a character. Text committed by an input method
+    /// counts once per committed character.
+    pub printable: i64,
+    /// Words of the final text that were edited on the way there, lowercased
+    pub corrected_words: Vec<String>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token<'a> {
+    Char(char),
+    /// The name inside a `[Backspace]`-style token
+    Key(&'a str),
+}
+
+/// Corrections as a share of printable keys
+pub fn ratio(corrections: i64, printable: i64) -> f64 {
+    if printable <= 0 {
+        return 0.0;
+    }
+    corrections as f64 / printable as f64
+}
+
+/// Replay a record's stored keystrokes, counting corrections and finding
+/// the words they went into. A word counts as corrected when a deletion
+/// touched it or it was typed in place of deleted text.
+pub fn analyze(content: &str) -> Analysis {
+    let mut analysis = Analysis::default();
+    let mut editor = TextEditor::default();
+    // One flag per character of the editor's text: touched by a correction
+    let mut marked: Vec<bool> = Vec::new();
+    // Letters typed straight after a deletion replace what it removed
+    let mut replacing = false;
+
+    for token in tokens(content) {
+        let edit = match token {
+            Token::Char(c) => {
+                analysis.printable += 1;
+                Edit::Insert(c)
+            }
+            Token::Key("Enter") => Edit::Insert('\n'),
+            Token::Key("Tab") => Edit::Insert('\t'),
+            Token::Key("Backspace") => {
+                analysis.corrections += 1;
+                Edit::Backspace
+            }
+            Token::Key("Delete") => {
+                analysis.corrections += 1;
+                Edit::Delete
+            }
+            Token::Key("Up") => Edit::Up,
+            Token::Key("Down") => Edit::Down,
+            Token::Key("Left") => Edit::Left,
+            Token::Key("Right") => Edit::Right,
+            Token::Key(_) => continue,
+        };
+
+        let (len, cursor) = (editor.len(), editor.cursor());
+        editor.apply(edit);
+        match edit {
+            Edit::Insert(c) => {
+                replacing &= is_word_char(c);
+                marked.insert(cursor, replacing);
+            }
+            Edit::Backspace | Edit::Delete => {
+                let at = editor.cursor();
+                if editor.len() < len {
+                    marked.remove(at);
+                }
+                if at > 0 {
+                    marked[at - 1] = true;
+                }
+                if let Some(next) = marked.get_mut(at) {
+                    *next = true;
+                }
+                replacing = true;
+            }
+            _ => replacing = false,
+        }
+    }
+
+    // A trailing separator ends the last word
+    let text = editor.text();
+    let chars = text.chars().chain(Some(' '));
+    let mut word = String::new();
+    let mut corrected = false;
+    for (c, marked) in chars.zip(marked.into_iter().chain(Some(false))) {
+        if is_word_char(c) {
+            word.extend(c.to_lowercase());
+            corrected |= marked;
+            continue;
+        }
+        if corrected && word.chars().count() <= MAX_WORD_CHARS {
+            analysis.corrected_words.push(word.clone());
+        }
+        word.clear();
+        corrected = false;
+    }
+    analysis
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric()
+}
+
+/// Split stored content into typed characters and key tokens. A `[` that
+/// does not start a token the listener writes is a typed bracket.
+fn tokens(content: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut rest = content;
+    while let Some(c) = rest.chars().next() {
+        if c == '[' {
+            if let Some((name, len)) = key_token(rest) {
+                tokens.push(Token::Key(name));
+                rest = &rest[len..];
+                continue;
+            }
+        }
+        tokens.push(Token::Char(c));
+        rest = &rest[c.len_utf8()..];
+    }
+    tokens
+}
+
+/// The key name and length of the token `s` starts with, if any
+fn key_token(s: &str) -> Option<(&str, usize)> {
+    let close = s[1..].find(']')? + 1;
+    let mut name = &s[1..close];
+    // A chord on the `]` key itself, such as `[Ctrl+]]`
+    if name.ends_with('+') && s[close + 1..].starts_with(']') {
+        name = &s[1..close + 1];
+    }
+
+    let is_key = matches!(
+        name,
+        "Enter" | "Tab" | "Backspace" | "Delete" | "Esc" | "Up" | "Down" | "Left" | "Right"
+    );
+    let is_chord = ["Ctrl+", "Alt+", "Meta+"]
+        .iter()
+        .any(|prefix| name.starts_with(prefix));
+    (is_key || is_chord).then_some((name, name.len() + 2))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn analysis(corrections: i64, printable: i64, corrected_words: &[&str]) -> Analysis {
+        Analysis {
+            corrections,
+            printable,
+            corrected_words: corrected_words.iter().map(|w| w.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn counts_corrections_and_corrected_words() {
+        let cases = [
+            ("hello world", analysis(0, 11, &[])),
+            ("Helo[Backspace]lo", analysis(1, 6, &["hello"])),
+            ("cat dgo[Backspace][Backspace]og", analysis(2, 9, &["dog"])),
+            ("ab[Left][Delete]", analysis(1, 2, &["a"])),
+            // A separator typed after a deletion starts a new, clean word
+            ("ab[Backspace] cd", analysis(1, 5, &["a"])),
+            // Editing keys and chords are not printable
+            ("a[Enter]b[Tab]c[Esc][Ctrl+S]", analysis(0, 3, &[])),
+            ("x[Ctrl+]]y", analysis(0, 2, &[])),
+            // A bracket that starts no token was typed
+            ("[x]", analysis(0, 3, &[])),
+            ("[Backspace]", analysis(1, 0, &[])),
+        ];
+        for (content, expected) in cases {
+            assert_eq!(analyze(content), expected, "{}", content);
+        }
+    }
+
+    #[test]
+    fn long_runs_are_not_words() {
+        let longest = format!("{}x[Backspace]", "a".repeat(MAX_WORD_CHARS));
+        assert_eq!(
+            analyze(&longest).corrected_words,
+            vec!["a".repeat(MAX_WORD_CHARS)]
+        );
+
+        let too_long = format!("{}x[Backspace]", "a".repeat(MAX_WORD_CHARS + 1));
+        assert!(analyze(&too_long).corrected_words.is_empty());
+    }
+
+    #[test]
+    fn splits_key_tokens() {
+        assert_eq!(
+            tokens("a[Enter][b]"),
+            [
+                Token::Char('a'),
+                Token::Key("Enter"),
+                Token::Char('['),
+                Token::Char('b'),
+                Token::Char(']'),
+            ]
+        );
+        assert_eq!(
+            tokens("[Ctrl+]]]"),
+            [Token::Key("Ctrl+]"), Token::Char(']')]
+        );
+    }
+
+    #[test]
+    fn key_tokens() {
+        assert_eq!(key_token("[Backspace]x"), Some(("Backspace", 11)));
+        assert_eq!(key_token("[Ctrl+S]"), Some(("Ctrl+S", 8)));
+        assert_eq!(key_token("[Ctrl+]]"), Some(("Ctrl+]", 8)));
+        assert_eq!(key_token("[Meta+Alt+]]x"), Some(("Meta+Alt+]", 12)));
+        assert_eq!(key_token("[Alt+[]"), Some(("Alt+[", 7)));
+        assert_eq!(key_token("[Enter"), None);
+        assert_eq!(key_token("[word]"), None);
+        assert_eq!(key_token("[]"), None);
+    }
+
+    #[test]
+    fn ratio_of_printable() {
+        assert_eq!(ratio(1, 4), 0.25);
+        assert_eq!(ratio(3, 0), 0.0);
+    }
+}
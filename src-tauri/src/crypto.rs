@@ -0,0 +1,349 @@
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use chacha20poly1305::aead::{Aead, KeyInit, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+/// A 256-bit key, wiped from memory when dropped.
+pub type Key = Zeroizing<[u8; 32]>;
+
+const MIN_PASSPHRASE_LEN: usize = 8;
+
+/// Marks a column value as `FieldCipher` output; anything else is plaintext
+const FIELD_PREFIX: &str = "enc1:";
+const NONCE_LEN: usize = 24;
+
+/// Argon2id settings for new passphrases: 64 MiB, 3 passes, one lane
+const M_COST: u32 = 64 * 1024;
+const T_COST: u32 = 3;
+const P_COST: u32 = 1;
+
+/// How a passphrase is turned into a key. Kept in a file next to the
+/// database, since the salt is needed before the database can be opened.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KdfParams {
+    /// Hex-encoded random salt
+    pub salt: String,
+    /// Memory cost in KiB
+    pub m_cost: u32,
+    pub t_cost: u32,
+    pub p_cost: u32,
+}
+
+impl KdfParams {
+    /// Current settings with a fresh salt
+    pub fn generate() -> Result<Self, String> {
+        let mut salt = [0u8; 16];
+        getrandom::getrandom(&mut salt).map_err(|e| e.to_string())?;
+        Ok(Self {
+            salt: hex::encode(salt),
+            m_cost: M_COST,
+            t_cost: T_COST,
+            p_cost: P_COST,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        serde_json::from_str(&json).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        write_json(path, self)
+    }
+}
+
+/// The random key record fields are encrypted with, itself encrypted under a
+/// key derived from the user's passphrase. Changing the passphrase only
+/// rewraps this key; rows never need re-encrypting.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyFile {
+    pub kdf: KdfParams,
+    /// Hex-encoded nonce the data key was wrapped with
+    pub nonce: String,
+    /// Hex-encoded wrapped data key
+    pub wrapped_key: String,
+}
+
+impl KeyFile {
+    /// Generate a data key and wrap it under `passphrase`.
+    pub fn create(passphrase: &str) -> Result<(Self, Key), String> {
+        let mut data_key = Zeroizing::new([0u8; 32]);
+        getrandom::getrandom(data_key.as_mut()).map_err(|e| e.to_string())?;
+        let file = Self::wrap(&data_key, passphrase)?;
+        Ok((file, data_key))
+    }
+
+    pub fn unwrap_key(&self, passphrase: &str) -> Result<Key, String> {
+        let wrapping_key = derive_key(passphrase, &self.kdf)?;
+        let nonce = hex::decode(&self.nonce).map_err(|e| format!("Invalid key file: {}", e))?;
+        let wrapped = hex::decode(&self.wrapped_key).map_err(|e| format!("Invalid key file: {}", e))?;
+        if nonce.len() != NONCE_LEN {
+            return Err("Invalid key file: bad nonce".to_string());
+        }
+
+        let plain = Zeroizing::new(
+            XChaCha20Poly1305::new(wrapping_key.as_ref().into())
+                .decrypt(XNonce::from_slice(&nonce), wrapped.as_slice())
+                .map_err(|_| "Wrong passphrase".to_string())?,
+        );
+        let mut data_key = Zeroizing::new([0u8; 32]);
+        if plain.len() != data_key.len() {
+            return Err("Invalid key file: bad key length".to_string());
+        }
+        data_key.copy_from_slice(&plain);
+        Ok(data_key)
+    }
+
+    /// The same data key, wrapped under `new_passphrase` with a fresh salt
+    pub fn rewrap(&self, old_passphrase: &str, new_passphrase: &str) -> Result<Self, String> {
+        let data_key = self.unwrap_key(old_passphrase)?;
+        Self::wrap(&data_key, new_passphrase)
+    }
+
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let json = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        serde_json::from_str(&json).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        write_json(path, self)
+    }
+
+    fn wrap(data_key: &Key, passphrase: &str) -> Result<Self, String> {
+        let kdf = KdfParams::generate()?;
+        let wrapping_key = derive_key(passphrase, &kdf)?;
+        let nonce = random_nonce()?;
+        let wrapped = XChaCha20Poly1305::new(wrapping_key.as_ref().into())
+            .encrypt(XNonce::from_slice(&nonce), data_key.as_slice())
+            .map_err(|e| e.to_string())?;
+        Ok(Self {
+            kdf,
+            nonce: hex::encode(nonce),
+            wrapped_key: hex::encode(wrapped),
+        })
+    }
+}
+
+/// Encrypts individual column values with XChaCha20-Poly1305. The column name
+/// is bound in as associated data, so a value cannot be moved to another
+/// column unnoticed.
+#[derive(Clone)]
+pub struct FieldCipher {
+    cipher: XChaCha20Poly1305,
+}
+
+impl FieldCipher {
+    pub fn new(key: &Key) -> Self {
+        Self {
+            cipher: XChaCha20Poly1305::new(key.as_ref().into()),
+        }
+    }
+
+    /// `enc1:` followed by the base64 of nonce and ciphertext
+    pub fn encrypt(&self, column: &str, plaintext: &str) -> Result<String, String> {
+        let nonce = random_nonce()?;
+        let payload = Payload {
+            msg: plaintext.as_bytes(),
+            aad: column.as_bytes(),
+        };
+        let mut sealed = nonce.to_vec();
+        sealed.extend(
+            self.cipher
+                .encrypt(XNonce::from_slice(&nonce), payload)
+                .map_err(|e| e.to_string())?,
+        );
+        Ok(format!("{}{}", FIELD_PREFIX, BASE64.encode(sealed)))
+    }
+
+    /// Decrypt a value written by `encrypt`. Values without the prefix were
+    /// stored before encryption was turned on and are returned as they are.
+    pub fn decrypt(&self, column: &str, value: &str) -> Result<String, String> {
+        let encoded = match value.strip_prefix(FIELD_PREFIX) {
+            Some(encoded) => encoded,
+            None => return Ok(value.to_string()),
+        };
+        let sealed = BASE64.decode(encoded).map_err(|e| e.to_string())?;
+        if sealed.len() < NONCE_LEN {
+            return Err(format!("Encrypted {} is truncated", column));
+        }
+
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: column.as_bytes(),
+        };
+        let plain = self
+            .cipher
+            .decrypt(XNonce::from_slice(nonce), payload)
+            .map_err(|_| format!("Cannot decrypt {}", column))?;
+        String::from_utf8(plain).map_err(|e| e.to_string())
+    }
+
+    pub fn is_encrypted(value: &str) -> bool {
+        value.starts_with(FIELD_PREFIX)
+    }
+}
+
+/// Where the key derivation settings for the database at `db_path` live. The
+/// file existing is what marks the database as encrypted.
+pub fn kdf_path(db_path: &Path) -> PathBuf {
+    db_path.with_extension("kdf")
+}
+
+/// Settings written while a passphrase change is in progress, and promoted to
+/// `kdf_path` once the database has been rekeyed.
+pub fn pending_kdf_path(db_path: &Path) -> PathBuf {
+    db_path.with_extension("kdf.new")
+}
+
+/// Where the passphrase-protected field encryption key for the database at
+/// `db_path` lives. The file existing turns field encryption on.
+pub fn key_file_path(db_path: &Path) -> PathBuf {
+    db_path.with_extension("key")
+}
+
+/// The rewrapped key file written while a passphrase change is in progress,
+/// and promoted to `key_file_path` once everything else has been changed.
+pub fn pending_key_file_path(db_path: &Path) -> PathBuf {
+    db_path.with_extension("key.new")
+}
+
+/// Where the key for hashed redactions in the database at `db_path` lives
+pub fn redaction_key_path(db_path: &Path) -> PathBuf {
+    db_path.with_extension("redact")
+}
+
+pub fn check_passphrase(passphrase: &str) -> Result<(), String> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        return Err(format!("Passphrase must be at least {} characters", MIN_PASSPHRASE_LEN));
+    }
+    Ok(())
+}
+
+/// Derive a key from `passphrase` with Argon2id.
+pub fn derive_key(passphrase: &str, params: &KdfParams) -> Result<Key, String> {
+    let salt = hex::decode(&params.salt).map_err(|e| format!("Invalid salt: {}", e))?;
+    let argon2 = Argon2::new(
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::new(params.m_cost, params.t_cost, params.p_cost, Some(32)).map_err(|e| e.to_string())?,
+    );
+
+    let mut key = Zeroizing::new([0u8; 32]);
+    argon2
+        .hash_password_into(passphrase.as_bytes(), &salt, key.as_mut())
+        .map_err(|e| e.to_string())?;
+    Ok(key)
+}
+
+fn random_nonce() -> Result<[u8; NONCE_LEN], String> {
+    let mut nonce = [0u8; NONCE_LEN];
+    getrandom::getrandom(&mut nonce).map_err(|e| e.to_string())?;
+    Ok(nonce)
+}
+
+/// Write via a temporary file, so a crash never leaves a truncated file
+fn write_json<T: Serialize>(path: &Path, value: &T) -> Result<(), String> {
+    let json = serde_json::to_string_pretty(value).map_err(|e| e.to_string())?;
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    std::fs::write(&tmp, json).map_err(|e| e.to_string())?;
+    std::fs::rename(&tmp, path).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cipher(byte: u8) -> FieldCipher {
+        FieldCipher::new(&Zeroizing::new([byte; 32]))
+    }
+
+    #[test]
+    fn field_round_trip() {
+        let cipher = cipher(1);
+        let sealed = cipher.encrypt("content", "hello 世界").unwrap();
+        assert!(FieldCipher::is_encrypted(&sealed));
+        assert!(!sealed.contains("hello"));
+        assert_eq!(cipher.decrypt("content", &sealed).unwrap(), "hello 世界");
+
+        // A fresh nonce every time
+        assert_ne!(cipher.encrypt("content", "hello 世界").unwrap(), sealed);
+        // Values stored before encryption was turned on pass through
+        assert!(!FieldCipher::is_encrypted("hello"));
+        assert_eq!(cipher.decrypt("content", "hello").unwrap(), "hello");
+    }
+
+    #[test]
+    fn field_is_bound_to_column_and_key() {
+        let sealed = cipher(1).encrypt("content", "hello").unwrap();
+        assert!(cipher(1).decrypt("final_text", &sealed).is_err());
+        assert!(cipher(2).decrypt("content", &sealed).is_err());
+    }
+
+    #[test]
+    fn rejects_damaged_fields() {
+        let cipher = cipher(1);
+        let sealed = cipher.encrypt("content", "hello").unwrap();
+
+        let truncated = format!("{}{}", FIELD_PREFIX, BASE64.encode([0u8; NONCE_LEN - 1]));
+        assert_eq!(
+            cipher.decrypt("content", &truncated),
+            Err("Encrypted content is truncated".to_string())
+        );
+        assert!(cipher.decrypt("content", "enc1:not base64!").is_err());
+        assert!(cipher.decrypt("content", FIELD_PREFIX).is_err());
+        assert!(cipher.decrypt("content", &sealed[..sealed.len() - 4]).is_err());
+
+        let mut bytes = BASE64.decode(&sealed[FIELD_PREFIX.len()..]).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let flipped = format!("{}{}", FIELD_PREFIX, BASE64.encode(bytes));
+        assert_eq!(
+            cipher.decrypt("content", &flipped),
+            Err("Cannot decrypt content".to_string())
+        );
+    }
+
+    #[test]
+    fn key_file_needs_the_passphrase() {
+        let (file, key) = KeyFile::create("correct horse").unwrap();
+        assert_eq!(*file.unwrap_key("correct horse").unwrap(), *key);
+        assert_eq!(
+            file.unwrap_key("wrong horse").map(|_| ()),
+            Err("Wrong passphrase".to_string())
+        );
+
+        let damaged = KeyFile {
+            nonce: "00".to_string(),
+            ..file
+        };
+        assert!(damaged.unwrap_key("correct horse").is_err());
+    }
+
+    #[test]
+    fn rewrap_keeps_the_data_key() {
+        let (file, key) = KeyFile::create("correct horse").unwrap();
+        let sealed = FieldCipher::new(&key).encrypt("content", "hello").unwrap();
+
+        assert!(file.rewrap("wrong horse", "battery staple").is_err());
+        let rewrapped = file.rewrap("correct horse", "battery staple").unwrap();
+        assert_ne!(rewrapped.kdf.salt, file.kdf.salt);
+        assert!(rewrapped.unwrap_key("correct horse").is_err());
+
+        let key = rewrapped.unwrap_key("battery staple").unwrap();
+        assert_eq!(FieldCipher::new(&key).decrypt("content", &sealed).unwrap(), "hello");
+    }
+
+    #[test]
+    fn passphrase_length() {
+        assert!(check_passphrase("short").is_err());
+        assert!(check_passphrase("long enough").is_ok());
+        // Counted in characters, not bytes
+        assert!(check_passphrase("密码密码").is_err());
+    }
+}
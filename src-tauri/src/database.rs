@@ -1,148 +1,514 @@
-use chrono::{DateTime, NaiveDateTime, Utc};
-use rusqlite::{params, Connection, Result};
-use std::path::PathBuf;
+use chrono::{DateTime, Timelike, Utc};
+use once_cell::sync::OnceCell;
+use rdev::Key;
+use rusqlite::types::Type;
+use rusqlite::{params, Connection, ErrorCode, Result};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Mutex;
+use zeroize::Zeroizing;
 
-use crate::models::{AppStats, DailyStats, InputRecord, SearchFilter};
+use crate::activity::{self, Activity};
+use crate::corrections;
+use crate::crypto::FieldCipher;
+use crate::input::KeyAction;
+use crate::migrations;
+use crate::models::{
+    ActiveTime, ActivitySession, AppSpeed, AppStats, AppTime, BigramCount, Burst, CorrectedWord, CorrectionCount, CorrectionStats, DailyStats, InputRecord,
+    KeyCount, KeyHeatmap, KeyStroke, KeyTally, PendingRecord, SearchFilter, ShortcutStats, Stats, StatsSeries,
+    TypingSpeed,
+};
+use crate::search;
+use crate::stats::{self, Bucket, GroupBy, StatsRange};
+use crate::timezone::Zone;
 
 pub struct Database {
     conn: Mutex<Connection>,
+    /// Set when field encryption is on: typed text, window titles and key
+    /// codes are then stored encrypted, while timestamps and app names stay
+    /// queryable. Key bigrams, which spell out what was typed, are not kept.
+    cipher: OnceCell<FieldCipher>,
+    /// Zone date filters are read in and daily statistics grouped by
+    zone: Mutex<Zone>,
 }
 
+/// Width of a `key_frequency` row
+const HOUR_MILLIS: i64 = 3_600_000;
+
+/// Most bigrams `get_key_heatmap` returns
+const MAX_BIGRAMS: usize = 100;
+
+/// Columns `FieldCipher` protects
+const ENCRYPTED_COLUMNS: [&str; 3] = ["window_title", "content", "final_text"];
+
 impl Database {
     pub fn new(db_path: PathBuf) -> Result<Self> {
+        Self::from_connection(Connection::open(db_path)?)
+    }
+
+    /// A throwaway database, used by the replay harness
+    pub fn in_memory() -> Result<Self> {
+        Self::from_connection(Connection::open_in_memory()?)
+    }
+
+    /// Open a SQLCipher database keyed with `key`. A wrong key fails here,
+    /// with an error `is_wrong_key` recognises.
+    pub fn open_encrypted(db_path: PathBuf, key: &[u8; 32]) -> Result<Self> {
         let conn = Connection::open(db_path)?;
-        let db = Self {
-            conn: Mutex::new(conn),
+        Self::apply_key(&conn, "key", key)?;
+        Self::from_connection(conn)
+    }
+
+    /// Whether `key` opens the SQLCipher database at `db_path`.
+    pub fn verify_key(db_path: &Path, key: &[u8; 32]) -> Result<bool> {
+        let conn = Connection::open(db_path)?;
+        Self::apply_key(&conn, "key", key)?;
+        match conn.query_row("SELECT count(*) FROM sqlite_master", [], |_| Ok(())) {
+            Ok(()) => Ok(true),
+            Err(e) if Self::is_wrong_key(&e) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Whether SQLite was built with SQLCipher (the `sqlcipher` feature).
+    /// Plain SQLite ignores `PRAGMA key`, so this must be checked first.
+    pub fn encryption_available() -> bool {
+        Connection::open_in_memory()
+            .and_then(|conn| conn.query_row("PRAGMA cipher_version", [], |row| row.get::<_, String>(0)))
+            .is_ok()
+    }
+
+    pub fn is_wrong_key(e: &rusqlite::Error) -> bool {
+        e.sqlite_error_code() == Some(ErrorCode::NotADatabase)
+    }
+
+    /// Rewrite this plaintext database as a SQLCipher database keyed with
+    /// `key`, replacing the file in place and reopening it. A failure before
+    /// the file is replaced leaves the plaintext database open as it was.
+    pub fn encrypt(&self, key: &[u8; 32]) -> std::result::Result<(), String> {
+        let mut conn = self.conn.lock().unwrap();
+        let path = match conn.path() {
+            Some(path) if !path.is_empty() => PathBuf::from(path),
+            _ => return Err("Cannot encrypt an in-memory database".to_string()),
+        };
+        let tmp = path.with_extension("db.encrypting");
+        let _ = std::fs::remove_file(&tmp);
+
+        let raw_key = Zeroizing::new(format!("x'{}'", hex::encode(key)));
+        let export = || -> Result<()> {
+            conn.execute(
+                "ATTACH DATABASE ?1 AS encrypted KEY ?2",
+                params![tmp.to_string_lossy(), raw_key.as_str()],
+            )?;
+            conn.query_row("SELECT sqlcipher_export('encrypted')", [], |_| Ok(()))?;
+            // sqlcipher_export copies the schema and rows, but not the header
+            let version: i64 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+            conn.execute_batch(&format!("PRAGMA encrypted.user_version = {}", version))?;
+            conn.execute_batch("DETACH DATABASE encrypted")
         };
-        db.init_tables()?;
-        Ok(db)
-    }
-
-    fn init_tables(&self) -> Result<()> {
-        let conn = self.conn.lock().unwrap();
-        conn.execute_batch(
-            "
-            CREATE TABLE IF NOT EXISTS inputs (
-                id INTEGER PRIMARY KEY AUTOINCREMENT,
-                timestamp DATETIME NOT NULL,
-                app_name TEXT NOT NULL,
-                window_title TEXT,
-                content TEXT NOT NULL,
-                key_count INTEGER DEFAULT 1,
-                created_at DATETIME DEFAULT CURRENT_TIMESTAMP
-            );
-
-            CREATE INDEX IF NOT EXISTS idx_timestamp ON inputs(timestamp);
-            CREATE INDEX IF NOT EXISTS idx_app_name ON inputs(app_name);
-
-            CREATE TABLE IF NOT EXISTS settings (
-                key TEXT PRIMARY KEY,
-                value TEXT NOT NULL
-            );
-            ",
+        if let Err(e) = export() {
+            let _ = std::fs::remove_file(&tmp);
+            return Err(e.to_string());
+        }
+
+        // Close the plaintext file before replacing it
+        let plain = std::mem::replace(&mut *conn, Connection::open_in_memory().map_err(|e| e.to_string())?);
+        if let Err((plain, e)) = plain.close() {
+            *conn = plain;
+            let _ = std::fs::remove_file(&tmp);
+            return Err(e.to_string());
+        }
+        if let Err(e) = std::fs::rename(&tmp, &path) {
+            let _ = std::fs::remove_file(&tmp);
+            let plain = Connection::open(&path).and_then(|plain| Self::configure(&plain).map(|_| plain));
+            return match plain {
+                Ok(plain) => {
+                    *conn = plain;
+                    Err(e.to_string())
+                }
+                Err(reopen) => Err(format!("{}; reopening the database failed: {}", e, reopen)),
+            };
+        }
+
+        let encrypted = Connection::open(&path)
+            .and_then(|encrypted| Self::apply_key(&encrypted, "key", key).map(|_| encrypted))
+            .and_then(|encrypted| Self::configure(&encrypted).map(|_| encrypted))
+            .map_err(|e| format!("The database was encrypted, but reopening it failed: {}", e))?;
+        *conn = encrypted;
+        Ok(())
+    }
+
+    /// Re-encrypt the open SQLCipher database under a new key.
+    pub fn rekey(&self, key: &[u8; 32]) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        Self::apply_key(&conn, "rekey", key)
+    }
+
+    /// Turn on field encryption for new records, and for `encrypt_fields`.
+    pub fn set_field_cipher(&self, cipher: FieldCipher) {
+        let _ = self.cipher.set(cipher);
+    }
+
+    pub fn field_encrypted(&self) -> bool {
+        self.cipher.get().is_some()
+    }
+
+    /// Encrypt the protected columns of every row stored before field
+    /// encryption was turned on, and drop the key bigrams. Returns the number
+    /// of records changed.
+    pub fn encrypt_fields(&self) -> Result<usize> {
+        let cipher = match self.cipher.get() {
+            Some(cipher) => cipher,
+            None => return Ok(0),
+        };
+
+        let mut conn = self.conn.lock().unwrap();
+        // Overwrite freed pages, so the plaintext is not left behind in them
+        conn.pragma_update(None, "secure_delete", "ON")?;
+        let tx = conn.transaction()?;
+        let rows: Vec<(i64, [Option<String>; 3])> = tx
+            .prepare("SELECT id, window_title, content, final_text FROM inputs")?
+            .query_map([], |row| Ok((row.get(0)?, [row.get(1)?, row.get(2)?, row.get(3)?])))?
+            .collect::<Result<_>>()?;
+
+        let mut changed = 0;
+        {
+            let mut stmt = tx.prepare("UPDATE inputs SET window_title = ?1, content = ?2, final_text = ?3 WHERE id = ?4")?;
+            for (id, values) in rows {
+                if values.iter().flatten().all(|v| FieldCipher::is_encrypted(v)) {
+                    continue;
+                }
+                let mut sealed: [Option<String>; 3] = Default::default();
+                for (i, value) in values.iter().enumerate() {
+                    sealed[i] = match value {
+                        Some(v) if !FieldCipher::is_encrypted(v) => Some(Self::seal(cipher, ENCRYPTED_COLUMNS[i], v)?),
+                        other => other.clone(),
+                    };
+                }
+                stmt.execute(params![sealed[0], sealed[1], sealed[2], id])?;
+                changed += 1;
+            }
+
+            let events: Vec<(i64, String)> = tx
+                .prepare("SELECT id, key_code FROM key_events")?
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<_>>()?;
+            let mut stmt = tx.prepare("UPDATE key_events SET key_code = ?1 WHERE id = ?2")?;
+            for (id, code) in events {
+                if !FieldCipher::is_encrypted(&code) {
+                    stmt.execute(params![Self::seal(cipher, "key_code", &code)?, id])?;
+                }
+            }
+        }
+        tx.execute("DELETE FROM key_frequency WHERE kind = 'bigram'", [])?;
+        // The triggers have dropped the plaintext from the full-text index;
+        // merge the index so the old entries are gone for good
+        if changed > 0 {
+            tx.execute_batch("INSERT INTO inputs_fts(inputs_fts) VALUES ('optimize')")?;
+        }
+        tx.commit()?;
+
+        // Rewrite the file without the old pages, then empty the WAL, which
+        // still holds copies of them
+        conn.execute_batch("VACUUM")?;
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+        Ok(changed)
+    }
+
+    fn seal(cipher: &FieldCipher, column: &str, value: &str) -> Result<String> {
+        cipher
+            .encrypt(column, value)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))
+    }
+
+    /// Encrypt `value` for `column` if field encryption is on
+    fn protect(&self, column: &str, value: &str) -> Result<String> {
+        match self.cipher.get() {
+            Some(cipher) => Self::seal(cipher, column, value),
+            None => Ok(value.to_string()),
+        }
+    }
+
+    /// Decrypt column `idx` of `row`, leaving plaintext values as they are
+    fn reveal(&self, row: &rusqlite::Row, idx: usize, column: &str) -> Result<Option<String>> {
+        let value: Option<String> = row.get(idx)?;
+        match (value, self.cipher.get()) {
+            (Some(v), Some(cipher)) => cipher
+                .decrypt(column, &v)
+                .map(Some)
+                .map_err(|e| rusqlite::Error::FromSqlConversionFailure(idx, Type::Text, e.into())),
+            (value, _) => Ok(value),
+        }
+    }
+
+    fn apply_key(conn: &Connection, pragma: &str, key: &[u8; 32]) -> Result<()> {
+        // A raw key in x'...' form skips SQLCipher's own key derivation
+        let sql = Zeroizing::new(format!("PRAGMA {} = \"x'{}'\";", pragma, hex::encode(key)));
+        conn.execute_batch(&sql)
+    }
+
+    fn from_connection(mut conn: Connection) -> Result<Self> {
+        Self::configure(&conn)?;
+        migrations::migrate(&mut conn)?;
+        Ok(Self {
+            conn: Mutex::new(conn),
+            cipher: OnceCell::new(),
+            zone: Mutex::new(Zone::default()),
+        })
+    }
+
+    fn configure(conn: &Connection) -> Result<()> {
+        // Readers no longer wait on the writer, and commits need one sync
+        // instead of two. In-memory databases stay in "memory" mode.
+        conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+        conn.pragma_update(None, "synchronous", "NORMAL")
+    }
+
+    pub fn set_time_zone(&self, zone: Zone) {
+        *self.zone.lock().unwrap() = zone;
+    }
+
+    fn time_zone(&self) -> Zone {
+        *self.zone.lock().unwrap()
+    }
+
+    /// A date filter as epoch milliseconds in the configured zone
+    fn date_bound(&self, value: &str, end: bool) -> Result<i64> {
+        self.time_zone()
+            .parse_bound(value, end)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))
+    }
+
+    /// Save records, with their key events and shortcuts, in one
+    /// transaction. Returns the new record ids in order.
+    pub fn insert_batch(&self, batch: &[PendingRecord]) -> Result<Vec<i64>> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        let mut ids = Vec::with_capacity(batch.len());
+        for pending in batch {
+            let id = self.insert_record(&tx, &pending.record)?;
+            self.insert_key_events(&tx, id, &pending.key_events)?;
+            insert_shortcuts(&tx, id, &pending.shortcuts)?;
+            add_key_frequency(&tx, &pending.record, &pending.key_frequency, self.field_encrypted())?;
+            ids.push(id);
+        }
+        tx.commit()?;
+        Ok(ids)
+    }
+
+    fn insert_record(&self, conn: &Connection, record: &InputRecord) -> Result<i64> {
+        conn.prepare_cached(
+            "INSERT INTO inputs (timestamp, app_name, window_title, content, key_count, composed, final_text, duration_ms) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?
+        .execute(params![
+            record.timestamp.timestamp_millis(),
+            record.app_name,
+            record.window_title.as_deref().map(|t| self.protect("window_title", t)).transpose()?,
+            self.protect("content", &record.content)?,
+            record.key_count,
+            record.composed,
+            self.protect("final_text", &record.final_text)?,
+            record.duration_ms,
+        ])?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    fn insert_key_events(&self, conn: &Connection, input_id: i64, events: &[KeyStroke]) -> Result<()> {
+        let mut stmt = conn.prepare_cached(
+            "INSERT INTO key_events (input_id, time_ms, action, key_code, modifiers) VALUES (?1, ?2, ?3, ?4, ?5)",
         )?;
+        for event in events {
+            let action = match event.action {
+                KeyAction::Press => "press",
+                KeyAction::Release => "release",
+            };
+            let code = self.protect("key_code", &key_code(event.key))?;
+            stmt.execute(params![input_id, event.time_ms, action, code, event.modifiers])?;
+        }
         Ok(())
     }
 
-    pub fn insert_record(&self, record: &InputRecord) -> Result<i64> {
+    pub fn get_key_events(&self, input_id: i64) -> Result<Vec<KeyStroke>> {
         let conn = self.conn.lock().unwrap();
-        conn.execute(
-            "INSERT INTO inputs (timestamp, app_name, window_title, content, key_count) VALUES (?1, ?2, ?3, ?4, ?5)",
-            params![
-                record.timestamp.format("%Y-%m-%d %H:%M:%S").to_string(),
-                record.app_name,
-                record.window_title,
-                record.content,
-                record.key_count,
-            ],
+        let mut stmt = conn.prepare(
+            "SELECT time_ms, action, key_code, modifiers FROM key_events WHERE input_id = ? ORDER BY time_ms, id",
         )?;
-        Ok(conn.last_insert_rowid())
+        let events = stmt
+            .query_map(params![input_id], |row| {
+                let action: String = row.get(1)?;
+                let code = self.reveal(row, 2, "key_code")?.unwrap_or_default();
+                Ok(KeyStroke {
+                    input_id: Some(input_id),
+                    time_ms: row.get(0)?,
+                    action: if action == "release" { KeyAction::Release } else { KeyAction::Press },
+                    key: parse_key_code(&code),
+                    modifiers: row.get(3)?,
+                })
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(events)
     }
 
+    /// The most used shortcuts of each app, best first, at most `limit` per
+    /// app. Pass `app_name` to look at a single app.
+    pub fn shortcut_stats(&self, app_name: Option<&str>, limit: i64) -> Result<Vec<ShortcutStats>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT app_name, shortcut, uses FROM (
+                SELECT i.app_name, s.shortcut, COUNT(*) AS uses,
+                    ROW_NUMBER() OVER (PARTITION BY i.app_name ORDER BY COUNT(*) DESC, s.shortcut) AS rank
+                FROM shortcuts s JOIN inputs i ON i.id = s.input_id
+                WHERE ?1 IS NULL OR i.app_name = ?1
+                GROUP BY i.app_name, s.shortcut
+            )
+            WHERE rank <= ?2
+            ORDER BY app_name, uses DESC, shortcut",
+        )?;
+        let stats = stmt
+            .query_map(params![app_name, limit], |row| {
+                Ok(ShortcutStats {
+                    app_name: row.get(0)?,
+                    shortcut: row.get(1)?,
+                    count: row.get(2)?,
+                })
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(stats)
+    }
+
+    /// Records matching `filter`, newest first, or best match first for a
+    /// search the full-text index answers.
+    ///
+    /// With field encryption on, a search decrypts and checks every record
+    /// in the other filters' range instead: it is slower, and matches come
+    /// newest first, unranked.
     pub fn get_records(&self, filter: &SearchFilter) -> Result<Vec<InputRecord>> {
         let conn = self.conn.lock().unwrap();
+        let query = filter.query.as_deref().and_then(search::parse);
+
+        // Encrypted text can only be searched once decrypted, and the trigram
+        // index cannot answer every query. The query, and the paging that
+        // depends on it, are then applied below instead of by SQLite.
+        let fts = match query {
+            Some(ref query) if !self.field_encrypted() => query.to_fts(),
+            _ => None,
+        };
+        let search_decrypted = query.is_some() && fts.is_none();
+
         let mut sql = String::from(
-            "SELECT id, timestamp, app_name, window_title, content, key_count, created_at FROM inputs WHERE 1=1",
+            "SELECT inputs.id, timestamp, inputs.app_name, inputs.window_title, content, key_count, created_at, composed, inputs.final_text, duration_ms",
         );
         let mut params_vec: Vec<Box<dyn rusqlite::ToSql>> = Vec::new();
 
-        if let Some(ref query) = filter.query {
-            sql.push_str(" AND content LIKE ?");
-            params_vec.push(Box::new(format!("%{}%", query)));
+        if let Some(ref fts) = fts {
+            sql.push_str(&format!(
+                ", snippet(inputs_fts, -1, char({}), char({}), '…', 24), inputs_fts.rank \
+                 FROM inputs_fts JOIN inputs ON inputs.id = inputs_fts.rowid WHERE inputs_fts MATCH ?",
+                search::MATCH_START as u32,
+                search::MATCH_END as u32
+            ));
+            params_vec.push(Box::new(fts.clone()));
+        } else {
+            sql.push_str(", NULL, NULL FROM inputs WHERE 1=1");
         }
 
         if let Some(ref app_name) = filter.app_name {
-            sql.push_str(" AND app_name = ?");
+            sql.push_str(" AND inputs.app_name = ?");
             params_vec.push(Box::new(app_name.clone()));
         }
 
         if let Some(ref start_date) = filter.start_date {
             sql.push_str(" AND timestamp >= ?");
-            params_vec.push(Box::new(start_date.clone()));
+            params_vec.push(Box::new(self.date_bound(start_date, false)?));
         }
 
         if let Some(ref end_date) = filter.end_date {
-            sql.push_str(" AND timestamp <= ?");
-            params_vec.push(Box::new(end_date.clone()));
+            sql.push_str(" AND timestamp < ?");
+            params_vec.push(Box::new(self.date_bound(end_date, true)?));
         }
 
-        sql.push_str(" ORDER BY timestamp DESC");
-
-        if let Some(limit) = filter.limit {
-            sql.push_str(&format!(" LIMIT {}", limit));
+        if fts.is_some() {
+            sql.push_str(" ORDER BY inputs_fts.rank, timestamp DESC, inputs.id DESC");
+        } else {
+            sql.push_str(" ORDER BY timestamp DESC, inputs.id DESC");
         }
 
-        if let Some(offset) = filter.offset {
-            sql.push_str(&format!(" OFFSET {}", offset));
+        if !search_decrypted {
+            if let Some(limit) = filter.limit {
+                sql.push_str(&format!(" LIMIT {}", limit));
+            }
+
+            if let Some(offset) = filter.offset {
+                sql.push_str(&format!(" OFFSET {}", offset));
+            }
         }
 
         let params_refs: Vec<&dyn rusqlite::ToSql> = params_vec.iter().map(|p| p.as_ref()).collect();
         let mut stmt = conn.prepare(&sql)?;
 
         let records = stmt.query_map(params_refs.as_slice(), |row| {
-            let timestamp_str: String = row.get(1)?;
-            let timestamp = NaiveDateTime::parse_from_str(&timestamp_str, "%Y-%m-%d %H:%M:%S")
-                .map(|dt| DateTime::<Utc>::from_naive_utc_and_offset(dt, Utc))
-                .unwrap_or_else(|_| Utc::now());
-
-            let created_at_str: Option<String> = row.get(6)?;
-            let created_at = created_at_str.and_then(|s| {
-                NaiveDateTime::parse_from_str(&s, "%Y-%m-%d %H:%M:%S")
-                    .map(|dt| DateTime::<Utc>::from_naive_utc_and_offset(dt, Utc))
-                    .ok()
-            });
+            let timestamp = millis_to_datetime(row, 1)?;
+            let created_at = Some(millis_to_datetime(row, 6)?);
+
+            let content = self.reveal(row, 4, "content")?.unwrap_or_default();
+            let final_text = self.reveal(row, 8, "final_text")?;
 
             Ok(InputRecord {
                 id: Some(row.get(0)?),
                 timestamp,
                 app_name: row.get(2)?,
-                window_title: row.get(3)?,
-                content: row.get(4)?,
+                window_title: self.reveal(row, 3, "window_title")?,
+                final_text: final_text.unwrap_or_else(|| content.clone()),
+                content,
                 key_count: row.get(5)?,
+                composed: row.get(7)?,
+                duration_ms: row.get(9)?,
                 created_at,
+                snippet: row.get(10)?,
+                rank: row.get(11)?,
             })
         })?;
 
-        records.collect()
+        let query = match query {
+            Some(query) if search_decrypted => query,
+            _ => return records.collect(),
+        };
+
+        let mut matched = Vec::new();
+        for record in records {
+            let mut record = record?;
+            if query.matches(&record) {
+                record.snippet = query.snippet(&record.final_text);
+                matched.push(record);
+            }
+        }
+        Ok(matched
+            .into_iter()
+            .skip(filter.offset.unwrap_or(0).max(0) as usize)
+            .take(filter.limit.map_or(usize::MAX, |l| l.max(0) as usize))
+            .collect())
     }
 
     pub fn get_today_stats(&self) -> Result<DailyStats> {
-        let conn = self.conn.lock().unwrap();
-        let today = Utc::now().format("%Y-%m-%d").to_string();
+        let zone = self.time_zone();
+        let today = zone.today();
+        let start = zone.start_of_day(today);
+        let end = zone.start_of_day(today + chrono::Duration::days(1));
 
+        let conn = self.conn.lock().unwrap();
         let (total_keys, total_records): (i64, i64) = conn.query_row(
-            "SELECT COALESCE(SUM(key_count), 0), COUNT(*) FROM inputs WHERE date(timestamp) = ?",
-            params![today],
+            "SELECT COALESCE(SUM(key_count), 0), COUNT(*) FROM inputs WHERE timestamp >= ?1 AND timestamp < ?2",
+            params![start, end],
             |row| Ok((row.get(0)?, row.get(1)?)),
         )?;
 
         let mut stmt = conn.prepare(
-            "SELECT app_name, SUM(key_count), COUNT(*) FROM inputs WHERE date(timestamp) = ? GROUP BY app_name ORDER BY SUM(key_count) DESC",
+            "SELECT app_name, SUM(key_count), COUNT(*) FROM inputs WHERE timestamp >= ?1 AND timestamp < ?2 GROUP BY app_name ORDER BY SUM(key_count) DESC",
         )?;
 
         let app_stats = stmt
-            .query_map(params![today], |row| {
+            .query_map(params![start, end], |row| {
                 Ok(AppStats {
                     app_name: row.get(0)?,
                     key_count: row.get(1)?,
@@ -152,13 +518,347 @@ impl Database {
             .collect::<Result<Vec<_>>>()?;
 
         Ok(DailyStats {
-            date: today,
+            date: today.format("%Y-%m-%d").to_string(),
             total_keys,
             total_records,
             app_stats,
         })
     }
 
+    /// Key and record counts in `range`, per `bucket` and split by `group_by`.
+    /// Every series has an entry for every bucket, zero where nothing was typed.
+    pub fn get_stats(&self, range: &StatsRange, bucket: Bucket, group_by: GroupBy) -> Result<Stats> {
+        let zone = self.time_zone();
+        let start = self.date_bound(&range.start, false)?;
+        let end = self.date_bound(&range.end, true)?;
+        let starts = stats::bucket_starts(zone, start, end, bucket)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))?;
+
+        let column = match group_by {
+            GroupBy::None => "NULL",
+            GroupBy::App => "app_name",
+            GroupBy::WindowTitle => "window_title",
+        };
+        let empty = |group: Option<String>| StatsSeries {
+            group,
+            key_count: 0,
+            record_count: 0,
+            key_counts: vec![0; starts.len()],
+            record_counts: vec![0; starts.len()],
+        };
+
+        let mut series: HashMap<Option<String>, StatsSeries> = HashMap::new();
+        if group_by == GroupBy::None {
+            series.insert(None, empty(None));
+        }
+
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT timestamp, key_count, {} FROM inputs WHERE timestamp >= ?1 AND timestamp < ?2",
+            column
+        ))?;
+        let mut rows = stmt.query(params![start, end])?;
+        while let Some(row) = rows.next()? {
+            let timestamp: i64 = row.get(0)?;
+            let key_count: i64 = row.get::<_, Option<i64>>(1)?.unwrap_or(0);
+            // Titles may be encrypted, so they are grouped once decrypted
+            let group = match group_by {
+                GroupBy::WindowTitle => self.reveal(row, 2, "window_title")?,
+                _ => row.get(2)?,
+            };
+
+            let i = stats::bucket_index(&starts, timestamp);
+            let entry = series.entry(group.clone()).or_insert_with(|| empty(group));
+            entry.key_count += key_count;
+            entry.record_count += 1;
+            entry.key_counts[i] += key_count;
+            entry.record_counts[i] += 1;
+        }
+
+        let mut series: Vec<StatsSeries> = series.into_values().collect();
+        series.sort_by(|a, b| b.key_count.cmp(&a.key_count).then_with(|| a.group.cmp(&b.group)));
+
+        Ok(Stats {
+            buckets: starts.iter().map(|&start| zone.format(start)).collect(),
+            series,
+        })
+    }
+
+    /// Corrections in `range`, overall, per app, per hour of the day and
+    /// per `bucket`, with the `word_limit` words most often corrected.
+    /// Records are replayed from their stored keystrokes, so this covers
+    /// everything recorded so far.
+    pub fn get_correction_stats(&self, range: &StatsRange, bucket: Bucket, word_limit: usize) -> Result<CorrectionStats> {
+        let zone = self.time_zone();
+        let start = self.date_bound(&range.start, false)?;
+        let end = self.date_bound(&range.end, true)?;
+        let starts = stats::bucket_starts(zone, start, end, bucket)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))?;
+
+        // Corrections and printable keys for each breakdown
+        let mut apps: HashMap<String, (i64, i64)> = HashMap::new();
+        let mut hours = [(0i64, 0i64); 24];
+        let mut over_time = vec![(0i64, 0i64); starts.len()];
+        let mut words: HashMap<String, i64> = HashMap::new();
+
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT timestamp, app_name, content FROM inputs WHERE timestamp >= ?1 AND timestamp < ?2")?;
+        let mut rows = stmt.query(params![start, end])?;
+        while let Some(row) = rows.next()? {
+            let timestamp: i64 = row.get(0)?;
+            let analysis = corrections::analyze(&self.reveal(row, 2, "content")?.unwrap_or_default());
+
+            let counts = [
+                apps.entry(row.get(1)?).or_default(),
+                &mut hours[zone.local_time(timestamp).hour() as usize],
+                &mut over_time[stats::bucket_index(&starts, timestamp)],
+            ];
+            for count in counts {
+                count.0 += analysis.corrections;
+                count.1 += analysis.printable;
+            }
+            for word in analysis.corrected_words {
+                *words.entry(word).or_default() += 1;
+            }
+        }
+
+        let count = |label: String, (corrections, printable): (i64, i64)| CorrectionCount {
+            label,
+            corrections,
+            printable,
+            ratio: corrections::ratio(corrections, printable),
+        };
+
+        let mut apps: Vec<CorrectionCount> = apps.into_iter().map(|(app, c)| count(app, c)).collect();
+        apps.sort_by(|a, b| b.printable.cmp(&a.printable).then_with(|| a.label.cmp(&b.label)));
+
+        let mut words: Vec<CorrectedWord> = words.into_iter().map(|(word, count)| CorrectedWord { word, count }).collect();
+        words.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.word.cmp(&b.word)));
+        words.truncate(word_limit);
+
+        let corrections = apps.iter().map(|a| a.corrections).sum();
+        let printable = apps.iter().map(|a| a.printable).sum();
+        Ok(CorrectionStats {
+            corrections,
+            printable,
+            ratio: corrections::ratio(corrections, printable),
+            apps,
+            hours: hours
+                .iter()
+                .enumerate()
+                .map(|(hour, &c)| count(format!("{:02}", hour), c))
+                .collect(),
+            over_time: starts
+                .iter()
+                .zip(over_time)
+                .map(|(&start, c)| count(zone.format(start), c))
+                .collect(),
+            words,
+        })
+    }
+
+    pub fn start_focus_span(&self, app_name: &str, start_ms: i64, end_ms: i64) -> Result<i64> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO focus_spans (app_name, start_ms, end_ms) VALUES (?1, ?2, ?3)",
+            params![app_name, start_ms, end_ms],
+        )?;
+        Ok(conn.last_insert_rowid())
+    }
+
+    pub fn extend_focus_span(&self, id: i64, end_ms: i64) -> Result<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute("UPDATE focus_spans SET end_ms = ?1 WHERE id = ?2", params![end_ms, id])?;
+        Ok(())
+    }
+
+    /// Foreground time per app and day in `range`. Spans crossing midnight
+    /// or the edges of the range are split at them.
+    pub fn get_active_time(&self, range: &StatsRange) -> Result<ActiveTime> {
+        let zone = self.time_zone();
+        let start = self.date_bound(&range.start, false)?;
+        let end = self.date_bound(&range.end, true)?;
+        let days = stats::bucket_starts(zone, start, end, Bucket::Day)
+            .map_err(|e| rusqlite::Error::ToSqlConversionFailure(e.into()))?;
+
+        let mut apps: HashMap<String, Vec<i64>> = HashMap::new();
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare("SELECT app_name, start_ms, end_ms FROM focus_spans WHERE end_ms > ?1 AND start_ms < ?2")?;
+        let mut rows = stmt.query(params![start, end])?;
+        while let Some(row) = rows.next()? {
+            let app_name: String = row.get(0)?;
+            let span_start = row.get::<_, i64>(1)?.max(start);
+            let span_end = row.get::<_, i64>(2)?.min(end);
+
+            let daily = apps.entry(app_name).or_insert_with(|| vec![0; days.len()]);
+            for (i, &day_start) in days.iter().enumerate().skip(stats::bucket_index(&days, span_start)) {
+                if day_start >= span_end {
+                    break;
+                }
+                let day_end = days.get(i + 1).copied().unwrap_or(end);
+                daily[i] += span_end.min(day_end) - span_start.max(day_start);
+            }
+        }
+
+        let mut apps: Vec<AppTime> = apps
+            .into_iter()
+            .map(|(app_name, daily_ms)| AppTime {
+                app_name,
+                total_ms: daily_ms.iter().sum(),
+                daily_ms,
+            })
+            .collect();
+        apps.sort_by(|a, b| b.total_ms.cmp(&a.total_ms).then_with(|| a.app_name.cmp(&b.app_name)));
+
+        Ok(ActiveTime {
+            days: days.iter().map(|&day| zone.format(day)).collect(),
+            total_ms: apps.iter().map(|a| a.total_ms).sum(),
+            apps,
+        })
+    }
+
+    /// Typing in `range` grouped into sessions, split wherever nothing was
+    /// typed for longer than `idle_gap`. Oldest first.
+    pub fn get_sessions(&self, range: &StatsRange, idle_gap: std::time::Duration) -> Result<Vec<ActivitySession>> {
+        let start = self.date_bound(&range.start, false)?;
+        let end = self.date_bound(&range.end, true)?;
+
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT timestamp, duration_ms, app_name, key_count FROM inputs WHERE timestamp >= ?1 AND timestamp < ?2",
+        )?;
+        let activity = stmt
+            .query_map(params![start, end], |row| {
+                let end_ms: i64 = row.get(0)?;
+                Ok(Activity {
+                    start_ms: end_ms - row.get::<_, Option<i64>>(1)?.unwrap_or(0),
+                    end_ms,
+                    app_name: row.get(2)?,
+                    key_count: row.get::<_, Option<i64>>(3)?.unwrap_or(0),
+                })
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(activity::group_sessions(activity, idle_gap))
+    }
+
+    /// Key, modifier and bigram presses in `range`, in every app or just
+    /// `app`. Presses are counted by the hour, so a range is matched to the
+    /// hours starting inside it.
+    pub fn get_key_heatmap(&self, range: &StatsRange, app: Option<&str>) -> Result<KeyHeatmap> {
+        let start = self.date_bound(&range.start, false)?;
+        let end = self.date_bound(&range.end, true)?;
+
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT kind, key, SUM(count) FROM key_frequency
+             WHERE hour >= ?1 AND hour < ?2 AND (?3 IS NULL OR app_name = ?3)
+             GROUP BY kind, key ORDER BY SUM(count) DESC, key",
+        )?;
+        let mut rows = stmt.query(params![start, end, app])?;
+
+        let mut heatmap = KeyHeatmap {
+            total: 0,
+            keys: Vec::new(),
+            modifiers: Vec::new(),
+            bigrams: Vec::new(),
+        };
+        while let Some(row) = rows.next()? {
+            let kind: String = row.get(0)?;
+            let key: String = row.get(1)?;
+            let count: i64 = row.get(2)?;
+            match kind.as_str() {
+                "key" => {
+                    heatmap.total += count;
+                    heatmap.keys.push(KeyCount { key, count });
+                }
+                "modifier" => heatmap.modifiers.push(KeyCount { key, count }),
+                "bigram" if heatmap.bigrams.len() < MAX_BIGRAMS => {
+                    if let Some((first, second)) = key.split_once(' ') {
+                        heatmap.bigrams.push(BigramCount {
+                            first: first.to_string(),
+                            second: second.to_string(),
+                            count,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(heatmap)
+    }
+
+    /// Typing speed in `range`, overall and per app, from records long
+    /// enough to be timed.
+    pub fn get_typing_speed(&self, range: &StatsRange) -> Result<TypingSpeed> {
+        let start = self.date_bound(&range.start, false)?;
+        let end = self.date_bound(&range.end, true)?;
+
+        // Per app: characters, milliseconds, and each record's speed
+        let mut apps: HashMap<String, (i64, i64, Vec<f64>)> = HashMap::new();
+        let mut longest_burst: Option<Burst> = None;
+
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(
+            "SELECT id, timestamp, app_name, final_text, duration_ms FROM inputs WHERE timestamp >= ?1 AND timestamp < ?2 AND duration_ms >= ?3",
+        )?;
+        let mut rows = stmt.query(params![start, end, stats::MIN_TIMED_MS])?;
+        while let Some(row) = rows.next()? {
+            let app_name: String = row.get(2)?;
+            let chars = self.reveal(row, 3, "final_text")?.unwrap_or_default().chars().count() as i64;
+            let duration_ms: i64 = row.get(4)?;
+
+            let app = apps.entry(app_name.clone()).or_default();
+            app.0 += chars;
+            app.1 += duration_ms;
+            app.2.push(stats::cpm(chars, duration_ms));
+
+            if longest_burst.as_ref().is_none_or(|b| duration_ms > b.duration_ms) {
+                longest_burst = Some(Burst {
+                    input_id: row.get(0)?,
+                    app_name,
+                    timestamp: millis_to_datetime(row, 1)?,
+                    duration_ms,
+                    chars,
+                    cpm: stats::cpm(chars, duration_ms),
+                });
+            }
+        }
+
+        let mut app_speeds: Vec<AppSpeed> = apps
+            .into_iter()
+            .map(|(app_name, (chars, typing_ms, mut speeds))| {
+                speeds.sort_by(f64::total_cmp);
+                AppSpeed {
+                    app_name,
+                    wpm: stats::wpm(chars, typing_ms),
+                    cpm: stats::cpm(chars, typing_ms),
+                    chars,
+                    typing_ms,
+                    records: speeds.len() as i64,
+                    cpm_p25: stats::percentile(&speeds, 25.0),
+                    cpm_median: stats::percentile(&speeds, 50.0),
+                    cpm_p75: stats::percentile(&speeds, 75.0),
+                    cpm_p90: stats::percentile(&speeds, 90.0),
+                    cpm_max: speeds.last().copied().unwrap_or(0.0),
+                }
+            })
+            .collect();
+        app_speeds.sort_by(|a, b| b.typing_ms.cmp(&a.typing_ms).then_with(|| a.app_name.cmp(&b.app_name)));
+
+        let chars = app_speeds.iter().map(|a| a.chars).sum();
+        let typing_ms = app_speeds.iter().map(|a| a.typing_ms).sum();
+        Ok(TypingSpeed {
+            wpm: stats::wpm(chars, typing_ms),
+            cpm: stats::cpm(chars, typing_ms),
+            chars,
+            typing_ms,
+            records: app_speeds.iter().map(|a| a.records).sum(),
+            longest_burst,
+            apps: app_speeds,
+        })
+    }
+
     pub fn get_app_list(&self) -> Result<Vec<String>> {
         let conn = self.conn.lock().unwrap();
         let mut stmt = conn.prepare("SELECT DISTINCT app_name FROM inputs ORDER BY app_name")?;
@@ -169,8 +869,20 @@ impl Database {
     }
 
     pub fn delete_records_before(&self, date: &str) -> Result<usize> {
-        let conn = self.conn.lock().unwrap();
-        let count = conn.execute("DELETE FROM inputs WHERE timestamp < ?", params![date])?;
+        let before = self.date_bound(date, false)?;
+        let mut conn = self.conn.lock().unwrap();
+        // All or nothing, so the totals kept beside the records stay in step
+        let tx = conn.transaction()?;
+        tx.execute("DELETE FROM key_frequency WHERE hour < ?", params![before])?;
+        tx.execute("DELETE FROM focus_spans WHERE end_ms < ?", params![before])?;
+        for table in ["key_events", "shortcuts"] {
+            tx.execute(
+                &format!("DELETE FROM {} WHERE input_id IN (SELECT id FROM inputs WHERE timestamp < ?)", table),
+                params![before],
+            )?;
+        }
+        let count = tx.execute("DELETE FROM inputs WHERE timestamp < ?", params![before])?;
+        tx.commit()?;
         Ok(count)
     }
 
@@ -197,3 +909,464 @@ impl Database {
         Ok(())
     }
 }
+
+/// Add a record's key counts to its hour. Bigrams are skipped when
+/// `skip_bigrams` is set, as they would keep a readable trace of the text.
+fn add_key_frequency(
+    conn: &Connection,
+    record: &InputRecord,
+    tallies: &HashMap<KeyTally, i64>,
+    skip_bigrams: bool,
+) -> Result<()> {
+    let millis = record.timestamp.timestamp_millis();
+    let hour = millis - millis.rem_euclid(HOUR_MILLIS);
+    let mut stmt = conn.prepare_cached(
+        "INSERT INTO key_frequency (hour, app_name, kind, key, count) VALUES (?1, ?2, ?3, ?4, ?5)
+         ON CONFLICT (hour, app_name, kind, key) DO UPDATE SET count = count + excluded.count",
+    )?;
+    for (tally, count) in tallies {
+        let (kind, key) = match *tally {
+            KeyTally::Key(key) => ("key", key_code(key)),
+            KeyTally::Bigram(..) if skip_bigrams => continue,
+            KeyTally::Bigram(first, second) => ("bigram", format!("{} {}", key_code(first), key_code(second))),
+            KeyTally::Modifier(key) => ("modifier", key_code(key)),
+        };
+        stmt.execute(params![hour, record.app_name, kind, key, count])?;
+    }
+    Ok(())
+}
+
+fn insert_shortcuts(conn: &Connection, input_id: i64, shortcuts: &[String]) -> Result<()> {
+    let mut stmt = conn.prepare_cached("INSERT INTO shortcuts (input_id, shortcut) VALUES (?1, ?2)")?;
+    for shortcut in shortcuts {
+        stmt.execute(params![input_id, shortcut])?;
+    }
+    Ok(())
+}
+
+/// Read an epoch-milliseconds column as a UTC time
+fn millis_to_datetime(row: &rusqlite::Row, idx: usize) -> Result<DateTime<Utc>> {
+    let millis: i64 = row.get(idx)?;
+    DateTime::<Utc>::from_timestamp_millis(millis).ok_or_else(|| {
+        rusqlite::Error::FromSqlConversionFailure(
+            idx,
+            Type::Integer,
+            format!("timestamp {} out of range", millis).into(),
+        )
+    })
+}
+
+/// Keys are stored under their rdev name (`KeyA`), or as JSON for keys that
+/// carry a code (`{"Unknown":191}`), so they read back exactly.
+fn key_code(key: Key) -> String {
+    match serde_json::to_value(key) {
+        Ok(Value::String(name)) => name,
+        Ok(value) => value.to_string(),
+        Err(_) => format!("{:?}", key),
+    }
+}
+
+fn parse_key_code(code: &str) -> Key {
+    serde_json::from_value(Value::String(code.to_string()))
+        .or_else(|_| serde_json::from_str(code))
+        .unwrap_or(Key::Unknown(0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zeroize::Zeroizing;
+
+    fn pending(record: InputRecord, key_events: Vec<KeyStroke>, key_frequency: HashMap<KeyTally, i64>) -> PendingRecord {
+        PendingRecord {
+            record,
+            key_events,
+            shortcuts: Vec::new(),
+            key_frequency,
+        }
+    }
+
+    fn press(key: Key) -> KeyStroke {
+        KeyStroke {
+            input_id: None,
+            time_ms: 0,
+            action: KeyAction::Press,
+            key,
+            modifiers: 0,
+        }
+    }
+
+    fn tallies() -> HashMap<KeyTally, i64> {
+        HashMap::from([
+            (KeyTally::Key(Key::KeyH), 1),
+            (KeyTally::Key(Key::KeyI), 1),
+            (KeyTally::Bigram(Key::KeyH, Key::KeyI), 1),
+        ])
+    }
+
+    fn raw_key_codes(db: &Database) -> Vec<String> {
+        let conn = db.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT key_code FROM key_events ORDER BY id").unwrap();
+        let codes = stmt.query_map([], |row| row.get(0)).unwrap();
+        codes.collect::<Result<_>>().unwrap()
+    }
+
+    fn frequency_kinds(db: &Database) -> Vec<String> {
+        let conn = db.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT DISTINCT kind FROM key_frequency ORDER BY kind").unwrap();
+        let kinds = stmt.query_map([], |row| row.get(0)).unwrap();
+        kinds.collect::<Result<_>>().unwrap()
+    }
+
+    fn seed(db: &Database, rows: &[(&str, &str, &str, i32)]) {
+        let batch: Vec<PendingRecord> = rows
+            .iter()
+            .map(|&(timestamp, app, title, key_count)| {
+                let mut record = InputRecord::new(app.into(), Some(title.into()), "x".into());
+                record.timestamp = DateTime::parse_from_rfc3339(timestamp).unwrap().with_timezone(&Utc);
+                record.key_count = key_count;
+                pending(record, Vec::new(), HashMap::new())
+            })
+            .collect();
+        db.insert_batch(&batch).unwrap();
+    }
+
+    /// Records of `chars` typed characters over `duration_ms`
+    fn seed_timed(db: &Database, rows: &[(&str, &str, usize, Option<i64>)]) {
+        let batch: Vec<PendingRecord> = rows
+            .iter()
+            .map(|&(timestamp, app, chars, duration_ms)| {
+                let mut record = InputRecord::new(app.into(), None, "a".repeat(chars));
+                record.timestamp = DateTime::parse_from_rfc3339(timestamp).unwrap().with_timezone(&Utc);
+                record.duration_ms = duration_ms;
+                pending(record, Vec::new(), HashMap::new())
+            })
+            .collect();
+        db.insert_batch(&batch).unwrap();
+    }
+
+    fn range(start: &str, end: &str) -> StatsRange {
+        StatsRange {
+            start: start.into(),
+            end: end.into(),
+        }
+    }
+
+    fn stats_db() -> Database {
+        let db = Database::in_memory().unwrap();
+        db.set_time_zone(Zone::parse("Asia/Shanghai").unwrap());
+        seed(
+            &db,
+            &[
+                ("2026-04-30T23:30:00+08:00", "Editor", "a.rs", 10),
+                // Still April 30 in UTC
+                ("2026-05-01T00:10:00+08:00", "Editor", "a.rs", 5),
+                ("2026-05-01T09:15:00+08:00", "Browser", "docs", 7),
+                ("2026-05-01T09:45:00+08:00", "Editor", "b.rs", 3),
+                ("2026-05-04T12:00:00+08:00", "Editor", "a.rs", 20),
+                ("2026-05-31T23:59:59+08:00", "Browser", "docs", 1),
+                ("2026-06-01T00:00:00+08:00", "Browser", "docs", 100),
+            ],
+        );
+        db
+    }
+
+    #[test]
+    fn stats_by_day() {
+        let stats = stats_db()
+            .get_stats(&range("2026-05-01", "2026-05-31"), Bucket::Day, GroupBy::None)
+            .unwrap();
+        assert_eq!(stats.buckets.len(), 31);
+        assert_eq!(stats.buckets[0], "2026-05-01T00:00:00+08:00");
+        assert_eq!(stats.series.len(), 1);
+        let series = &stats.series[0];
+        assert_eq!(series.group, None);
+        assert_eq!((series.key_count, series.record_count), (36, 5));
+        assert_eq!((series.key_counts[0], series.record_counts[0]), (15, 3));
+        assert_eq!(series.key_counts[3], 20);
+        assert_eq!(series.key_counts[30], 1);
+    }
+
+    #[test]
+    fn stats_by_app() {
+        let stats = stats_db()
+            .get_stats(&range("2026-05-01", "2026-05-01"), Bucket::Hour, GroupBy::App)
+            .unwrap();
+        assert_eq!(stats.buckets.len(), 24);
+        let groups: Vec<_> = stats.series.iter().map(|s| (s.group.as_deref(), s.key_count)).collect();
+        assert_eq!(groups, [(Some("Editor"), 8), (Some("Browser"), 7)]);
+        assert_eq!(stats.series[0].key_counts[0], 5);
+        assert_eq!(stats.series[0].key_counts[9], 3);
+        assert_eq!(stats.series[1].key_counts[9], 7);
+    }
+
+    #[test]
+    fn stats_by_window_title() {
+        let db = stats_db();
+        db.set_field_cipher(FieldCipher::new(&Zeroizing::new([7; 32])));
+        db.encrypt_fields().unwrap();
+
+        // Titles are grouped after decryption
+        let stats = db
+            .get_stats(&range("2026-05-01", "2026-05-31"), Bucket::Week, GroupBy::WindowTitle)
+            .unwrap();
+        assert_eq!(stats.buckets[0], "2026-04-27T00:00:00+08:00");
+        assert_eq!(stats.buckets.len(), 5);
+        let titles: Vec<_> = stats.series.iter().map(|s| s.group.as_deref()).collect();
+        assert_eq!(titles, [Some("a.rs"), Some("docs"), Some("b.rs")]);
+        assert_eq!(stats.series[0].key_counts, [5, 20, 0, 0, 0]);
+    }
+
+    #[test]
+    fn stats_by_month_and_empty_ranges() {
+        let db = stats_db();
+        let stats = db
+            .get_stats(&range("2026-01-15", "2026-12-31"), Bucket::Month, GroupBy::None)
+            .unwrap();
+        assert_eq!(stats.buckets.len(), 12);
+        assert_eq!(stats.series[0].key_counts[3..6], [10, 36, 100]);
+
+        let empty = db
+            .get_stats(&range("2020-01-01", "2020-01-02"), Bucket::Day, GroupBy::None)
+            .unwrap();
+        assert_eq!(empty.series[0].key_counts, [0, 0]);
+        let empty = db
+            .get_stats(&range("2020-01-01", "2020-01-02"), Bucket::Day, GroupBy::App)
+            .unwrap();
+        assert!(empty.series.is_empty());
+        assert!(db
+            .get_stats(&range("2000-01-01", "2026-01-01"), Bucket::Hour, GroupBy::None)
+            .is_err());
+    }
+
+    #[test]
+    fn typing_speed() {
+        let db = Database::in_memory().unwrap();
+        db.set_time_zone(Zone::parse("Asia/Shanghai").unwrap());
+        seed_timed(
+            &db,
+            &[
+                ("2026-05-01T09:00:00+08:00", "Editor", 100, Some(60_000)),
+                ("2026-05-01T10:00:00+08:00", "Editor", 50, Some(10_000)),
+                ("2026-05-01T11:00:00+08:00", "Editor", 30, Some(20_000)),
+                // Too short to time, or not timed at all
+                ("2026-05-01T12:00:00+08:00", "Editor", 5, Some(stats::MIN_TIMED_MS - 1)),
+                ("2026-05-01T13:00:00+08:00", "Editor", 10, None),
+                ("2026-05-01T14:00:00+08:00", "Browser", 20, Some(stats::MIN_TIMED_MS)),
+                ("2026-05-02T00:00:00+08:00", "Browser", 100, Some(4_000)),
+            ],
+        );
+
+        let speed = db.get_typing_speed(&range("2026-05-01", "2026-05-01")).unwrap();
+        assert_eq!((speed.chars, speed.typing_ms, speed.records), (200, 92_000, 4));
+        // Records are weighed by their typing time, not averaged
+        assert_eq!(speed.cpm, stats::cpm(200, 92_000));
+        assert_eq!(speed.wpm, speed.cpm / 5.0);
+
+        let apps: Vec<_> = speed
+            .apps
+            .iter()
+            .map(|a| (a.app_name.as_str(), a.chars, a.typing_ms, a.records, a.wpm))
+            .collect();
+        assert_eq!(apps, [("Editor", 180, 90_000, 3, 24.0), ("Browser", 20, 2_000, 1, 120.0)]);
+        // The editor's records went at 100, 300 and 90 characters a minute
+        let editor = &speed.apps[0];
+        assert_eq!(
+            [editor.cpm_p25, editor.cpm_median, editor.cpm_p75, editor.cpm_p90, editor.cpm_max],
+            [90.0, 100.0, 300.0, 300.0, 300.0]
+        );
+
+        let burst = speed.longest_burst.unwrap();
+        assert_eq!(
+            (burst.app_name.as_str(), burst.duration_ms, burst.chars, burst.cpm),
+            ("Editor", 60_000, 100, 100.0)
+        );
+
+        let empty = db.get_typing_speed(&range("2020-01-01", "2020-01-01")).unwrap();
+        assert_eq!((empty.records, empty.wpm), (0, 0.0));
+        assert!(empty.apps.is_empty() && empty.longest_burst.is_none());
+    }
+
+    #[test]
+    fn key_heatmap() {
+        let db = Database::in_memory().unwrap();
+        db.set_time_zone(Zone::parse("Asia/Shanghai").unwrap());
+        let at = |timestamp: &str, app: &str, tallies: &[(KeyTally, i64)]| {
+            let mut record = InputRecord::new(app.into(), None, "x".into());
+            record.timestamp = DateTime::parse_from_rfc3339(timestamp).unwrap().with_timezone(&Utc);
+            pending(record, Vec::new(), tallies.iter().copied().collect())
+        };
+        db.insert_batch(&[
+            at(
+                "2026-05-01T09:10:00+08:00",
+                "Editor",
+                &[
+                    (KeyTally::Key(Key::KeyH), 2),
+                    (KeyTally::Key(Key::KeyI), 1),
+                    (KeyTally::Bigram(Key::KeyH, Key::KeyI), 1),
+                    (KeyTally::Modifier(Key::ShiftLeft), 1),
+                ],
+            ),
+            // Same app and hour: added to the counts above
+            at("2026-05-01T09:50:00+08:00", "Editor", &[(KeyTally::Key(Key::KeyH), 1)]),
+            at(
+                "2026-05-01T10:05:00+08:00",
+                "Browser",
+                &[(KeyTally::Key(Key::KeyH), 4), (KeyTally::Key(Key::KeyA), 1)],
+            ),
+            at("2026-04-30T23:59:00+08:00", "Editor", &[(KeyTally::Key(Key::KeyZ), 9)]),
+            at("2026-05-02T00:30:00+08:00", "Editor", &[(KeyTally::Key(Key::KeyZ), 9)]),
+        ])
+        .unwrap();
+
+        let rows: i64 = db
+            .conn
+            .lock()
+            .unwrap()
+            .query_row("SELECT count(*) FROM key_frequency WHERE key = 'KeyH'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 2);
+
+        let counts = |counts: &[KeyCount]| -> Vec<(String, i64)> {
+            counts.iter().map(|k| (k.key.clone(), k.count)).collect()
+        };
+        let day = range("2026-05-01", "2026-05-01");
+
+        let all = db.get_key_heatmap(&day, None).unwrap();
+        assert_eq!(all.total, 9);
+        assert_eq!(counts(&all.keys), [("KeyH".into(), 7), ("KeyA".into(), 1), ("KeyI".into(), 1)]);
+        assert_eq!(counts(&all.modifiers), [("ShiftLeft".into(), 1)]);
+        let bigrams: Vec<_> = all.bigrams.iter().map(|b| (b.first.as_str(), b.second.as_str(), b.count)).collect();
+        assert_eq!(bigrams, [("KeyH", "KeyI", 1)]);
+
+        let editor = db.get_key_heatmap(&day, Some("Editor")).unwrap();
+        assert_eq!(editor.total, 4);
+        assert_eq!(counts(&editor.keys), [("KeyH".into(), 3), ("KeyI".into(), 1)]);
+        assert!(db.get_key_heatmap(&day, Some("Terminal")).unwrap().keys.is_empty());
+
+        // Hours are counted whole, by the hour they start in
+        let later = db
+            .get_key_heatmap(&range("2026-05-01T10:00:00+08:00", "2026-05-01T23:59:59+08:00"), None)
+            .unwrap();
+        assert_eq!(counts(&later.keys), [("KeyH".into(), 4), ("KeyA".into(), 1)]);
+        assert!(later.modifiers.is_empty() && later.bigrams.is_empty());
+        let two_days = db.get_key_heatmap(&range("2026-04-30", "2026-05-02"), None).unwrap();
+        assert_eq!(two_days.total, 27);
+    }
+
+    #[test]
+    fn delete_records_before_is_all_or_nothing() {
+        let db = Database::in_memory().unwrap();
+        let mut record = InputRecord::new("Editor".into(), None, "hi".into());
+        record.timestamp = DateTime::parse_from_rfc3339("2026-05-01T09:00:00Z").unwrap().with_timezone(&Utc);
+        db.insert_batch(&[pending(record, vec![press(Key::KeyH), press(Key::KeyI)], tallies())])
+            .unwrap();
+        let counts = |db: &Database| -> (i64, i64, i64) {
+            let conn = db.conn.lock().unwrap();
+            let count = |table: &str| {
+                conn.query_row(&format!("SELECT count(*) FROM {}", table), [], |row| row.get(0))
+                    .unwrap()
+            };
+            (count("inputs"), count("key_events"), count("key_frequency"))
+        };
+        assert_eq!(counts(&db), (1, 2, 3));
+
+        // The last delete fails, and takes the earlier ones back with it
+        db.conn
+            .lock()
+            .unwrap()
+            .execute_batch(
+                "CREATE TRIGGER refuse BEFORE DELETE ON inputs BEGIN SELECT RAISE(ABORT, 'refused'); END",
+            )
+            .unwrap();
+        assert!(db.delete_records_before("2026-06-01").is_err());
+        assert_eq!(counts(&db), (1, 2, 3));
+
+        db.conn.lock().unwrap().execute_batch("DROP TRIGGER refuse").unwrap();
+        assert_eq!(db.delete_records_before("2026-06-01").unwrap(), 1);
+        assert_eq!(counts(&db), (0, 0, 0));
+    }
+
+    #[test]
+    fn search_under_field_encryption() {
+        let db = Database::in_memory().unwrap();
+        db.set_field_cipher(FieldCipher::new(&Zeroizing::new([7; 32])));
+        let at = |timestamp: &str, text: &str| {
+            let mut record = InputRecord::new("Editor".into(), None, text.into());
+            record.timestamp = DateTime::parse_from_rfc3339(timestamp).unwrap().with_timezone(&Utc);
+            pending(record, Vec::new(), HashMap::new())
+        };
+        db.insert_batch(&[
+            at("2026-05-01T09:00:00Z", "hello world"),
+            at("2026-05-01T10:00:00Z", "goodbye"),
+            at("2026-05-01T11:00:00Z", "hello again"),
+            at("2026-05-01T11:00:00Z", "say hello"),
+        ])
+        .unwrap();
+
+        let search = |limit: Option<i64>, offset: Option<i64>| -> Vec<InputRecord> {
+            db.get_records(&SearchFilter {
+                query: Some("hello".into()),
+                limit,
+                offset,
+                ..SearchFilter::default()
+            })
+            .unwrap()
+        };
+        let texts = |records: Vec<InputRecord>| -> Vec<String> { records.into_iter().map(|r| r.final_text).collect() };
+
+        // Newest first, the later insert first on a tie, and unranked
+        let records = search(None, None);
+        assert!(records.iter().all(|r| r.rank.is_none()));
+        assert_eq!(
+            records[0].snippet,
+            Some(format!("say {}hello{}", search::MATCH_START, search::MATCH_END))
+        );
+        assert_eq!(texts(records), ["say hello", "hello again", "hello world"]);
+        for _ in 0..3 {
+            assert_eq!(texts(search(Some(2), Some(1))), ["hello again", "hello world"]);
+        }
+    }
+
+    #[test]
+    fn encrypt_keeps_the_database_usable() {
+        let dir = std::env::temp_dir().join(format!("arkinput-encrypt-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db = Database::new(dir.join("arkinput.db")).unwrap();
+        let record = InputRecord::new("Editor".into(), None, "hi".into());
+        db.insert_batch(&[pending(record.clone(), Vec::new(), HashMap::new())]).unwrap();
+
+        let result = db.encrypt(&[7; 32]);
+        // Either way the open connection is a file database in WAL mode
+        let mode: String = db.conn.lock().unwrap().query_row("PRAGMA journal_mode", [], |row| row.get(0)).unwrap();
+        assert_eq!(mode, "wal");
+        assert_eq!(result.is_ok(), Database::encryption_available());
+        db.insert_batch(&[pending(record, Vec::new(), HashMap::new())]).unwrap();
+        assert_eq!(db.get_records(&SearchFilter::default()).unwrap().len(), 2);
+
+        drop(db);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn field_encryption_covers_key_events_and_bigrams() {
+        let db = Database::in_memory().unwrap();
+        let record = InputRecord::new("Editor".into(), None, "hi".into());
+        let events = vec![press(Key::KeyH), press(Key::KeyI)];
+        db.insert_batch(&[pending(record.clone(), events.clone(), tallies())]).unwrap();
+        assert_eq!(raw_key_codes(&db), ["KeyH", "KeyI"]);
+        assert_eq!(frequency_kinds(&db), ["bigram", "key"]);
+
+        db.set_field_cipher(FieldCipher::new(&Zeroizing::new([7; 32])));
+        assert_eq!(db.encrypt_fields().unwrap(), 1);
+        let id = db.insert_batch(&[pending(record, events, tallies())]).unwrap()[0];
+
+        let codes = raw_key_codes(&db);
+        assert_eq!(codes.len(), 4);
+        assert!(codes.iter().all(|code| FieldCipher::is_encrypted(code)));
+        assert_eq!(frequency_kinds(&db), ["key"]);
+
+        let keys: Vec<Key> = db.get_key_events(id).unwrap().into_iter().map(|e| e.key).collect();
+        assert_eq!(keys, [Key::KeyH, Key::KeyI]);
+    }
+}
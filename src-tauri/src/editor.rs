@@ -0,0 +1,177 @@
+/// A cursor movement or deletion applied to the reconstructed text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Edit {
+    Insert(char),
+    Backspace,
+    Delete,
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+/// Replays editing keys over the text typed into one buffer, to recover what
+/// the user actually ended up with. Only text typed within the buffer is
+/// known, so edits reaching before its start are clamped to it.
+#[derive(Debug, Clone, Default)]
+pub struct TextEditor {
+    text: Vec<char>,
+    cursor: usize,
+}
+
+impl TextEditor {
+    pub fn insert_str(&mut self, s: &str) {
+        let chars: Vec<char> = s.chars().collect();
+        let len = chars.len();
+        self.text.splice(self.cursor..self.cursor, chars);
+        self.cursor += len;
+    }
+
+    /// Remove up to `count` characters before the cursor, undoing an insert
+    pub fn remove_before(&mut self, count: usize) {
+        let start = self.cursor.saturating_sub(count);
+        self.text.drain(start..self.cursor);
+        self.cursor = start;
+    }
+
+    pub fn apply(&mut self, edit: Edit) {
+        match edit {
+            Edit::Insert(c) => {
+                self.text.insert(self.cursor, c);
+                self.cursor += 1;
+            }
+            Edit::Backspace => self.remove_before(1),
+            Edit::Delete => {
+                if self.cursor < self.text.len() {
+                    self.text.remove(self.cursor);
+                }
+            }
+            Edit::Left => self.cursor = self.cursor.saturating_sub(1),
+            Edit::Right => self.cursor = (self.cursor + 1).min(self.text.len()),
+            Edit::Up => {
+                let line_start = self.line_start(self.cursor);
+                if line_start == 0 {
+                    self.cursor = 0;
+                } else {
+                    let column = self.cursor - line_start;
+                    let prev_start = self.line_start(line_start - 1);
+                    self.cursor = prev_start + column.min(line_start - 1 - prev_start);
+                }
+            }
+            Edit::Down => {
+                let line_end = self.line_end(self.cursor);
+                if line_end == self.text.len() {
+                    self.cursor = line_end;
+                } else {
+                    let column = self.cursor - self.line_start(self.cursor);
+                    let next_start = line_end + 1;
+                    self.cursor = next_start + column.min(self.line_end(next_start) - next_start);
+                }
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.text.len()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn text(&self) -> String {
+        self.text.iter().collect()
+    }
+
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.cursor = 0;
+    }
+
+    fn line_start(&self, pos: usize) -> usize {
+        self.text[..pos]
+            .iter()
+            .rposition(|&c| c == '\n')
+            .map_or(0, |i| i + 1)
+    }
+
+    fn line_end(&self, pos: usize) -> usize {
+        self.text[pos..]
+            .iter()
+            .position(|&c| c == '\n')
+            .map_or(self.text.len(), |i| pos + i)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Edit::*;
+    use super::*;
+
+    #[test]
+    fn edits() {
+        // Typed text, then edits, then the text and cursor that result
+        let cases: [(&str, &[Edit], &str, usize); 18] = [
+            ("abc", &[], "abc", 3),
+            ("abc", &[Backspace], "ab", 2),
+            ("日本", &[Backspace], "日", 1),
+            ("abc", &[Left, Insert('X')], "abXc", 3),
+            ("abc", &[Left, Left, Delete], "ac", 1),
+            // Edits reaching past either end of the buffer are clamped
+            ("", &[Backspace, Delete, Left, Right], "", 0),
+            ("abc", &[Left, Left, Left, Left, Backspace], "abc", 0),
+            ("abc", &[Right, Delete], "abc", 3),
+            (
+                "ab",
+                &[Backspace, Backspace, Backspace, Insert('c')],
+                "c",
+                1,
+            ),
+            // Up and Down keep the column where the other line is long enough
+            ("abcd\nef", &[Up], "abcd\nef", 2),
+            ("ab\ncdef", &[Up], "ab\ncdef", 2),
+            ("ab\ncd", &[Up, Down], "ab\ncd", 5),
+            ("abcd\nef", &[Up, Right, Right, Down], "abcd\nef", 7),
+            ("ab\n\ncd", &[Up], "ab\n\ncd", 3),
+            ("ab\n\ncd", &[Up, Up, Insert('X')], "Xab\n\ncd", 1),
+            // Up on the first line goes to its start, Down on the last to its end
+            ("abc", &[Up], "abc", 0),
+            ("ab\ncd", &[Up, Up, Down, Down], "ab\ncd", 5),
+            ("ab\ncd", &[Up, Insert('\n'), Up, Backspace], "ab\n\ncd", 0),
+        ];
+        for (typed, edits, text, cursor) in cases {
+            let mut editor = TextEditor::default();
+            editor.insert_str(typed);
+            for &edit in edits {
+                editor.apply(edit);
+            }
+            assert_eq!(
+                (editor.text().as_str(), editor.cursor()),
+                (text, cursor),
+                "{:?} {:?}",
+                typed,
+                edits
+            );
+            assert_eq!(editor.len(), text.chars().count());
+        }
+    }
+
+    #[test]
+    fn remove_before_and_clear() {
+        let mut editor = TextEditor::default();
+        editor.insert_str("hello");
+        editor.apply(Left);
+        editor.apply(Left);
+        editor.remove_before(2);
+        assert_eq!((editor.text().as_str(), editor.cursor()), ("hlo", 1));
+
+        editor.remove_before(10);
+        assert_eq!((editor.text().as_str(), editor.cursor()), ("lo", 0));
+
+        editor.insert_str("a\nb");
+        assert_eq!((editor.text().as_str(), editor.cursor()), ("a\nblo", 3));
+
+        editor.clear();
+        assert_eq!((editor.text().as_str(), editor.cursor()), ("", 0));
+    }
+}
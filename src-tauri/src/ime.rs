@@ -0,0 +1,28 @@
+#[cfg(target_os = "linux")]
+mod fcitx;
+
+/// What the desktop's input method did in the focused text field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImeEvent {
+    /// The preedit (text still being composed) became visible (`true`) or was cleared
+    Preedit(bool),
+    /// Text the input method inserted into the application
+    Commit(String),
+}
+
+pub type ImeSink = Box<dyn FnMut(ImeEvent) + Send>;
+
+/// Watch the input method on a background thread, so text composed from
+/// several keystrokes (pinyin, kana, hangul jamo) can replace them in the
+/// record. On Linux this follows Fcitx 4/5 over the session bus. IBus keeps
+/// its traffic on a private bus and is not covered yet; elsewhere only the
+/// text the OS reports per key (rdev's `name`) is available.
+#[cfg(target_os = "linux")]
+pub fn start_monitor(sink: ImeSink) -> Result<(), String> {
+    fcitx::start(sink)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn start_monitor(_sink: ImeSink) -> Result<(), String> {
+    Err("not supported on this platform".to_string())
+}
@@ -0,0 +1,69 @@
+use std::thread;
+use std::time::Duration;
+
+use dbus::blocking::Connection;
+use dbus::channel::MatchingReceiver;
+use dbus::message::{MatchRule, MessageType};
+
+use super::{ImeEvent, ImeSink};
+
+/// Input context interfaces of Fcitx 5 and Fcitx 4. Both send commits and
+/// preedit updates as signals addressed to the client application, so we can
+/// only see them as a bus monitor.
+const INTERFACES: [&str; 2] = ["org.fcitx.Fcitx.InputContext1", "org.fcitx.Fcitx.InputContext"];
+const MEMBERS: [&str; 2] = ["CommitString", "UpdateFormattedPreedit"];
+
+pub fn start(mut sink: ImeSink) -> Result<(), String> {
+    let conn = Connection::new_session().map_err(|e| e.to_string())?;
+
+    let rules: Vec<String> = INTERFACES
+        .iter()
+        .flat_map(|interface| {
+            MEMBERS.iter().map(move |member| {
+                format!("type='signal',interface='{}',member='{}'", interface, member)
+            })
+        })
+        .collect();
+
+    let bus = conn.with_proxy("org.freedesktop.DBus", "/org/freedesktop/DBus", Duration::from_secs(2));
+    bus.method_call::<(), _, _, _>("org.freedesktop.DBus.Monitoring", "BecomeMonitor", (rules, 0u32))
+        .map_err(|e| format!("cannot monitor the session bus: {}", e))?;
+
+    // A monitor may not send anything, so take every message here rather than
+    // letting the connection auto-reply to stray method calls
+    conn.start_receive(
+        MatchRule::new(),
+        Box::new(move |msg, _| {
+            let is_fcitx = msg
+                .interface()
+                .is_some_and(|interface| INTERFACES.contains(&&*interface));
+            if msg.msg_type() != MessageType::Signal || !is_fcitx {
+                return true;
+            }
+
+            match msg.member().as_deref() {
+                Some("CommitString") => {
+                    if let Ok(text) = msg.read1::<String>() {
+                        sink(ImeEvent::Commit(text));
+                    }
+                }
+                Some("UpdateFormattedPreedit") => {
+                    if let Ok(segments) = msg.read1::<Vec<(String, i32)>>() {
+                        sink(ImeEvent::Preedit(segments.iter().any(|(text, _)| !text.is_empty())));
+                    }
+                }
+                _ => {}
+            }
+            true
+        }),
+    );
+
+    thread::spawn(move || loop {
+        if let Err(e) = conn.process(Duration::from_secs(1)) {
+            eprintln!("Fcitx D-Bus monitor lost: {}", e);
+            break;
+        }
+    });
+
+    Ok(())
+}
@@ -0,0 +1,292 @@
+#[cfg(target_os = "linux")]
+mod evdev;
+mod replay;
+
+use rdev::{listen, Event, EventType, Key};
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+#[cfg(target_os = "linux")]
+pub use evdev::EvdevSource;
+pub use replay::{RecordedEvent, ReplaySource};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeyAction {
+    Press,
+    Release,
+}
+
+/// A key event normalized across input sources. Keys use rdev's physical
+/// (US QWERTY position) naming whatever the source.
+#[derive(Debug, Clone)]
+pub struct KeyEvent {
+    pub action: KeyAction,
+    pub key: Key,
+    pub time: SystemTime,
+    /// Text the OS says this key produced, when the source knows it
+    pub name: Option<String>,
+}
+
+pub type EventSink = Box<dyn FnMut(KeyEvent)>;
+
+/// Whether Option+key types a character rather than a shortcut, as on macOS
+/// unless Cmd or Ctrl is held too
+const OPTION_TYPES_TEXT: bool = cfg!(target_os = "macos");
+
+/// Modifier keys currently held, one bit per physical key so that releasing
+/// one of two held Shift keys leaves the other in effect.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    const KEYS: [Key; 8] = [
+        Key::ShiftLeft,
+        Key::ShiftRight,
+        Key::ControlLeft,
+        Key::ControlRight,
+        Key::Alt,
+        Key::AltGr,
+        Key::MetaLeft,
+        Key::MetaRight,
+    ];
+
+    fn bit(key: Key) -> Option<u8> {
+        Self::KEYS.iter().position(|k| *k == key).map(|i| 1 << i)
+    }
+
+    pub fn is_modifier(key: Key) -> bool {
+        Self::bit(key).is_some()
+    }
+
+    /// Follow a key event. Returns true when the key was a modifier.
+    pub fn update(&mut self, event: &KeyEvent) -> bool {
+        let bit = match Self::bit(event.key) {
+            Some(bit) => bit,
+            None => return false,
+        };
+        match event.action {
+            KeyAction::Press => self.0 |= bit,
+            KeyAction::Release => self.0 &= !bit,
+        }
+        true
+    }
+
+    /// The held keys in `KEYS` order: bit 0 is left Shift, bit 7 right Meta
+    pub fn bits(self) -> u8 {
+        self.0
+    }
+
+    fn any(self, keys: &[Key]) -> bool {
+        keys.iter().any(|&key| Self::bit(key).is_some_and(|bit| self.0 & bit != 0))
+    }
+
+    /// The modifier keys held down
+    pub fn held(self) -> impl Iterator<Item = Key> {
+        Self::KEYS
+            .into_iter()
+            .filter(move |&key| Self::bit(key).is_some_and(|bit| self.0 & bit != 0))
+    }
+
+    pub fn shift(self) -> bool {
+        self.any(&[Key::ShiftLeft, Key::ShiftRight])
+    }
+
+    /// Left Alt only; AltGr picks characters rather than shortcuts
+    pub fn alt(self) -> bool {
+        self.any(&[Key::Alt])
+    }
+
+    pub fn altgr(self) -> bool {
+        self.any(&[Key::AltGr])
+    }
+
+    pub fn meta(self) -> bool {
+        self.any(&[Key::MetaLeft, Key::MetaRight])
+    }
+
+    /// The shortcut token for pressing `key` with these modifiers, such as
+    /// `[Ctrl+Shift+T]`. `None` unless Ctrl, Alt or Meta is held, since Shift
+    /// alone just selects the character.
+    pub fn chord(self, key: Key) -> Option<String> {
+        self.chord_with(key, OPTION_TYPES_TEXT)
+    }
+
+    fn chord_with(self, key: Key, option_types_text: bool) -> Option<String> {
+        // Windows reports AltGr as left Ctrl plus right Alt; that Ctrl is part
+        // of picking a character, not of a shortcut
+        let ctrl = self.any(&[Key::ControlRight])
+            || (self.any(&[Key::ControlLeft]) && !self.any(&[Key::AltGr]));
+        let alt = self.alt() && !(option_types_text && !ctrl && !self.meta());
+        if Self::is_modifier(key) || !(ctrl || alt || self.meta()) {
+            return None;
+        }
+
+        let mut parts = Vec::new();
+        if ctrl {
+            parts.push("Ctrl");
+        }
+        if alt {
+            parts.push("Alt");
+        }
+        if self.shift() {
+            parts.push("Shift");
+        }
+        if self.meta() {
+            parts.push("Meta");
+        }
+        let label = key_label(key);
+        parts.push(&label);
+        Some(format!("[{}]", parts.join("+")))
+    }
+}
+
+/// A short name for a key as printed on a US keyboard, for shortcut tokens.
+fn key_label(key: Key) -> String {
+    let label = match key {
+        Key::Return => "Enter",
+        Key::Escape => "Esc",
+        Key::UpArrow => "Up",
+        Key::DownArrow => "Down",
+        Key::LeftArrow => "Left",
+        Key::RightArrow => "Right",
+        Key::BackQuote => "`",
+        Key::Minus => "-",
+        Key::Equal => "=",
+        Key::LeftBracket => "[",
+        Key::RightBracket => "]",
+        Key::SemiColon => ";",
+        Key::Quote => "'",
+        Key::BackSlash | Key::IntlBackslash => "\\",
+        Key::Comma => ",",
+        Key::Dot => ".",
+        Key::Slash => "/",
+        _ => {
+            let name = format!("{:?}", key);
+            return match name.strip_prefix("Key").or_else(|| name.strip_prefix("Num")) {
+                Some(rest) if rest.len() == 1 => rest.to_string(),
+                _ => name,
+            };
+        }
+    };
+    label.to_string()
+}
+
+/// Something that produces key events for the keyboard pipeline.
+pub trait InputSource: Send {
+    fn name(&self) -> &'static str;
+
+    /// Deliver events to `sink` until the source is exhausted or fails. Live
+    /// sources block forever.
+    fn run(self: Box<Self>, sink: EventSink) -> Result<(), String>;
+}
+
+/// Global hook through rdev, available on every platform.
+pub struct RdevSource;
+
+impl InputSource for RdevSource {
+    fn name(&self) -> &'static str {
+        "rdev"
+    }
+
+    fn run(self: Box<Self>, mut sink: EventSink) -> Result<(), String> {
+        listen(move |event: Event| {
+            let (action, key) = match event.event_type {
+                EventType::KeyPress(key) => (KeyAction::Press, key),
+                EventType::KeyRelease(key) => (KeyAction::Release, key),
+                _ => return,
+            };
+            sink(KeyEvent {
+                action,
+                key,
+                time: event.time,
+                name: event.name,
+            });
+        })
+        .map_err(|e| format!("{:?}", e))
+    }
+}
+
+/// Pick the input source named by `ARKINPUT_INPUT_SOURCE`: `rdev` (default),
+/// `evdev` on Linux, or `replay:<path>` to feed a recorded event file.
+pub fn default_source() -> Box<dyn InputSource> {
+    let configured = std::env::var("ARKINPUT_INPUT_SOURCE").unwrap_or_default();
+
+    if let Some(path) = configured.strip_prefix("replay:") {
+        return Box::new(ReplaySource::new(path.into()));
+    }
+
+    #[cfg(target_os = "linux")]
+    if configured == "evdev" {
+        match EvdevSource::detect() {
+            Ok(source) => return Box::new(source),
+            Err(e) => eprintln!("Failed to open evdev keyboards, falling back to rdev: {}", e),
+        }
+    }
+
+    Box::new(RdevSource)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn held(keys: &[Key]) -> Modifiers {
+        let mut modifiers = Modifiers::default();
+        for &key in keys {
+            modifiers.update(&KeyEvent {
+                action: KeyAction::Press,
+                key,
+                time: SystemTime::UNIX_EPOCH,
+                name: None,
+            });
+        }
+        modifiers
+    }
+
+    #[test]
+    fn chords() {
+        let cases: &[(&[Key], Key, bool, Option<&str>)] = &[
+            (&[], Key::KeyA, false, None),
+            (&[Key::ShiftLeft], Key::KeyA, false, None),
+            (&[Key::ControlLeft], Key::ControlRight, false, None),
+            (&[Key::ControlLeft], Key::KeyS, false, Some("[Ctrl+S]")),
+            (&[Key::ControlRight, Key::ShiftLeft], Key::KeyT, false, Some("[Ctrl+Shift+T]")),
+            (&[Key::Alt], Key::Tab, false, Some("[Alt+Tab]")),
+            (&[Key::MetaLeft], Key::KeyL, false, Some("[Meta+L]")),
+            // AltGr alone, and as Windows reports it with left Ctrl, types text
+            (&[Key::AltGr], Key::KeyE, false, None),
+            (&[Key::ControlLeft, Key::AltGr], Key::KeyE, false, None),
+            (&[Key::ControlLeft, Key::AltGr, Key::ShiftLeft], Key::Num7, false, None),
+            (&[Key::ControlRight, Key::AltGr], Key::KeyE, false, Some("[Ctrl+E]")),
+            // macOS Option types text unless Cmd or Ctrl is held too
+            (&[Key::Alt], Key::KeyE, true, None),
+            (&[Key::Alt, Key::ShiftLeft], Key::KeyK, true, None),
+            (&[Key::Alt, Key::MetaLeft], Key::KeyH, true, Some("[Alt+Meta+H]")),
+            (&[Key::Alt, Key::ControlLeft], Key::KeyF, true, Some("[Ctrl+Alt+F]")),
+        ];
+
+        for (keys, key, option_types_text, expected) in cases {
+            assert_eq!(
+                held(keys).chord_with(*key, *option_types_text).as_deref(),
+                *expected,
+                "{:?} + {:?}",
+                keys,
+                key
+            );
+        }
+    }
+
+    #[test]
+    fn releasing_one_shift_keeps_the_other() {
+        let mut modifiers = held(&[Key::ShiftLeft, Key::ShiftRight]);
+        modifiers.update(&KeyEvent {
+            action: KeyAction::Release,
+            key: Key::ShiftLeft,
+            time: SystemTime::UNIX_EPOCH,
+            name: None,
+        });
+        assert!(modifiers.shift());
+        assert!(!Modifiers::default().shift());
+    }
+}
@@ -0,0 +1,335 @@
+use rdev::Key;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+use super::{EventSink, InputSource, KeyAction, KeyEvent};
+
+const EV_KEY: u16 = 1;
+const EV_REP: u16 = 20;
+
+/// `struct input_event`: a timeval, then type, code and value. Its size
+/// follows the target's `time_t`.
+const INPUT_EVENT_SIZE: usize = std::mem::size_of::<libc::input_event>();
+
+/// Reads keyboards straight from `/dev/input/event*`. Works without a display
+/// server (and under Wayland), but needs read access to the device nodes,
+/// usually through the `input` group.
+pub struct EvdevSource {
+    devices: Vec<PathBuf>,
+}
+
+impl EvdevSource {
+    pub fn new(devices: Vec<PathBuf>) -> Self {
+        Self { devices }
+    }
+
+    /// Find keyboards listed in `/proc/bus/input/devices`: a `kbd` handler
+    /// and autorepeat support tells them apart from power buttons and the like.
+    pub fn detect() -> Result<Self, String> {
+        let listing = std::fs::read_to_string("/proc/bus/input/devices").map_err(|e| e.to_string())?;
+
+        let devices: Vec<PathBuf> = listing
+            .split("\n\n")
+            .filter_map(|block| {
+                let mut handlers = None;
+                let mut ev_bits = 0u32;
+                for line in block.lines() {
+                    if let Some(h) = line.strip_prefix("H: Handlers=") {
+                        handlers = Some(h.split_whitespace().collect::<Vec<_>>());
+                    } else if let Some(ev) = line.strip_prefix("B: EV=") {
+                        ev_bits = u32::from_str_radix(ev.trim(), 16).unwrap_or(0);
+                    }
+                }
+                let handlers = handlers?;
+                if !handlers.contains(&"kbd") || ev_bits & (1u32 << EV_REP) == 0 {
+                    return None;
+                }
+                handlers
+                    .iter()
+                    .find(|h| h.starts_with("event"))
+                    .map(|h| PathBuf::from("/dev/input").join(h))
+            })
+            .collect();
+
+        if devices.is_empty() {
+            return Err("no keyboard devices found".to_string());
+        }
+        Ok(Self::new(devices))
+    }
+}
+
+impl InputSource for EvdevSource {
+    fn name(&self) -> &'static str {
+        "evdev"
+    }
+
+    fn run(self: Box<Self>, mut sink: EventSink) -> Result<(), String> {
+        let (tx, rx) = mpsc::channel();
+
+        // One unreadable node must not stop the keyboards that can be read
+        let mut opened = 0;
+        for path in self.devices {
+            let mut file = match File::open(&path) {
+                Ok(file) => file,
+                Err(e) => {
+                    eprintln!("Skipping {}: {}", path.display(), e);
+                    continue;
+                }
+            };
+            opened += 1;
+            let tx = tx.clone();
+            thread::spawn(move || {
+                let mut raw = [0u8; INPUT_EVENT_SIZE];
+                while file.read_exact(&mut raw).is_ok() {
+                    if let Some(event) = parse_event(&raw) {
+                        if tx.send(event).is_err() {
+                            break;
+                        }
+                    }
+                }
+                eprintln!("Stopped reading {}", path.display());
+            });
+        }
+        drop(tx);
+        if opened == 0 {
+            return Err("no keyboard device could be opened".to_string());
+        }
+
+        for event in rx {
+            sink(event);
+        }
+        Err("all keyboard devices closed".to_string())
+    }
+}
+
+fn parse_event(raw: &[u8; INPUT_EVENT_SIZE]) -> Option<KeyEvent> {
+    // Any bit pattern is a valid input_event, and the buffer need not be aligned
+    let event: libc::input_event = unsafe { std::ptr::read_unaligned(raw.as_ptr().cast()) };
+
+    if event.type_ != EV_KEY {
+        return None;
+    }
+
+    // 1 = press, 2 = autorepeat (reported as a press, like X11 does), 0 = release
+    let action = match event.value {
+        1 | 2 => KeyAction::Press,
+        0 => KeyAction::Release,
+        _ => return None,
+    };
+
+    Some(KeyEvent {
+        action,
+        key: key_from_code(event.code),
+        time: UNIX_EPOCH
+            + Duration::new(
+                event.time.tv_sec.max(0) as u64,
+                (event.time.tv_usec.max(0) as u32) * 1000,
+            ),
+        name: None,
+    })
+}
+
+/// Linux input-event-codes.h key codes. X11 keycodes are these plus 8, so
+/// unknown keys keep the same `Key::Unknown` value rdev would report.
+fn key_from_code(code: u16) -> Key {
+    match code {
+        1 => Key::Escape,
+        2 => Key::Num1,
+        3 => Key::Num2,
+        4 => Key::Num3,
+        5 => Key::Num4,
+        6 => Key::Num5,
+        7 => Key::Num6,
+        8 => Key::Num7,
+        9 => Key::Num8,
+        10 => Key::Num9,
+        11 => Key::Num0,
+        12 => Key::Minus,
+        13 => Key::Equal,
+        14 => Key::Backspace,
+        15 => Key::Tab,
+        16 => Key::KeyQ,
+        17 => Key::KeyW,
+        18 => Key::KeyE,
+        19 => Key::KeyR,
+        20 => Key::KeyT,
+        21 => Key::KeyY,
+        22 => Key::KeyU,
+        23 => Key::KeyI,
+        24 => Key::KeyO,
+        25 => Key::KeyP,
+        26 => Key::LeftBracket,
+        27 => Key::RightBracket,
+        28 => Key::Return,
+        29 => Key::ControlLeft,
+        30 => Key::KeyA,
+        31 => Key::KeyS,
+        32 => Key::KeyD,
+        33 => Key::KeyF,
+        34 => Key::KeyG,
+        35 => Key::KeyH,
+        36 => Key::KeyJ,
+        37 => Key::KeyK,
+        38 => Key::KeyL,
+        39 => Key::SemiColon,
+        40 => Key::Quote,
+        41 => Key::BackQuote,
+        42 => Key::ShiftLeft,
+        43 => Key::BackSlash,
+        44 => Key::KeyZ,
+        45 => Key::KeyX,
+        46 => Key::KeyC,
+        47 => Key::KeyV,
+        48 => Key::KeyB,
+        49 => Key::KeyN,
+        50 => Key::KeyM,
+        51 => Key::Comma,
+        52 => Key::Dot,
+        53 => Key::Slash,
+        54 => Key::ShiftRight,
+        55 => Key::KpMultiply,
+        56 => Key::Alt,
+        57 => Key::Space,
+        58 => Key::CapsLock,
+        59 => Key::F1,
+        60 => Key::F2,
+        61 => Key::F3,
+        62 => Key::F4,
+        63 => Key::F5,
+        64 => Key::F6,
+        65 => Key::F7,
+        66 => Key::F8,
+        67 => Key::F9,
+        68 => Key::F10,
+        69 => Key::NumLock,
+        70 => Key::ScrollLock,
+        71 => Key::Kp7,
+        72 => Key::Kp8,
+        73 => Key::Kp9,
+        74 => Key::KpMinus,
+        75 => Key::Kp4,
+        76 => Key::Kp5,
+        77 => Key::Kp6,
+        78 => Key::KpPlus,
+        79 => Key::Kp1,
+        80 => Key::Kp2,
+        81 => Key::Kp3,
+        82 => Key::Kp0,
+        83 => Key::KpDelete,
+        86 => Key::IntlBackslash,
+        87 => Key::F11,
+        88 => Key::F12,
+        96 => Key::KpReturn,
+        97 => Key::ControlRight,
+        98 => Key::KpDivide,
+        99 => Key::PrintScreen,
+        100 => Key::AltGr,
+        102 => Key::Home,
+        103 => Key::UpArrow,
+        104 => Key::PageUp,
+        105 => Key::LeftArrow,
+        106 => Key::RightArrow,
+        107 => Key::End,
+        108 => Key::DownArrow,
+        109 => Key::PageDown,
+        110 => Key::Insert,
+        111 => Key::Delete,
+        119 => Key::Pause,
+        125 => Key::MetaLeft,
+        126 => Key::MetaRight,
+        _ => Key::Unknown(code as u32 + 8),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    const EV_SYN: u16 = 0;
+    const KEY_A: u16 = 30;
+
+    fn raw(kind: u16, code: u16, value: i32) -> [u8; INPUT_EVENT_SIZE] {
+        let event = libc::input_event {
+            time: libc::timeval {
+                tv_sec: 1_700_000_000,
+                tv_usec: 250_000,
+            },
+            type_: kind,
+            code,
+            value,
+        };
+        let mut raw = [0u8; INPUT_EVENT_SIZE];
+        unsafe { std::ptr::write_unaligned(raw.as_mut_ptr().cast(), event) };
+        raw
+    }
+
+    #[test]
+    fn parses_key_events() {
+        let cases = [
+            (raw(EV_KEY, KEY_A, 1), Some((KeyAction::Press, Key::KeyA))),
+            (raw(EV_KEY, KEY_A, 2), Some((KeyAction::Press, Key::KeyA))),
+            (raw(EV_KEY, KEY_A, 0), Some((KeyAction::Release, Key::KeyA))),
+            (raw(EV_KEY, KEY_A, 3), None),
+            (raw(EV_SYN, 0, 0), None),
+            // Unmapped keys carry their X11 keycode, like rdev reports them
+            (raw(EV_KEY, 240, 1), Some((KeyAction::Press, Key::Unknown(248)))),
+        ];
+        for (raw, expected) in cases {
+            let event = parse_event(&raw);
+            assert_eq!(event.as_ref().map(|e| (e.action, e.key)), expected);
+            if let Some(event) = event {
+                assert_eq!(
+                    event.time.duration_since(UNIX_EPOCH).unwrap(),
+                    Duration::from_millis(1_700_000_000_250)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn skips_devices_that_fail_to_open() {
+        let dir = std::env::temp_dir().join(format!("arkinput-evdev-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let readable = dir.join("event0");
+        std::fs::write(&readable, [raw(EV_KEY, KEY_A, 1), raw(EV_KEY, KEY_A, 0)].concat()).unwrap();
+        let missing = dir.join("event1");
+
+        let received = Rc::new(RefCell::new(Vec::new()));
+        let sink = {
+            let received = received.clone();
+            Box::new(move |event: KeyEvent| received.borrow_mut().push((event.action, event.key)))
+        };
+        let source = Box::new(EvdevSource::new(vec![missing.clone(), readable]));
+        // Reading a plain file ends at its end, like a device being unplugged
+        assert_eq!(source.run(sink), Err("all keyboard devices closed".to_string()));
+        assert_eq!(
+            *received.borrow(),
+            [(KeyAction::Press, Key::KeyA), (KeyAction::Release, Key::KeyA)]
+        );
+
+        let source = Box::new(EvdevSource::new(vec![missing]));
+        assert_eq!(
+            source.run(Box::new(|_| {})),
+            Err("no keyboard device could be opened".to_string())
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn event_size_matches_the_target() {
+        #[cfg(target_pointer_width = "64")]
+        assert_eq!(INPUT_EVENT_SIZE, 24);
+        assert_eq!(
+            INPUT_EVENT_SIZE,
+            std::mem::size_of::<libc::timeval>()
+                + 2 * std::mem::size_of::<u16>()
+                + std::mem::size_of::<i32>()
+        );
+    }
+}
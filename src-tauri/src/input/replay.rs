@@ -0,0 +1,171 @@
+use rdev::Key;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use super::{EventSink, InputSource, KeyAction, KeyEvent};
+
+/// One line of a recorded event file, e.g.
+/// `{"at_ms": 120, "action": "press", "key": "KeyA", "app": "Code"}`.
+/// `at_ms` is measured from the start of the recording. `app` and `title` are
+/// only used by `keyboard::replay`, to script focus changes.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedEvent {
+    pub at_ms: u64,
+    pub action: KeyAction,
+    pub key: Key,
+    #[serde(default)]
+    pub name: Option<String>,
+    #[serde(default)]
+    pub app: Option<String>,
+    #[serde(default)]
+    pub title: Option<String>,
+}
+
+impl RecordedEvent {
+    pub fn parse_lines(reader: impl BufRead) -> Result<Vec<Self>, String> {
+        let mut events = Vec::new();
+        for (i, line) in reader.lines().enumerate() {
+            let line = line.map_err(|e| e.to_string())?;
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let event = serde_json::from_str(line).map_err(|e| format!("line {}: {}", i + 1, e))?;
+            events.push(event);
+        }
+        Ok(events)
+    }
+}
+
+/// Plays back a JSON-lines event file at its recorded pace.
+pub struct ReplaySource {
+    path: PathBuf,
+}
+
+impl ReplaySource {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+impl InputSource for ReplaySource {
+    fn name(&self) -> &'static str {
+        "replay"
+    }
+
+    fn run(self: Box<Self>, mut sink: EventSink) -> Result<(), String> {
+        let file = std::fs::File::open(&self.path)
+            .map_err(|e| format!("{}: {}", self.path.display(), e))?;
+        let events = RecordedEvent::parse_lines(BufReader::new(file))?;
+
+        let start = SystemTime::now();
+        let mut elapsed_ms = 0;
+        for event in events {
+            if event.at_ms > elapsed_ms {
+                thread::sleep(Duration::from_millis(event.at_ms - elapsed_ms));
+                elapsed_ms = event.at_ms;
+            }
+            sink(KeyEvent {
+                action: event.action,
+                key: event.key,
+                time: start + Duration::from_millis(event.at_ms),
+                name: event.name,
+            });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    const SCRIPT: &str = r#"
+# typed into the editor
+{"at_ms": 0, "action": "press", "key": "KeyH", "name": "h", "app": "Editor", "title": "notes.txt"}
+
+{"at_ms": 40, "action": "release", "key": "KeyH"}
+{"at_ms": 40, "action": "press", "key": "ShiftLeft"}
+"#;
+
+    #[test]
+    fn parses_lines() {
+        let events = RecordedEvent::parse_lines(SCRIPT.as_bytes()).unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].key, Key::KeyH);
+        assert_eq!(events[0].name.as_deref(), Some("h"));
+        assert_eq!(events[0].app.as_deref(), Some("Editor"));
+        assert_eq!(events[1].action, KeyAction::Release);
+        assert!(events[1].name.is_none() && events[1].app.is_none() && events[1].title.is_none());
+    }
+
+    #[test]
+    fn reports_the_bad_line() {
+        let script = r#"{"at_ms": 0, "action": "press", "key": "KeyA"}
+
+{"at_ms": 5, "action": "tap", "key": "KeyA"}
+"#;
+        let err = RecordedEvent::parse_lines(script.as_bytes()).unwrap_err();
+        assert!(err.starts_with("line 3:"), "{}", err);
+    }
+
+    #[test]
+    fn replays_at_the_recorded_pace() {
+        let path =
+            std::env::temp_dir().join(format!("arkinput-replay-{}.jsonl", std::process::id()));
+        std::fs::write(&path, SCRIPT).unwrap();
+
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let sink_received = received.clone();
+        let started = std::time::Instant::now();
+        Box::new(ReplaySource::new(path.clone()))
+            .run(Box::new(move |event| {
+                sink_received.lock().unwrap().push(event)
+            }))
+            .unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(40));
+        std::fs::remove_file(&path).unwrap();
+
+        let received = received.lock().unwrap();
+        let keys: Vec<_> = received.iter().map(|e| (e.action, e.key)).collect();
+        assert_eq!(
+            keys,
+            [
+                (KeyAction::Press, Key::KeyH),
+                (KeyAction::Release, Key::KeyH),
+                (KeyAction::Press, Key::ShiftLeft)
+            ]
+        );
+        assert_eq!(received[0].name.as_deref(), Some("h"));
+        // Event times keep the recorded spacing
+        let offsets: Vec<_> = received
+            .iter()
+            .map(|e| e.time.duration_since(received[0].time).unwrap())
+            .collect();
+        assert_eq!(
+            offsets,
+            [
+                Duration::ZERO,
+                Duration::from_millis(40),
+                Duration::from_millis(40)
+            ]
+        );
+    }
+
+    #[test]
+    fn missing_file_is_an_error() {
+        let path = PathBuf::from("/nonexistent/arkinput-replay.jsonl");
+        let err = Box::new(ReplaySource::new(path))
+            .run(Box::new(|_| {}))
+            .unwrap_err();
+        assert!(
+            err.contains("/nonexistent/arkinput-replay.jsonl"),
+            "{}",
+            err
+        );
+    }
+}
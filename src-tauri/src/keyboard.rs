@@ -1,263 +1,1164 @@
-use once_cell::sync::OnceCell;
+use chrono::{DateTime, Utc};
+use once_cell::sync::{Lazy, OnceCell};
 use parking_lot::Mutex;
-use rdev::{listen, Event, EventType, Key};
+use rdev::Key;
+use std::collections::HashMap;
 use std::sync::Arc;
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use crate::activity::{self, FocusTracker};
 use crate::database::Database;
-use crate::models::InputRecord;
-use crate::window::get_active_window;
+use crate::editor::{Edit, TextEditor};
+use crate::ime::ImeEvent;
+use crate::input::{InputSource, KeyAction, KeyEvent, Modifiers, RecordedEvent};
+use crate::layout::{KeyTranslator, Layout};
+use crate::models::{
+    InputRecord, KeyStroke, KeyTally, PendingRecord, RecordingState, RecordingStatus, Settings, WriterMetrics,
+};
+use crate::redact::{Redaction, RedactionMode, Redactor};
+use crate::rules::RuleSet;
+use crate::secure::{self, SecureContext, SecureRule};
+use crate::timezone::Zone;
+use crate::window::{active_layout, get_active_window, WindowInfo};
+use crate::writer::Writer;
 
 static DB: OnceCell<Arc<Database>> = OnceCell::new();
-static EXCLUDED_APPS: OnceCell<Mutex<Vec<String>>> = OnceCell::new();
+static PIPELINE: OnceCell<Arc<Pipeline>> = OnceCell::new();
+static STATUS: Lazy<Mutex<RecordingStatus>> = Lazy::new(|| {
+    Mutex::new(RecordingStatus {
+        state: RecordingState::Stopped,
+        paused_until: None,
+        secure_input: false,
+        suppressed_keys: 0,
+        listener_running: false,
+    })
+});
+static STATUS_LISTENER: OnceCell<StatusListener> = OnceCell::new();
+static SECURE_RULES: Lazy<Mutex<Vec<Box<dyn SecureRule>>>> = Lazy::new(|| Mutex::new(secure::default_rules()));
+static CONFIG: Lazy<Mutex<ListenerConfig>> = Lazy::new(|| Mutex::new(ListenerConfig::default()));
+/// The system layout last detected, and when
+static SYSTEM_LAYOUT: Mutex<Option<(Instant, Layout)>> = Mutex::new(None);
+
+type StatusListener = Box<dyn Fn(&RecordingStatus) + Send + Sync>;
+
+const FLUSH_TICK: Duration = Duration::from_millis(100);
+/// How soon after a key press an input method commit without a preedit
+/// (e.g. full-width punctuation) is taken to replace that key's text
+const DIRECT_COMMIT_WINDOW: Duration = Duration::from_millis(200);
+/// How long an open preedit may hold the buffer back from being saved before
+/// the composition counts as abandoned
+const COMPOSITION_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long a detected system layout is used before asking the system again
+const LAYOUT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Listener settings that can be swapped while it runs. The pipeline reads them
+/// on every event and flush tick, so changes apply immediately.
+#[derive(Debug, Clone)]
+pub struct ListenerConfig {
+    /// Which windows may be recorded
+    pub rules: RuleSet,
+    /// Zone the schedules in `rules` are read in
+    pub time_zone: Zone,
+    /// Idle time after which buffered input is saved as a record
+    pub merge_interval: Duration,
+    /// Fallback for sources that do not report the text a key produced;
+    /// `None` follows the layout the system has active
+    pub layout: Option<Layout>,
+    /// Keep every press and release alongside the merged records
+    pub record_key_events: bool,
+    /// Scrubs secrets from records before they are saved
+    pub redactor: Redactor,
+    /// Time without a key press after which foreground time stops counting
+    pub idle_timeout: Duration,
+}
+
+impl Default for ListenerConfig {
+    fn default() -> Self {
+        Self {
+            rules: RuleSet::default(),
+            time_zone: Zone::default(),
+            merge_interval: Duration::from_millis(500),
+            layout: Some(Layout::default()),
+            record_key_events: false,
+            redactor: Redactor::default(),
+            idle_timeout: Duration::from_secs(300),
+        }
+    }
+}
+
+impl From<&Settings> for ListenerConfig {
+    fn from(settings: &Settings) -> Self {
+        Self {
+            rules: RuleSet::new(&settings.capture_rules, &settings.excluded_apps),
+            time_zone: Zone::parse(&settings.time_zone).unwrap_or_default(),
+            merge_interval: Duration::from_millis(settings.merge_interval_ms),
+            layout: Layout::from_setting(&settings.keyboard_layout),
+            record_key_events: settings.record_key_events,
+            redactor: Redactor::new(
+                RedactionMode::from_name(&settings.redaction_mode),
+                &settings.redaction_patterns,
+            ),
+            idle_timeout: Duration::from_secs(settings.idle_timeout_secs),
+        }
+    }
+}
+
+/// Time source for the pipeline, so replays can run on virtual time.
+pub trait Clock: Send + Sync {
+    /// Monotonic time, used for the merge timeout
+    fn elapsed(&self) -> Duration;
+    /// Wall-clock time stamped on saved records
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// A clock that only moves when told to.
+pub struct VirtualClock {
+    origin: DateTime<Utc>,
+    offset: Mutex<Duration>,
+}
+
+impl VirtualClock {
+    pub fn new(origin: DateTime<Utc>) -> Self {
+        Self {
+            origin,
+            offset: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    pub fn set(&self, offset: Duration) {
+        *self.offset.lock() = offset;
+    }
+}
+
+impl Clock for VirtualClock {
+    fn elapsed(&self) -> Duration {
+        *self.offset.lock()
+    }
+
+    fn now(&self) -> DateTime<Utc> {
+        self.origin + chrono::Duration::from_std(self.elapsed()).unwrap_or_default()
+    }
+}
 
 struct InputBuffer {
+    /// Raw keystrokes, with editing keys as `[Backspace]`-style tokens
     content: String,
+    /// The text as edited, with those keys applied
+    editor: TextEditor,
     app_name: String,
     window_title: Option<String>,
-    last_input_time: Instant,
+    last_input_time: Duration,
+    /// When the first and the latest key typed into this record were pressed
+    first_key_time: Option<Duration>,
+    last_key_time: Duration,
     key_count: i32,
+    /// Some of the content was committed by an input method
+    composed: bool,
+    /// Where the text of the last key press starts, while it is still the tail
+    /// of `content`
+    last_text_start: Option<usize>,
+    /// Where the keystrokes of the current input method composition start
+    composition_start: Option<usize>,
+    /// The input method is showing a preedit
+    composing: bool,
+    /// Presses and releases typed into this buffer, when they are kept
+    key_events: Vec<KeyStroke>,
+    /// Shortcut tokens in `content`, kept for `shortcut_stats`
+    shortcuts: Vec<String>,
+    /// Key presses counted for `key_frequency`, and the last key pressed
+    key_frequency: HashMap<KeyTally, i64>,
+    last_key: Option<Key>,
 }
 
 impl InputBuffer {
     fn new() -> Self {
         Self {
             content: String::new(),
+            editor: TextEditor::default(),
             app_name: String::new(),
             window_title: None,
-            last_input_time: Instant::now(),
+            last_input_time: Duration::ZERO,
+            first_key_time: None,
+            last_key_time: Duration::ZERO,
             key_count: 0,
+            composed: false,
+            last_text_start: None,
+            composition_start: None,
+            composing: false,
+            key_events: Vec::new(),
+            shortcuts: Vec::new(),
+            key_frequency: HashMap::new(),
+            last_key: None,
         }
     }
 
     fn reset(&mut self) {
         self.content.clear();
+        self.editor.clear();
         self.app_name.clear();
         self.window_title = None;
+        self.first_key_time = None;
         self.key_count = 0;
+        self.composed = false;
+        self.last_text_start = None;
+        self.composition_start = None;
+        self.composing = false;
+        self.key_events.clear();
+        self.shortcuts.clear();
+        self.key_frequency.clear();
+        self.last_key = None;
+    }
+
+    /// Count a key press, with the key before it and the modifiers held
+    fn tally(&mut self, key: Key, modifiers: Modifiers) {
+        *self.key_frequency.entry(KeyTally::Key(key)).or_default() += 1;
+        if let Some(previous) = self.last_key.replace(key) {
+            *self.key_frequency.entry(KeyTally::Bigram(previous, key)).or_default() += 1;
+        }
+        for held in modifiers.held() {
+            *self.key_frequency.entry(KeyTally::Modifier(held)).or_default() += 1;
+        }
+    }
+
+    /// Note typing at `now`, for the record's duration
+    fn touch(&mut self, now: Duration) {
+        self.first_key_time.get_or_insert(now);
+        self.last_key_time = now;
     }
 
     fn is_empty(&self) -> bool {
         self.content.is_empty()
     }
+
+    /// Apply an editing key to the final text, unless the input method is
+    /// composing and the key never reaches the application.
+    fn edit(&mut self, edit: Edit) {
+        if !self.composing {
+            self.editor.apply(edit);
+        }
+    }
+
+    /// Characters produced by the last key press, while they end `content`
+    fn last_text_len(&self) -> usize {
+        self.last_text_start
+            .map_or(0, |start| self.content[start..].chars().count())
+    }
 }
 
 pub fn init_database(db: Arc<Database>) {
     let _ = DB.set(db);
-    let _ = EXCLUDED_APPS.set(Mutex::new(Vec::new()));
-}
-
-pub fn set_excluded_apps(apps: Vec<String>) {
-    if let Some(excluded) = EXCLUDED_APPS.get() {
-        let mut guard = excluded.lock();
-        *guard = apps;
-    }
-}
-
-fn is_app_excluded(app_name: &str) -> bool {
-    if let Some(excluded) = EXCLUDED_APPS.get() {
-        let guard = excluded.lock();
-        guard.iter().any(|a| a.eq_ignore_ascii_case(app_name))
-    } else {
-        false
-    }
-}
-
-fn key_to_char(key: Key, shift: bool) -> Option<char> {
-    match key {
-        Key::KeyA => Some(if shift { 'A' } else { 'a' }),
-        Key::KeyB => Some(if shift { 'B' } else { 'b' }),
-        Key::KeyC => Some(if shift { 'C' } else { 'c' }),
-        Key::KeyD => Some(if shift { 'D' } else { 'd' }),
-        Key::KeyE => Some(if shift { 'E' } else { 'e' }),
-        Key::KeyF => Some(if shift { 'F' } else { 'f' }),
-        Key::KeyG => Some(if shift { 'G' } else { 'g' }),
-        Key::KeyH => Some(if shift { 'H' } else { 'h' }),
-        Key::KeyI => Some(if shift { 'I' } else { 'i' }),
-        Key::KeyJ => Some(if shift { 'J' } else { 'j' }),
-        Key::KeyK => Some(if shift { 'K' } else { 'k' }),
-        Key::KeyL => Some(if shift { 'L' } else { 'l' }),
-        Key::KeyM => Some(if shift { 'M' } else { 'm' }),
-        Key::KeyN => Some(if shift { 'N' } else { 'n' }),
-        Key::KeyO => Some(if shift { 'O' } else { 'o' }),
-        Key::KeyP => Some(if shift { 'P' } else { 'p' }),
-        Key::KeyQ => Some(if shift { 'Q' } else { 'q' }),
-        Key::KeyR => Some(if shift { 'R' } else { 'r' }),
-        Key::KeyS => Some(if shift { 'S' } else { 's' }),
-        Key::KeyT => Some(if shift { 'T' } else { 't' }),
-        Key::KeyU => Some(if shift { 'U' } else { 'u' }),
-        Key::KeyV => Some(if shift { 'V' } else { 'v' }),
-        Key::KeyW => Some(if shift { 'W' } else { 'w' }),
-        Key::KeyX => Some(if shift { 'X' } else { 'x' }),
-        Key::KeyY => Some(if shift { 'Y' } else { 'y' }),
-        Key::KeyZ => Some(if shift { 'Z' } else { 'z' }),
-        Key::Num0 => Some(if shift { ')' } else { '0' }),
-        Key::Num1 => Some(if shift { '!' } else { '1' }),
-        Key::Num2 => Some(if shift { '@' } else { '2' }),
-        Key::Num3 => Some(if shift { '#' } else { '3' }),
-        Key::Num4 => Some(if shift { '$' } else { '4' }),
-        Key::Num5 => Some(if shift { '%' } else { '5' }),
-        Key::Num6 => Some(if shift { '^' } else { '6' }),
-        Key::Num7 => Some(if shift { '&' } else { '7' }),
-        Key::Num8 => Some(if shift { '*' } else { '8' }),
-        Key::Num9 => Some(if shift { '(' } else { '9' }),
-        Key::Space => Some(' '),
-        Key::Minus => Some(if shift { '_' } else { '-' }),
-        Key::Equal => Some(if shift { '+' } else { '=' }),
-        Key::LeftBracket => Some(if shift { '{' } else { '[' }),
-        Key::RightBracket => Some(if shift { '}' } else { ']' }),
-        Key::BackSlash => Some(if shift { '|' } else { '\\' }),
-        Key::SemiColon => Some(if shift { ':' } else { ';' }),
-        Key::Quote => Some(if shift { '"' } else { '\'' }),
-        Key::Comma => Some(if shift { '<' } else { ',' }),
-        Key::Dot => Some(if shift { '>' } else { '.' }),
-        Key::Slash => Some(if shift { '?' } else { '/' }),
-        Key::BackQuote => Some(if shift { '~' } else { '`' }),
-        _ => None,
-    }
-}
-
-fn save_buffer(buffer: &mut InputBuffer) {
-    if buffer.is_empty() {
-        return;
-    }
-
-    if let Some(db) = DB.get() {
+}
+
+pub fn set_config(config: ListenerConfig) {
+    *CONFIG.lock() = config;
+}
+
+/// Push saved settings into the running listener.
+pub fn apply_settings(settings: &Settings) {
+    set_config(settings.into());
+}
+
+fn is_window_excluded(window_info: &WindowInfo, now: DateTime<Utc>) -> bool {
+    let config = CONFIG.lock();
+    !config.rules.allows(window_info, now, config.time_zone)
+}
+
+/// Ask every secure-context rule about this key press, returning the name of
+/// one that flagged it. All rules see every key, so stateful ones stay in step.
+fn is_secure_context(ctx: &SecureContext) -> Option<&'static str> {
+    let mut flagged = None;
+    for rule in SECURE_RULES.lock().iter_mut() {
+        if rule.is_secure(ctx) {
+            flagged = flagged.or(Some(rule.name()));
+        }
+    }
+    flagged
+}
+
+/// Count a dropped keystroke and tell the status listener when a secure
+/// context begins or ends.
+fn note_secure_input(secure: bool, count: bool) {
+    let changed = {
+        let mut status = STATUS.lock();
+        if secure && count {
+            status.suppressed_keys += 1;
+        }
+        if status.secure_input == secure {
+            None
+        } else {
+            status.secure_input = secure;
+            Some(status.clone())
+        }
+    };
+
+    if let Some(status) = changed {
+        notify_status(&status);
+    }
+}
+
+fn merge_interval() -> Duration {
+    CONFIG.lock().merge_interval
+}
+
+pub fn idle_timeout() -> Duration {
+    CONFIG.lock().idle_timeout
+}
+
+/// The configured layout, or the one the system has active when following it
+fn keyboard_layout() -> Layout {
+    if let Some(layout) = CONFIG.lock().layout {
+        return layout;
+    }
+
+    let mut detected = SYSTEM_LAYOUT.lock();
+    match *detected {
+        Some((at, layout)) if at.elapsed() < LAYOUT_CHECK_INTERVAL => layout,
+        _ => {
+            let layout = active_layout().unwrap_or_default();
+            *detected = Some((Instant::now(), layout));
+            layout
+        }
+    }
+}
+
+fn record_key_events() -> bool {
+    CONFIG.lock().record_key_events
+}
+
+fn redactor() -> Redactor {
+    CONFIG.lock().redactor.clone()
+}
+
+/// Keys that edit or navigate rather than produce text
+fn is_editing_key(key: Key) -> bool {
+    matches!(
+        key,
+        Key::Return
+            | Key::Tab
+            | Key::Backspace
+            | Key::Delete
+            | Key::Escape
+            | Key::UpArrow
+            | Key::DownArrow
+            | Key::LeftArrow
+            | Key::RightArrow
+    )
+}
+
+/// The merge pipeline: turns key events into buffered records and saves them
+/// once the app changes or input goes idle.
+struct Pipeline {
+    writer: Writer,
+    clock: Arc<dyn Clock>,
+    buffer: Mutex<InputBuffer>,
+    translator: Mutex<KeyTranslator>,
+    modifiers: Mutex<Modifiers>,
+    focus: Mutex<FocusTracker>,
+    /// When a key was last pressed or released
+    last_activity: Mutex<Option<Duration>>,
+}
+
+impl Pipeline {
+    fn new(db: Arc<Database>, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            writer: Writer::spawn(db),
+            focus: Mutex::new(FocusTracker::default()),
+            last_activity: Mutex::new(None),
+            clock,
+            buffer: Mutex::new(InputBuffer::new()),
+            translator: Mutex::new(KeyTranslator::default()),
+            modifiers: Mutex::new(Modifiers::default()),
+        }
+    }
+
+    /// An open preedit holds the buffer back: picking a candidate can take a
+    /// while, and the keystrokes must still be there when the commit arrives.
+    /// One cleared without a commit, or left open past `COMPOSITION_TIMEOUT`,
+    /// was abandoned and holds nothing back.
+    fn is_idle(&self, buf: &InputBuffer) -> bool {
+        let quiet = self.clock.elapsed().saturating_sub(buf.last_input_time);
+        (!buf.is_empty() || !buf.key_events.is_empty())
+            && quiet > merge_interval()
+            && (!buf.composing || quiet > COMPOSITION_TIMEOUT)
+    }
+
+    fn flush_if_idle(&self) {
+        let mut buf = self.buffer.lock();
+        if self.is_idle(&buf) {
+            self.save_buffer(&mut buf);
+        }
+    }
+
+    fn flush(&self) {
+        let mut buf = self.buffer.lock();
+        self.save_buffer(&mut buf);
+    }
+
+    fn save_buffer(&self, buffer: &mut InputBuffer) {
+        if buffer.is_empty() {
+            // Modifier taps, releases and keys that type nothing do not make
+            // a record
+            buffer.key_events.clear();
+            buffer.key_frequency.clear();
+            buffer.last_key = None;
+            return;
+        }
+
+        let mut content = buffer.content.clone();
+        let mut final_text = buffer.editor.text();
+        match redactor().redact_record(&content, &final_text) {
+            Redaction::Clean => {}
+            Redaction::Replaced {
+                content: redacted_content,
+                final_text: redacted_final,
+            } => {
+                content = redacted_content;
+                final_text = redacted_final;
+                // The individual keys would spell the secret out again
+                buffer.key_events.clear();
+                buffer.key_frequency.clear();
+            }
+            Redaction::Drop => {
+                buffer.reset();
+                return;
+            }
+        }
+
         let record = InputRecord {
             id: None,
-            timestamp: chrono::Utc::now(),
+            timestamp: self.clock.now(),
             app_name: buffer.app_name.clone(),
             window_title: buffer.window_title.clone(),
-            content: buffer.content.clone(),
+            content,
+            final_text,
             key_count: buffer.key_count,
+            composed: buffer.composed,
+            duration_ms: buffer
+                .first_key_time
+                .map(|first| buffer.last_key_time.saturating_sub(first).as_millis() as i64),
             created_at: None,
+            snippet: None,
+            rank: None,
+        };
+
+        self.writer.submit(PendingRecord {
+            record,
+            key_events: std::mem::take(&mut buffer.key_events),
+            shortcuts: std::mem::take(&mut buffer.shortcuts),
+            key_frequency: std::mem::take(&mut buffer.key_frequency),
+        });
+
+        buffer.reset();
+    }
+
+    /// Note which app has focus, unless the user is idle or the window is
+    /// excluded. `active_window` gives `None` while not recording.
+    fn sample_focus(&self, active_window: impl FnOnce() -> Option<WindowInfo>) {
+        let now = self.clock.elapsed();
+        let present = self
+            .last_activity
+            .lock()
+            .is_some_and(|at| now.saturating_sub(at) <= idle_timeout());
+        let app = if present {
+            active_window()
+                .filter(|window| !is_window_excluded(window, self.clock.now()))
+                .map(|window| window.app_name)
+        } else {
+            None
         };
 
-        if let Err(e) = db.insert_record(&record) {
-            eprintln!("Failed to save input record: {}", e);
+        let write = self
+            .focus
+            .lock()
+            .sample(app.as_deref(), self.clock.now().timestamp_millis());
+        if let Some(write) = write {
+            self.writer.submit_focus(write);
         }
     }
 
-    buffer.reset();
-}
+    fn handle_key_event(&self, event: &KeyEvent, active_window: impl FnOnce() -> Option<WindowInfo>) {
+        *self.last_activity.lock() = Some(self.clock.elapsed());
+        let key = event.key;
+        let is_press = event.action == KeyAction::Press;
+        let (is_modifier, modifiers) = {
+            let mut held = self.modifiers.lock();
+            (held.update(event), *held)
+        };
+        let keep_stroke = record_key_events();
 
-pub fn start_keyboard_listener() {
-    thread::spawn(|| {
-        let buffer = Arc::new(Mutex::new(InputBuffer::new()));
-        let buffer_clone = buffer.clone();
-        let shift_pressed = Arc::new(Mutex::new(false));
-        let shift_clone = shift_pressed.clone();
+        // The translator follows Shift/AltGr/Caps Lock on its own; none of the
+        // modifiers produce text
+        let is_modifier = self.translator.lock().track_modifiers(event) || is_modifier;
+        if (is_modifier || !is_press) && !keep_stroke {
+            return;
+        }
 
-        // Flush timer thread
-        let buffer_for_timer = buffer.clone();
-        thread::spawn(move || {
-            loop {
-                thread::sleep(Duration::from_millis(100));
-                let mut buf = buffer_for_timer.lock();
-                if !buf.is_empty() && buf.last_input_time.elapsed() > Duration::from_millis(500) {
-                    save_buffer(&mut buf);
+        // Get current window info
+        let window_info = active_window();
+        if window_info.is_none() {
+            return;
+        }
+        let window_info = window_info.unwrap();
+
+        // Check if the window is excluded
+        if is_window_excluded(&window_info, self.clock.now()) {
+            return;
+        }
+
+        // Translate up front, so secure-context rules can follow what is typed
+        let produces_text = is_press && !is_modifier && !is_editing_key(key) && modifiers.chord(key).is_none();
+        let text = if produces_text {
+            self.translator
+                .lock()
+                .translate(keyboard_layout, key, event.name.as_deref())
+        } else {
+            None
+        };
+
+        // Nothing is kept while a password field or prompt has focus; releases
+        // and modifiers follow the decision made for the last key press
+        let counts = is_press && !is_modifier;
+        let secure = if counts {
+            let flagged = is_secure_context(&SecureContext {
+                window: &window_info,
+                key,
+                text: text.as_deref(),
+            });
+            if let Some(rule) = flagged {
+                if !STATUS.lock().secure_input {
+                    eprintln!("Secure input detected ({}), keys are not recorded", rule);
                 }
             }
-        });
-
-        let callback = move |event: Event| {
-            match event.event_type {
-                EventType::KeyPress(key) => {
-                    // Track shift state
-                    if matches!(key, Key::ShiftLeft | Key::ShiftRight) {
-                        *shift_clone.lock() = true;
-                        return;
-                    }
+            flagged.is_some()
+        } else {
+            STATUS.lock().secure_input
+        };
+        note_secure_input(secure, counts);
+        if secure {
+            return;
+        }
 
-                    // Get current window info
-                    let window_info = get_active_window();
-                    if window_info.is_none() {
-                        return;
-                    }
-                    let window_info = window_info.unwrap();
+        let mut buf = self.buffer.lock();
+        let now = self.clock.elapsed();
 
-                    // Check if app is excluded
-                    if is_app_excluded(&window_info.app_name) {
-                        return;
-                    }
+        if is_press {
+            // A preedit that was cleared without a commit was abandoned
+            if !buf.composing {
+                buf.composition_start = None;
+            }
+            self.switch_buffer(&mut buf, window_info);
+        } else if buf.app_name != window_info.app_name {
+            // Released after focus moved on; the press went with the old record
+            return;
+        }
 
-                    let mut buf = buffer_clone.lock();
-                    let now = Instant::now();
+        if keep_stroke {
+            buf.key_events.push(KeyStroke {
+                input_id: None,
+                time_ms: event
+                    .time
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_millis() as i64),
+                action: event.action,
+                key,
+                modifiers: modifiers.bits(),
+            });
+            buf.last_input_time = now;
+        }
 
-                    // Check if we need to start a new buffer
-                    let app_changed = !buf.is_empty() && buf.app_name != window_info.app_name;
-                    let timeout = !buf.is_empty() && buf.last_input_time.elapsed() > Duration::from_millis(500);
+        if is_modifier || !is_press {
+            return;
+        }
 
-                    if app_changed || timeout {
-                        save_buffer(&mut buf);
-                    }
+        buf.last_text_start = None;
+        buf.tally(key, modifiers);
 
-                    // Handle special keys
-                    match key {
-                        Key::Return => {
-                            buf.content.push_str("[Enter]");
-                            buf.key_count += 1;
-                        }
-                        Key::Tab => {
-                            buf.content.push_str("[Tab]");
-                            buf.key_count += 1;
-                        }
-                        Key::Backspace => {
-                            buf.content.push_str("[Backspace]");
-                            buf.key_count += 1;
-                        }
-                        Key::Delete => {
-                            buf.content.push_str("[Delete]");
-                            buf.key_count += 1;
-                        }
-                        Key::Escape => {
-                            buf.content.push_str("[Esc]");
-                            buf.key_count += 1;
-                        }
-                        Key::UpArrow => {
-                            buf.content.push_str("[Up]");
-                            buf.key_count += 1;
-                        }
-                        Key::DownArrow => {
-                            buf.content.push_str("[Down]");
-                            buf.key_count += 1;
-                        }
-                        Key::LeftArrow => {
-                            buf.content.push_str("[Left]");
-                            buf.key_count += 1;
-                        }
-                        Key::RightArrow => {
-                            buf.content.push_str("[Right]");
-                            buf.key_count += 1;
-                        }
-                        _ => {
-                            let shift = *shift_pressed.lock();
-                            if let Some(c) = key_to_char(key, shift) {
-                                buf.content.push(c);
-                                buf.key_count += 1;
-                            }
-                        }
-                    }
+        // Ctrl/Alt/Meta combinations are shortcuts rather than text
+        if let Some(chord) = modifiers.chord(key) {
+            buf.content.push_str(&chord);
+            buf.shortcuts.push(chord);
+            buf.key_count += 1;
+            buf.last_input_time = now;
+            return;
+        }
 
-                    // Update buffer state
-                    if buf.app_name.is_empty() {
-                        buf.app_name = window_info.app_name;
-                        buf.window_title = window_info.window_title;
+        // Handle special keys
+        match key {
+            Key::Return => {
+                buf.content.push_str("[Enter]");
+                buf.key_count += 1;
+                buf.edit(Edit::Insert('\n'));
+            }
+            Key::Tab => {
+                buf.content.push_str("[Tab]");
+                buf.key_count += 1;
+                buf.edit(Edit::Insert('\t'));
+            }
+            Key::Backspace => {
+                buf.content.push_str("[Backspace]");
+                buf.key_count += 1;
+                buf.edit(Edit::Backspace);
+            }
+            Key::Delete => {
+                buf.content.push_str("[Delete]");
+                buf.key_count += 1;
+                buf.edit(Edit::Delete);
+            }
+            Key::Escape => {
+                buf.content.push_str("[Esc]");
+                buf.key_count += 1;
+            }
+            Key::UpArrow => {
+                buf.content.push_str("[Up]");
+                buf.key_count += 1;
+                buf.edit(Edit::Up);
+            }
+            Key::DownArrow => {
+                buf.content.push_str("[Down]");
+                buf.key_count += 1;
+                buf.edit(Edit::Down);
+            }
+            Key::LeftArrow => {
+                buf.content.push_str("[Left]");
+                buf.key_count += 1;
+                buf.edit(Edit::Left);
+            }
+            Key::RightArrow => {
+                buf.content.push_str("[Right]");
+                buf.key_count += 1;
+                buf.edit(Edit::Right);
+            }
+            _ => {
+                if let Some(text) = text {
+                    buf.last_text_start = Some(buf.content.len());
+                    buf.content.push_str(&text);
+                    buf.key_count += 1;
+                    if !buf.composing {
+                        buf.editor.insert_str(&text);
                     }
-                    buf.last_input_time = now;
                 }
-                EventType::KeyRelease(key) => {
-                    if matches!(key, Key::ShiftLeft | Key::ShiftRight) {
-                        *shift_clone.lock() = false;
-                    }
+            }
+        }
+
+        buf.touch(now);
+        buf.last_input_time = now;
+    }
+
+    /// Fold input method output into the buffer. A commit replaces the
+    /// keystrokes typed since its preedit appeared with the text it produced.
+    fn handle_ime_event(&self, event: ImeEvent, active_window: impl FnOnce() -> Option<WindowInfo>) {
+        let mut buf = self.buffer.lock();
+
+        let text = match event {
+            ImeEvent::Preedit(composing) => {
+                if composing && buf.composition_start.is_none() {
+                    // The key that opened the preedit has already been buffered,
+                    // but the application never received it
+                    buf.composition_start = Some(buf.last_text_start.unwrap_or(buf.content.len()));
+                    let opener = buf.last_text_len();
+                    buf.editor.remove_before(opener);
                 }
-                _ => {}
+                buf.composing = composing;
+                return;
+            }
+            ImeEvent::Commit(text) => text,
+        };
+
+        let window_info = match active_window() {
+            Some(window_info) => window_info,
+            None => return,
+        };
+        if is_window_excluded(&window_info, self.clock.now()) {
+            return;
+        }
+
+        // Committed text goes through the secure-context rules like a key press
+        let flagged = is_secure_context(&SecureContext {
+            window: &window_info,
+            key: Key::Unknown(0),
+            text: Some(&text),
+        });
+        if let Some(rule) = flagged {
+            if !STATUS.lock().secure_input {
+                eprintln!("Secure input detected ({}), keys are not recorded", rule);
             }
+        }
+        let secure = flagged.is_some();
+        note_secure_input(secure, true);
+        if secure {
+            buf.composition_start = None;
+            buf.composing = false;
+            return;
+        }
+
+        let now = self.clock.elapsed();
+        self.switch_buffer(&mut buf, window_info);
+
+        let replace_from = match buf.composition_start.take() {
+            Some(start) => Some(start),
+            None if now.saturating_sub(buf.last_input_time) < DIRECT_COMMIT_WINDOW => {
+                let replaced = buf.last_text_len();
+                buf.editor.remove_before(replaced);
+                buf.last_text_start
+            }
+            None => None,
         };
+        if let Some(start) = replace_from {
+            buf.content.truncate(start);
+        }
+
+        buf.content.push_str(&text);
+        buf.editor.insert_str(&text);
+        buf.composed = true;
+        buf.composing = false;
+        buf.last_text_start = None;
+        buf.touch(now);
+        buf.last_input_time = now;
+    }
+
+    /// Save the buffer if input moved to another app or went idle, and claim
+    /// it for `window_info`.
+    fn switch_buffer(&self, buf: &mut InputBuffer, window_info: WindowInfo) {
+        let app_changed = !buf.is_empty() && buf.app_name != window_info.app_name;
+        let timeout = self.is_idle(buf);
+
+        if app_changed || timeout {
+            self.save_buffer(buf);
+        }
+
+        if buf.app_name.is_empty() {
+            buf.app_name = window_info.app_name;
+            buf.window_title = window_info.window_title;
+        }
+    }
+}
+
+pub fn start_keyboard_listener() -> Result<(), String> {
+    start_listener(crate::input::default_source())
+}
+
+/// Run the merge pipeline over events from `source` on a background thread.
+/// Only one listener can run; later calls are no-ops. Key presses are dropped
+/// unless recording is on.
+///
+/// The listener runs until its source gives out. rdev has no way to remove
+/// its hook once `listen` is running, so stopping recording cannot uninstall
+/// it; `RecordingStatus::listener_running` tells the UI as much.
+pub fn start_listener(source: Box<dyn InputSource>) -> Result<(), String> {
+    let db = DB
+        .get()
+        .cloned()
+        .ok_or_else(|| "Database not initialized".to_string())?;
+
+    let pipeline = Arc::new(Pipeline::new(db, Arc::new(SystemClock::new())));
+    if PIPELINE.set(pipeline.clone()).is_err() {
+        return Ok(());
+    }
+
+    thread::spawn(move || {
+        // Flush timer thread
+        let pipeline_for_timer = pipeline.clone();
+        thread::spawn(move || loop {
+            thread::sleep(FLUSH_TICK);
+            pipeline_for_timer.flush_if_idle();
+        });
+
+        // Foreground sampler thread
+        let pipeline_for_focus = pipeline.clone();
+        thread::spawn(move || loop {
+            thread::sleep(activity::SAMPLE_INTERVAL);
+            let recording = is_recording();
+            pipeline_for_focus.sample_focus(|| if recording { get_active_window() } else { None });
+        });
+
+        let pipeline_for_ime = pipeline.clone();
+        let ime_sink = Box::new(move |event: ImeEvent| {
+            // Preedit state is followed while not recording, like modifiers
+            if matches!(event, ImeEvent::Commit(_)) && !is_recording() {
+                return;
+            }
+            pipeline_for_ime.handle_ime_event(event, get_active_window);
+        });
+        if let Err(e) = crate::ime::start_monitor(ime_sink) {
+            eprintln!("Input method text will not be captured: {}", e);
+        }
+
+        let source_name = source.name();
+        let sink = Box::new(move |event: KeyEvent| {
+            // Modifiers are still tracked while not recording, so they are right on resume
+            let is_modifier = KeyTranslator::is_modifier(event.key) || Modifiers::is_modifier(event.key);
+            if !is_modifier && !is_recording() {
+                return;
+            }
+            pipeline.handle_key_event(&event, get_active_window);
+        });
+
+        set_listener_running(true);
+        if let Err(e) = source.run(sink) {
+            eprintln!("Error listening to keyboard events ({}): {}", source_name, e);
+        }
+        set_listener_running(false);
+    });
+
+    Ok(())
+}
+
+fn set_listener_running(running: bool) {
+    let status = {
+        let mut current = STATUS.lock();
+        current.listener_running = running;
+        current.clone()
+    };
+    notify_status(&status);
+}
+
+pub fn on_status_change(listener: impl Fn(&RecordingStatus) + Send + Sync + 'static) {
+    let _ = STATUS_LISTENER.set(Box::new(listener));
+}
+
+pub fn recording_status() -> RecordingStatus {
+    STATUS.lock().clone()
+}
+
+fn is_recording() -> bool {
+    STATUS.lock().state == RecordingState::Recording
+}
+
+/// Switch state, saving whatever is still buffered when recording stops or
+/// pauses, then notify the status listener.
+fn transition(state: RecordingState, paused_until: Option<DateTime<Utc>>) -> RecordingStatus {
+    let status = {
+        let mut current = STATUS.lock();
+        current.state = state;
+        current.paused_until = paused_until;
+        current.clone()
+    };
 
-        if let Err(e) = listen(callback) {
-            eprintln!("Error listening to keyboard events: {:?}", e);
+    if state != RecordingState::Recording {
+        if let Some(pipeline) = PIPELINE.get() {
+            pipeline.flush();
+            // So the records are there when the UI reloads on this change
+            pipeline.writer.flush();
+        }
+    }
+
+    notify_status(&status);
+    status
+}
+
+pub fn writer_metrics() -> WriterMetrics {
+    PIPELINE.get().map(|p| p.writer.metrics()).unwrap_or_default()
+}
+
+/// Save what is still buffered and wait for it to reach the database. Called
+/// as the app exits.
+pub fn shutdown() {
+    if let Some(pipeline) = PIPELINE.get() {
+        pipeline.flush();
+        pipeline.writer.shutdown();
+    }
+}
+
+fn notify_status(status: &RecordingStatus) {
+    if let Some(listener) = STATUS_LISTENER.get() {
+        listener(status);
+    }
+}
+
+/// Start (or resume) recording, launching the listener on first use.
+pub fn start_recording() -> Result<RecordingStatus, String> {
+    start_keyboard_listener()?;
+    Ok(transition(RecordingState::Recording, None))
+}
+
+/// Stop recording. This is a soft stop: the input hook stays installed until
+/// the app exits and keys keep arriving, but they are dropped on arrival.
+/// Only modifier and input method state is followed, so it is right when
+/// recording resumes.
+pub fn stop_recording() -> RecordingStatus {
+    transition(RecordingState::Stopped, None)
+}
+
+/// Pause for `duration`, then resume on its own unless the state was changed
+/// in the meantime.
+pub fn pause_recording(duration: Duration) -> Result<RecordingStatus, String> {
+    if STATUS.lock().state == RecordingState::Stopped {
+        return Err("Recording is not running".to_string());
+    }
+
+    let until = Utc::now() + chrono::Duration::from_std(duration).map_err(|e| e.to_string())?;
+    let status = transition(RecordingState::Paused, Some(until));
+
+    thread::spawn(move || {
+        thread::sleep(duration);
+        let still_paused = {
+            let current = STATUS.lock();
+            current.state == RecordingState::Paused && current.paused_until == Some(until)
+        };
+        if still_paused {
+            transition(RecordingState::Recording, None);
         }
     });
+
+    Ok(status)
+}
+
+/// Where the replay harness starts its clock: a fixed point, so a script
+/// always replays to the same records
+pub const REPLAY_ORIGIN: DateTime<Utc> = DateTime::<Utc>::UNIX_EPOCH;
+
+/// Feed a recorded script through the merge pipeline on virtual time and save
+/// the resulting records to `db`, stamped relative to `origin`. The flush timer
+/// is simulated at the same 100 ms tick as the live listener, so merging and
+/// timeouts behave exactly as they would for real typing under the current
+/// `ListenerConfig`.
+///
+/// An event that names an `app` switches the focused window (taking its
+/// `title` along); otherwise the previous focus is kept.
+pub fn replay(db: Arc<Database>, events: &[RecordedEvent], origin: DateTime<Utc>) {
+    let clock = Arc::new(VirtualClock::new(origin));
+    let pipeline = Pipeline::new(db, clock.clone());
+
+    let mut window = WindowInfo {
+        app_name: "Unknown".to_string(),
+        window_title: None,
+        exe_path: None,
+    };
+    let mut tick = Duration::ZERO;
+
+    for event in events {
+        let at = Duration::from_millis(event.at_ms);
+
+        // Fire every timer tick that would have happened before this event
+        while tick + FLUSH_TICK <= at {
+            tick += FLUSH_TICK;
+            clock.set(tick);
+            pipeline.flush_if_idle();
+            if tick.as_millis().is_multiple_of(activity::SAMPLE_INTERVAL.as_millis()) {
+                pipeline.sample_focus(|| Some(window.clone()));
+            }
+        }
+        clock.set(at);
+
+        if let Some(ref app) = event.app {
+            window = WindowInfo {
+                app_name: app.clone(),
+                window_title: event.title.clone(),
+                exe_path: None,
+            };
+        }
+
+        let key_event = KeyEvent {
+            action: event.action,
+            key: event.key,
+            time: SystemTime::from(origin) + at,
+            name: event.name.clone(),
+        };
+        pipeline.handle_key_event(&key_event, || Some(window.clone()));
+    }
+
+    pipeline.flush();
+    pipeline.writer.shutdown();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::SearchFilter;
+
+    fn window(app_name: &str, title: &str) -> WindowInfo {
+        WindowInfo {
+            app_name: app_name.to_string(),
+            window_title: Some(title.to_string()),
+            exe_path: None,
+        }
+    }
+
+    fn pipeline() -> (Arc<Database>, Arc<VirtualClock>, Pipeline) {
+        let db = Arc::new(Database::in_memory().unwrap());
+        let clock = Arc::new(VirtualClock::new(DateTime::<Utc>::UNIX_EPOCH));
+        (db.clone(), clock.clone(), Pipeline::new(db, clock))
+    }
+
+    fn type_text(pipeline: &Pipeline, clock: &VirtualClock, at: Duration, text: &str) {
+        clock.set(at);
+        for c in text.chars() {
+            let key = match c {
+                'n' => Key::KeyN,
+                'i' => Key::KeyI,
+                _ => unreachable!(),
+            };
+            let event = KeyEvent {
+                action: KeyAction::Press,
+                key,
+                time: SystemTime::UNIX_EPOCH + at,
+                name: Some(c.to_string()),
+            };
+            pipeline.handle_key_event(&event, || Some(window("Editor", "notes.txt")));
+        }
+    }
+
+    /// Records saved by idle flushing alone
+    fn saved_when_idle(db: &Database, pipeline: &Pipeline, clock: &VirtualClock, at: Duration) -> Vec<InputRecord> {
+        clock.set(at);
+        pipeline.flush_if_idle();
+        pipeline.writer.flush();
+        db.get_records(&SearchFilter::default()).unwrap()
+    }
+
+    fn saved(db: &Database, pipeline: &Pipeline) -> Vec<InputRecord> {
+        pipeline.flush();
+        pipeline.writer.flush();
+        db.get_records(&SearchFilter::default()).unwrap()
+    }
+
+    /// Replay a recorded script from `fixtures/replay`
+    fn replay_fixture(script: &str) -> Vec<InputRecord> {
+        let events = RecordedEvent::parse_lines(script.as_bytes()).unwrap();
+        let db = Arc::new(Database::in_memory().unwrap());
+        replay(db.clone(), &events, REPLAY_ORIGIN);
+        let mut records = db.get_records(&SearchFilter::default()).unwrap();
+        records.sort_by_key(|r| r.id);
+        records
+    }
+
+    #[test]
+    fn replays_editing_fixture() {
+        let records = replay_fixture(include_str!("../fixtures/replay/editing.jsonl"));
+        let summary: Vec<_> = records
+            .iter()
+            .map(|r| {
+                (
+                    r.timestamp.to_rfc3339(),
+                    r.app_name.as_str(),
+                    r.window_title.as_deref(),
+                    r.content.as_str(),
+                    r.final_text.as_str(),
+                    r.key_count,
+                    r.duration_ms,
+                )
+            })
+            .collect();
+        // Each record is stamped when it is saved, once input goes idle
+        let editor = Some("notes.txt");
+        let browser = Some("New Tab");
+        assert_eq!(
+            summary,
+            [
+                (
+                    "1970-01-01T00:00:01.400+00:00".to_string(),
+                    "Editor",
+                    editor,
+                    "Hellp[Backspace]o w",
+                    "Hello w",
+                    9,
+                    Some(820)
+                ),
+                ("1970-01-01T00:00:02.100+00:00".to_string(), "Editor", editor, "[Ctrl+S]", "", 1, None),
+                (
+                    "1970-01-01T00:00:05.100+00:00".to_string(),
+                    "Browser",
+                    browser,
+                    "rust[Enter]",
+                    "rust\n",
+                    5,
+                    Some(500)
+                ),
+                ("1970-01-01T00:10:04.600+00:00".to_string(), "Browser", browser, "ok", "ok", 2, Some(100)),
+            ]
+        );
+    }
+
+    #[test]
+    fn ime_commit_into_secure_context_is_dropped() {
+        let (db, _, pipeline) = pipeline();
+        pipeline.handle_ime_event(ImeEvent::Commit("口令".into()), || Some(window("pinentry-qt", "")));
+        pipeline.handle_ime_event(ImeEvent::Commit("密码".into()), || Some(window("Terminal", "Password:")));
+        pipeline.handle_ime_event(ImeEvent::Commit("你好".into()), || Some(window("Editor", "notes.txt")));
+
+        let records = saved(&db, &pipeline);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].content, "你好");
+    }
+
+    #[test]
+    fn keys_typed_into_own_window_are_dropped() {
+        let (db, clock, pipeline) = pipeline();
+        let exe = std::env::current_exe().unwrap();
+        let own = window(&exe.file_name().unwrap().to_string_lossy(), "Arkinput - 输入记录");
+        clock.set(Duration::from_millis(100));
+        for key in [Key::KeyN, Key::KeyI] {
+            let event = KeyEvent {
+                action: KeyAction::Press,
+                key,
+                time: SystemTime::UNIX_EPOCH,
+                name: None,
+            };
+            pipeline.handle_key_event(&event, || Some(own.clone()));
+        }
+        type_text(&pipeline, &clock, Duration::from_millis(200), "n");
+
+        let records = saved(&db, &pipeline);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].app_name, "Editor");
+        assert_eq!(records[0].content, "n");
+    }
+
+    #[test]
+    fn cleared_preedit_does_not_hold_the_buffer() {
+        let (db, clock, pipeline) = pipeline();
+        type_text(&pipeline, &clock, Duration::from_millis(100), "n");
+        pipeline.handle_ime_event(ImeEvent::Preedit(true), || None);
+        type_text(&pipeline, &clock, Duration::from_millis(200), "i");
+        assert!(saved_when_idle(&db, &pipeline, &clock, Duration::from_secs(5)).is_empty());
+
+        pipeline.handle_ime_event(ImeEvent::Preedit(false), || None);
+        let records = saved_when_idle(&db, &pipeline, &clock, Duration::from_secs(6));
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].content, "ni");
+        // The application never received the keys typed into the preedit
+        assert_eq!(records[0].final_text, "");
+    }
+
+    #[test]
+    fn open_preedit_times_out() {
+        let (db, clock, pipeline) = pipeline();
+        type_text(&pipeline, &clock, Duration::from_millis(100), "n");
+        pipeline.handle_ime_event(ImeEvent::Preedit(true), || None);
+        type_text(&pipeline, &clock, Duration::from_millis(200), "i");
+
+        let before = Duration::from_millis(200) + COMPOSITION_TIMEOUT;
+        assert!(saved_when_idle(&db, &pipeline, &clock, before).is_empty());
+        let after = before + Duration::from_millis(1);
+        assert_eq!(saved_when_idle(&db, &pipeline, &clock, after).len(), 1);
+
+        // The next keys reach the application again
+        type_text(&pipeline, &clock, after + Duration::from_secs(1), "in");
+        let records = saved(&db, &pipeline);
+        assert_eq!(records.len(), 2);
+        assert!(records.iter().any(|r| r.final_text == "in"));
+    }
 }
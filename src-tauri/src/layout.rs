@@ -0,0 +1,487 @@
+use rdev::Key;
+
+use crate::input::{KeyAction, KeyEvent, Modifiers};
+
+/// Keyboard layout used when the input source cannot tell which text a key
+/// produced (evdev, replays, dead keys on X11).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    #[default]
+    Us,
+    German,
+    French,
+}
+
+impl Layout {
+    /// The layout a setting names, or `None` for "auto": follow the layout
+    /// the system has active
+    pub fn from_setting(name: &str) -> Option<Self> {
+        match name.trim().to_ascii_lowercase().as_str() {
+            "" | "auto" => None,
+            name => Some(Self::from_name(name)),
+        }
+    }
+
+    /// Layouts without a table of their own fall back to US
+    pub fn from_name(name: &str) -> Self {
+        match name.to_ascii_lowercase().as_str() {
+            "de" | "german" => Layout::German,
+            "fr" | "french" => Layout::French,
+            _ => Layout::Us,
+        }
+    }
+
+    fn table(self) -> &'static [Mapping] {
+        match self {
+            Layout::Us => US,
+            Layout::German => DE,
+            Layout::French => FR,
+        }
+    }
+
+    fn is_dead(self, c: char) -> bool {
+        let dead: &[char] = match self {
+            Layout::Us => &[],
+            Layout::German => &['^', '´', '`'],
+            Layout::French => &['^', '¨'],
+        };
+        dead.contains(&c)
+    }
+}
+
+/// What a physical key produces at each shift level.
+struct Mapping {
+    key: Key,
+    base: char,
+    shift: char,
+    altgr: Option<char>,
+}
+
+const fn map(key: Key, base: char, shift: char, altgr: Option<char>) -> Mapping {
+    Mapping {
+        key,
+        base,
+        shift,
+        altgr,
+    }
+}
+
+/// Turns key presses into text, tracking Shift, AltGr, Caps Lock and pending
+/// dead keys across events.
+#[derive(Debug, Default)]
+pub struct KeyTranslator {
+    /// Shift and AltGr, each Shift key on its own
+    held: Modifiers,
+    caps_lock: bool,
+    pending_dead: Option<char>,
+}
+
+impl KeyTranslator {
+    pub fn is_modifier(key: Key) -> bool {
+        matches!(
+            key,
+            Key::ShiftLeft | Key::ShiftRight | Key::AltGr | Key::CapsLock
+        )
+    }
+
+    /// Update modifier state. Returns true when the event was a modifier key.
+    pub fn track_modifiers(&mut self, event: &KeyEvent) -> bool {
+        match event.key {
+            Key::ShiftLeft | Key::ShiftRight | Key::AltGr => {
+                self.held.update(event);
+            }
+            Key::CapsLock => {
+                if event.action == KeyAction::Press {
+                    self.caps_lock = !self.caps_lock;
+                }
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    /// Text produced by a key press. The OS-provided `name` wins when it is
+    /// printable, since it already reflects the active layout and modifiers;
+    /// otherwise `layout` is asked for the layout to consult. Returns `None`
+    /// for keys that produce nothing (yet), such as a dead key waiting for its
+    /// base letter.
+    pub fn translate(
+        &mut self,
+        layout: impl FnOnce() -> Layout,
+        key: Key,
+        name: Option<&str>,
+    ) -> Option<String> {
+        let text = match name.filter(|n| is_printable(n)) {
+            Some(text) => text.to_string(),
+            None => {
+                let layout = layout();
+                let c = self.lookup(layout, key)?;
+                if layout.is_dead(c) {
+                    // Pressing a dead key twice yields both accents
+                    if let Some(previous) = self.pending_dead.take() {
+                        return Some(format!("{}{}", previous, c));
+                    }
+                    self.pending_dead = Some(c);
+                    return None;
+                }
+                c.to_string()
+            }
+        };
+
+        match self.pending_dead.take() {
+            Some(dead) => Some(compose(dead, &text)),
+            None => Some(text),
+        }
+    }
+
+    fn lookup(&self, layout: Layout, key: Key) -> Option<char> {
+        if key == Key::Space {
+            return Some(' ');
+        }
+
+        let mapping = layout.table().iter().find(|m| m.key == key)?;
+        if self.held.altgr() {
+            return mapping.altgr;
+        }
+
+        // Caps Lock only affects letters
+        let is_letter = mapping.base.is_alphabetic() && mapping.base.to_uppercase().eq([mapping.shift]);
+        let shifted = self.held.shift() ^ (self.caps_lock && is_letter);
+        Some(if shifted { mapping.shift } else { mapping.base })
+    }
+}
+
+fn is_printable(text: &str) -> bool {
+    !text.is_empty() && !text.chars().any(char::is_control)
+}
+
+fn compose(dead: char, text: &str) -> String {
+    let mut chars = text.chars();
+    if let (Some(base), None) = (chars.next(), chars.next()) {
+        if base == ' ' {
+            return dead.to_string();
+        }
+
+        let (from, to) = match dead {
+            '^' => ("aeiouAEIOU", "âêîôûÂÊÎÔÛ"),
+            '´' => ("aeiouyAEIOUY", "áéíóúýÁÉÍÓÚÝ"),
+            '`' => ("aeiouAEIOU", "àèìòùÀÈÌÒÙ"),
+            '¨' => ("aeiouyAEIOU", "äëïöüÿÄËÏÖÜ"),
+            _ => ("", ""),
+        };
+        if let Some(i) = from.chars().position(|c| c == base) {
+            if let Some(composed) = to.chars().nth(i) {
+                return composed.to_string();
+            }
+        }
+    }
+
+    // No composition: the accent is emitted as-is, followed by the text
+    format!("{}{}", dead, text)
+}
+
+#[rustfmt::skip]
+const US: &[Mapping] = &[
+    map(Key::BackQuote, '`', '~', None),
+    map(Key::Num1, '1', '!', None),
+    map(Key::Num2, '2', '@', None),
+    map(Key::Num3, '3', '#', None),
+    map(Key::Num4, '4', '$', None),
+    map(Key::Num5, '5', '%', None),
+    map(Key::Num6, '6', '^', None),
+    map(Key::Num7, '7', '&', None),
+    map(Key::Num8, '8', '*', None),
+    map(Key::Num9, '9', '(', None),
+    map(Key::Num0, '0', ')', None),
+    map(Key::Minus, '-', '_', None),
+    map(Key::Equal, '=', '+', None),
+    map(Key::KeyQ, 'q', 'Q', None),
+    map(Key::KeyW, 'w', 'W', None),
+    map(Key::KeyE, 'e', 'E', None),
+    map(Key::KeyR, 'r', 'R', None),
+    map(Key::KeyT, 't', 'T', None),
+    map(Key::KeyY, 'y', 'Y', None),
+    map(Key::KeyU, 'u', 'U', None),
+    map(Key::KeyI, 'i', 'I', None),
+    map(Key::KeyO, 'o', 'O', None),
+    map(Key::KeyP, 'p', 'P', None),
+    map(Key::LeftBracket, '[', '{', None),
+    map(Key::RightBracket, ']', '}', None),
+    map(Key::KeyA, 'a', 'A', None),
+    map(Key::KeyS, 's', 'S', None),
+    map(Key::KeyD, 'd', 'D', None),
+    map(Key::KeyF, 'f', 'F', None),
+    map(Key::KeyG, 'g', 'G', None),
+    map(Key::KeyH, 'h', 'H', None),
+    map(Key::KeyJ, 'j', 'J', None),
+    map(Key::KeyK, 'k', 'K', None),
+    map(Key::KeyL, 'l', 'L', None),
+    map(Key::SemiColon, ';', ':', None),
+    map(Key::Quote, '\'', '"', None),
+    map(Key::BackSlash, '\\', '|', None),
+    map(Key::KeyZ, 'z', 'Z', None),
+    map(Key::KeyX, 'x', 'X', None),
+    map(Key::KeyC, 'c', 'C', None),
+    map(Key::KeyV, 'v', 'V', None),
+    map(Key::KeyB, 'b', 'B', None),
+    map(Key::KeyN, 'n', 'N', None),
+    map(Key::KeyM, 'm', 'M', None),
+    map(Key::Comma, ',', '<', None),
+    map(Key::Dot, '.', '>', None),
+    map(Key::Slash, '/', '?', None),
+];
+
+#[rustfmt::skip]
+const DE: &[Mapping] = &[
+    map(Key::BackQuote, '^', '°', None),
+    map(Key::Num1, '1', '!', None),
+    map(Key::Num2, '2', '"', Some('²')),
+    map(Key::Num3, '3', '§', Some('³')),
+    map(Key::Num4, '4', '$', None),
+    map(Key::Num5, '5', '%', None),
+    map(Key::Num6, '6', '&', None),
+    map(Key::Num7, '7', '/', Some('{')),
+    map(Key::Num8, '8', '(', Some('[')),
+    map(Key::Num9, '9', ')', Some(']')),
+    map(Key::Num0, '0', '=', Some('}')),
+    map(Key::Minus, 'ß', '?', Some('\\')),
+    map(Key::Equal, '´', '`', None),
+    map(Key::KeyQ, 'q', 'Q', Some('@')),
+    map(Key::KeyW, 'w', 'W', None),
+    map(Key::KeyE, 'e', 'E', Some('€')),
+    map(Key::KeyR, 'r', 'R', None),
+    map(Key::KeyT, 't', 'T', None),
+    map(Key::KeyY, 'z', 'Z', None),
+    map(Key::KeyU, 'u', 'U', None),
+    map(Key::KeyI, 'i', 'I', None),
+    map(Key::KeyO, 'o', 'O', None),
+    map(Key::KeyP, 'p', 'P', None),
+    map(Key::LeftBracket, 'ü', 'Ü', None),
+    map(Key::RightBracket, '+', '*', Some('~')),
+    map(Key::KeyA, 'a', 'A', None),
+    map(Key::KeyS, 's', 'S', None),
+    map(Key::KeyD, 'd', 'D', None),
+    map(Key::KeyF, 'f', 'F', None),
+    map(Key::KeyG, 'g', 'G', None),
+    map(Key::KeyH, 'h', 'H', None),
+    map(Key::KeyJ, 'j', 'J', None),
+    map(Key::KeyK, 'k', 'K', None),
+    map(Key::KeyL, 'l', 'L', None),
+    map(Key::SemiColon, 'ö', 'Ö', None),
+    map(Key::Quote, 'ä', 'Ä', None),
+    map(Key::BackSlash, '#', '\'', None),
+    map(Key::IntlBackslash, '<', '>', Some('|')),
+    map(Key::KeyZ, 'y', 'Y', None),
+    map(Key::KeyX, 'x', 'X', None),
+    map(Key::KeyC, 'c', 'C', None),
+    map(Key::KeyV, 'v', 'V', None),
+    map(Key::KeyB, 'b', 'B', None),
+    map(Key::KeyN, 'n', 'N', None),
+    map(Key::KeyM, 'm', 'M', Some('µ')),
+    map(Key::Comma, ',', ';', None),
+    map(Key::Dot, '.', ':', None),
+    map(Key::Slash, '-', '_', None),
+];
+
+#[rustfmt::skip]
+const FR: &[Mapping] = &[
+    map(Key::BackQuote, '²', '²', None),
+    map(Key::Num1, '&', '1', None),
+    map(Key::Num2, 'é', '2', Some('~')),
+    map(Key::Num3, '"', '3', Some('#')),
+    map(Key::Num4, '\'', '4', Some('{')),
+    map(Key::Num5, '(', '5', Some('[')),
+    map(Key::Num6, '-', '6', Some('|')),
+    map(Key::Num7, 'è', '7', Some('`')),
+    map(Key::Num8, '_', '8', Some('\\')),
+    map(Key::Num9, 'ç', '9', Some('^')),
+    map(Key::Num0, 'à', '0', Some('@')),
+    map(Key::Minus, ')', '°', Some(']')),
+    map(Key::Equal, '=', '+', Some('}')),
+    map(Key::KeyQ, 'a', 'A', None),
+    map(Key::KeyW, 'z', 'Z', None),
+    map(Key::KeyE, 'e', 'E', Some('€')),
+    map(Key::KeyR, 'r', 'R', None),
+    map(Key::KeyT, 't', 'T', None),
+    map(Key::KeyY, 'y', 'Y', None),
+    map(Key::KeyU, 'u', 'U', None),
+    map(Key::KeyI, 'i', 'I', None),
+    map(Key::KeyO, 'o', 'O', None),
+    map(Key::KeyP, 'p', 'P', None),
+    map(Key::LeftBracket, '^', '¨', None),
+    map(Key::RightBracket, '$', '£', Some('¤')),
+    map(Key::KeyA, 'q', 'Q', None),
+    map(Key::KeyS, 's', 'S', None),
+    map(Key::KeyD, 'd', 'D', None),
+    map(Key::KeyF, 'f', 'F', None),
+    map(Key::KeyG, 'g', 'G', None),
+    map(Key::KeyH, 'h', 'H', None),
+    map(Key::KeyJ, 'j', 'J', None),
+    map(Key::KeyK, 'k', 'K', None),
+    map(Key::KeyL, 'l', 'L', None),
+    map(Key::SemiColon, 'm', 'M', None),
+    map(Key::Quote, 'ù', '%', None),
+    map(Key::BackSlash, '*', 'µ', None),
+    map(Key::IntlBackslash, '<', '>', None),
+    map(Key::KeyZ, 'w', 'W', None),
+    map(Key::KeyX, 'x', 'X', None),
+    map(Key::KeyC, 'c', 'C', None),
+    map(Key::KeyV, 'v', 'V', None),
+    map(Key::KeyB, 'b', 'B', None),
+    map(Key::KeyN, 'n', 'N', None),
+    map(Key::KeyM, ',', '?', None),
+    map(Key::Comma, ';', '.', None),
+    map(Key::Dot, ':', '/', None),
+    map(Key::Slash, '!', '§', None),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    /// Type `keys` (modifiers as press/release pairs) and collect the text
+    fn typed(layout: Layout, keys: &[(KeyAction, Key)]) -> String {
+        let mut translator = KeyTranslator::default();
+        let mut text = String::new();
+        for &(action, key) in keys {
+            let event = KeyEvent {
+                action,
+                key,
+                time: SystemTime::UNIX_EPOCH,
+                name: None,
+            };
+            if translator.track_modifiers(&event) || action == KeyAction::Release {
+                continue;
+            }
+            if let Some(t) = translator.translate(|| layout, key, None) {
+                text.push_str(&t);
+            }
+        }
+        text
+    }
+
+    /// A layout, the keys pressed and released, and the text they type
+    type Case<'a> = (Layout, &'a [(KeyAction, Key)], &'a str);
+
+    fn press(key: Key) -> (KeyAction, Key) {
+        (KeyAction::Press, key)
+    }
+
+    fn release(key: Key) -> (KeyAction, Key) {
+        (KeyAction::Release, key)
+    }
+
+    #[test]
+    fn shift_and_caps_lock() {
+        use Key::*;
+        #[rustfmt::skip]
+        let cases: &[Case] = &[
+            (Layout::Us, &[press(KeyA), press(Num1), press(Slash)], "a1/"),
+            (Layout::Us, &[press(ShiftLeft), press(KeyA), press(Num1), release(ShiftLeft), press(KeyA)], "A!a"),
+            (Layout::German, &[press(KeyY), press(KeyZ), press(Minus), press(SemiColon)], "zyßö"),
+            (Layout::German, &[press(ShiftRight), press(Num7), press(Quote), release(ShiftRight)], "/Ä"),
+            // Releasing one of two held Shift keys leaves the other in effect
+            (Layout::Us, &[press(ShiftLeft), press(ShiftRight), release(ShiftLeft), press(KeyA), release(ShiftRight), press(KeyA)], "Aa"),
+            (Layout::French, &[press(KeyQ), press(Num2), press(KeyM)], "aé,"),
+            (Layout::French, &[press(ShiftLeft), press(Num2), press(KeyM), release(ShiftLeft)], "2?"),
+            // Caps Lock shifts letters only, and Shift undoes it
+            (Layout::Us, &[press(CapsLock), press(KeyA), press(Num1)], "A1"),
+            (Layout::German, &[press(CapsLock), press(Quote), press(ShiftLeft), press(KeyA)], "Äa"),
+            (Layout::Us, &[press(CapsLock), release(CapsLock), press(CapsLock), press(KeyA)], "a"),
+            (Layout::Us, &[press(Space)], " "),
+        ];
+        for (layout, keys, expected) in cases {
+            assert_eq!(typed(*layout, keys), *expected, "{:?} {:?}", layout, keys);
+        }
+    }
+
+    #[test]
+    fn altgr() {
+        use Key::*;
+        #[rustfmt::skip]
+        let cases: &[Case] = &[
+            (Layout::German, &[press(AltGr), press(KeyQ), press(KeyE), press(Num8), release(AltGr), press(KeyQ)], "@€[q"),
+            (Layout::German, &[press(AltGr), press(IntlBackslash), press(Minus), press(KeyM)], "|\\µ"),
+            (Layout::French, &[press(AltGr), press(Num0), press(Num3), press(KeyE)], "@#€"),
+            // Keys with nothing on the AltGr level type nothing
+            (Layout::German, &[press(AltGr), press(KeyA)], ""),
+            (Layout::Us, &[press(AltGr), press(KeyA)], ""),
+        ];
+        for (layout, keys, expected) in cases {
+            assert_eq!(typed(*layout, keys), *expected, "{:?} {:?}", layout, keys);
+        }
+    }
+
+    #[test]
+    fn dead_keys() {
+        use Key::*;
+        #[rustfmt::skip]
+        let cases: &[Case] = &[
+            (Layout::German, &[press(BackQuote), press(KeyE)], "ê"),
+            (Layout::German, &[press(Equal), press(KeyA)], "á"),
+            (Layout::German, &[press(ShiftLeft), press(Equal), release(ShiftLeft), press(KeyE)], "è"),
+            (Layout::German, &[press(Equal), press(ShiftLeft), press(KeyZ)], "Ý"),
+            (Layout::French, &[press(LeftBracket), press(KeyO)], "ô"),
+            (Layout::French, &[press(ShiftLeft), press(LeftBracket), release(ShiftLeft), press(KeyU)], "ü"),
+            // Space types the accent itself, and a letter it does not go on follows it
+            (Layout::German, &[press(BackQuote), press(Space)], "^"),
+            (Layout::French, &[press(LeftBracket), press(KeyB)], "^b"),
+            // A dead key pressed twice types both accents
+            (Layout::French, &[press(LeftBracket), press(LeftBracket)], "^^"),
+            // US has no dead keys
+            (Layout::Us, &[press(Num6), press(KeyE)], "6e"),
+            (Layout::Us, &[press(ShiftLeft), press(Num6), release(ShiftLeft), press(KeyE)], "^e"),
+        ];
+        for (layout, keys, expected) in cases {
+            assert_eq!(typed(*layout, keys), *expected, "{:?} {:?}", layout, keys);
+        }
+    }
+
+    #[test]
+    fn os_text_wins_and_completes_a_dead_key() {
+        let mut translator = KeyTranslator::default();
+        let mut asked = false;
+        let text = translator.translate(
+            || {
+                asked = true;
+                Layout::French
+            },
+            Key::KeyQ,
+            Some("q"),
+        );
+        assert_eq!(text.as_deref(), Some("q"));
+        assert!(
+            !asked,
+            "the layout is only looked up when the OS gives no text"
+        );
+
+        assert_eq!(
+            translator.translate(|| Layout::German, Key::BackQuote, None),
+            None
+        );
+        assert_eq!(
+            translator
+                .translate(|| Layout::German, Key::KeyA, Some("a"))
+                .as_deref(),
+            Some("â")
+        );
+        // Control characters are not text
+        assert_eq!(
+            translator
+                .translate(|| Layout::Us, Key::KeyA, Some("\u{1}"))
+                .as_deref(),
+            Some("a")
+        );
+    }
+
+    #[test]
+    fn settings() {
+        assert_eq!(Layout::from_setting("auto"), None);
+        assert_eq!(Layout::from_setting(""), None);
+        assert_eq!(Layout::from_setting("DE"), Some(Layout::German));
+        assert_eq!(Layout::from_setting("french"), Some(Layout::French));
+        assert_eq!(Layout::from_setting("us"), Some(Layout::Us));
+        assert_eq!(Layout::from_name("gb"), Layout::Us);
+    }
+}
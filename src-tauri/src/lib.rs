@@ -1,52 +1,141 @@
+mod activity;
+mod corrections;
+mod crypto;
 mod database;
+mod editor;
+mod ime;
+mod input;
 mod keyboard;
+mod layout;
+mod migrations;
 mod models;
+mod redact;
+mod rules;
+mod search;
+mod secure;
+mod stats;
+mod timezone;
 mod window;
+mod writer;
 
 use database::Database;
-use models::{DailyStats, InputRecord, SearchFilter, Settings};
+use models::{
+    ActiveTime, ActivitySession, CorrectionStats, DailyStats, DatabaseStatus, InputRecord, KeyHeatmap, KeyStroke, RecordingStatus, SearchFilter, Settings, ShortcutStats, Stats,
+    TypingSpeed, WriterMetrics,
+};
 use once_cell::sync::OnceCell;
-use std::path::PathBuf;
+use rules::{CaptureRule, RuleSet};
+use stats::{Bucket, GroupBy, StatsRange};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
-use tauri::Manager;
+use tauri::{Emitter, Manager};
+use timezone::Zone;
 
 static DATABASE: OnceCell<Arc<Database>> = OnceCell::new();
 
-fn get_db() -> &'static Arc<Database> {
-    DATABASE.get().expect("Database not initialized")
+static DB_PATH: OnceCell<PathBuf> = OnceCell::new();
+
+fn get_db() -> Result<&'static Arc<Database>, String> {
+    DATABASE.get().ok_or_else(|| "Database is locked".to_string())
+}
+
+fn get_db_path() -> Result<&'static PathBuf, String> {
+    DB_PATH.get().ok_or_else(|| "Database not initialized".to_string())
 }
 
 #[tauri::command]
 fn get_records(filter: SearchFilter) -> Result<Vec<InputRecord>, String> {
-    get_db()
+    get_db()?
         .get_records(&filter)
         .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
 fn get_today_stats() -> Result<DailyStats, String> {
-    get_db()
+    get_db()?
         .get_today_stats()
         .map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+fn get_stats(range: StatsRange, bucket: Bucket, group_by: Option<GroupBy>) -> Result<Stats, String> {
+    get_db()?
+        .get_stats(&range, bucket, group_by.unwrap_or_default())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_correction_stats(range: StatsRange, bucket: Bucket, limit: Option<usize>) -> Result<CorrectionStats, String> {
+    get_db()?
+        .get_correction_stats(&range, bucket, limit.unwrap_or(20))
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_active_time(range: StatsRange) -> Result<ActiveTime, String> {
+    get_db()?
+        .get_active_time(&range)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_sessions(range: StatsRange, idle_gap_secs: Option<u64>) -> Result<Vec<ActivitySession>, String> {
+    let idle_gap = idle_gap_secs.map_or_else(keyboard::idle_timeout, std::time::Duration::from_secs);
+    get_db()?
+        .get_sessions(&range, idle_gap)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_key_heatmap(range: StatsRange, app: Option<String>) -> Result<KeyHeatmap, String> {
+    get_db()?
+        .get_key_heatmap(&range, app.as_deref())
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_typing_speed(range: StatsRange) -> Result<TypingSpeed, String> {
+    get_db()?
+        .get_typing_speed(&range)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_writer_metrics() -> WriterMetrics {
+    keyboard::writer_metrics()
+}
+
+#[tauri::command]
+fn get_key_events(input_id: i64) -> Result<Vec<KeyStroke>, String> {
+    get_db()?
+        .get_key_events(input_id)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+fn get_shortcut_stats(app_name: Option<String>, limit: Option<i64>) -> Result<Vec<ShortcutStats>, String> {
+    get_db()?
+        .shortcut_stats(app_name.as_deref(), limit.unwrap_or(10))
+        .map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 fn get_app_list() -> Result<Vec<String>, String> {
-    get_db()
+    get_db()?
         .get_app_list()
         .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
 fn delete_old_records(before_date: String) -> Result<usize, String> {
-    get_db()
+    get_db()?
         .delete_records_before(&before_date)
         .map_err(|e| e.to_string())
 }
 
 #[tauri::command]
 fn get_settings() -> Result<Settings, String> {
-    let db = get_db();
+    let db = get_db()?;
 
     let excluded_apps: Vec<String> = db
         .get_setting("excluded_apps")
@@ -66,16 +155,66 @@ fn get_settings() -> Result<Settings, String> {
         .and_then(|s| s.parse().ok())
         .unwrap_or(false);
 
+    let keyboard_layout: String = db
+        .get_setting("keyboard_layout")
+        .map_err(|e| e.to_string())?
+        .unwrap_or_else(|| "auto".to_string());
+
+    let record_key_events: bool = db
+        .get_setting("record_key_events")
+        .map_err(|e| e.to_string())?
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(false);
+
+    let redaction_mode: String = db
+        .get_setting("redaction_mode")
+        .map_err(|e| e.to_string())?
+        .unwrap_or_else(|| "mask".to_string());
+
+    let redaction_patterns: Vec<String> = db
+        .get_setting("redaction_patterns")
+        .map_err(|e| e.to_string())?
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default();
+
+    let capture_rules: Vec<CaptureRule> = db
+        .get_setting("capture_rules")
+        .map_err(|e| e.to_string())?
+        .and_then(|s| serde_json::from_str(&s).ok())
+        .unwrap_or_default();
+
+    let time_zone: String = db
+        .get_setting("time_zone")
+        .map_err(|e| e.to_string())?
+        .unwrap_or_default();
+
+    let idle_timeout_secs: u64 = db
+        .get_setting("idle_timeout_secs")
+        .map_err(|e| e.to_string())?
+        .and_then(|s| s.parse().ok())
+        .unwrap_or(300);
+
     Ok(Settings {
         excluded_apps,
         merge_interval_ms,
         auto_start,
+        keyboard_layout,
+        record_key_events,
+        redaction_mode,
+        redaction_patterns,
+        capture_rules,
+        time_zone,
+        idle_timeout_secs,
     })
 }
 
 #[tauri::command]
 fn save_settings(settings: Settings) -> Result<(), String> {
-    let db = get_db();
+    let db = get_db()?;
+
+    redact::validate_patterns(&settings.redaction_patterns)?;
+    RuleSet::validate(&settings.capture_rules)?;
+    let zone = Zone::parse(&settings.time_zone)?;
 
     let excluded_json = serde_json::to_string(&settings.excluded_apps)
         .map_err(|e| e.to_string())?;
@@ -88,15 +227,41 @@ fn save_settings(settings: Settings) -> Result<(), String> {
     db.set_setting("auto_start", &settings.auto_start.to_string())
         .map_err(|e| e.to_string())?;
 
-    // Update excluded apps in keyboard listener
-    keyboard::set_excluded_apps(settings.excluded_apps);
+    db.set_setting("keyboard_layout", &settings.keyboard_layout)
+        .map_err(|e| e.to_string())?;
+
+    db.set_setting("record_key_events", &settings.record_key_events.to_string())
+        .map_err(|e| e.to_string())?;
+
+    db.set_setting("redaction_mode", &settings.redaction_mode)
+        .map_err(|e| e.to_string())?;
+
+    let patterns_json = serde_json::to_string(&settings.redaction_patterns)
+        .map_err(|e| e.to_string())?;
+    db.set_setting("redaction_patterns", &patterns_json)
+        .map_err(|e| e.to_string())?;
+
+    let rules_json = serde_json::to_string(&settings.capture_rules)
+        .map_err(|e| e.to_string())?;
+    db.set_setting("capture_rules", &rules_json)
+        .map_err(|e| e.to_string())?;
+
+    db.set_setting("time_zone", settings.time_zone.trim())
+        .map_err(|e| e.to_string())?;
+    db.set_time_zone(zone);
+
+    db.set_setting("idle_timeout_secs", &settings.idle_timeout_secs.to_string())
+        .map_err(|e| e.to_string())?;
+
+    // Apply the new settings to the running keyboard listener
+    keyboard::apply_settings(&settings);
 
     Ok(())
 }
 
 #[tauri::command]
 fn export_records(filter: SearchFilter) -> Result<String, String> {
-    let records = get_db()
+    let records = get_db()?
         .get_records(&filter)
         .map_err(|e| e.to_string())?;
 
@@ -104,6 +269,272 @@ fn export_records(filter: SearchFilter) -> Result<String, String> {
         .map_err(|e| e.to_string())
 }
 
+#[tauri::command]
+fn start_recording() -> Result<RecordingStatus, String> {
+    keyboard::start_recording()
+}
+
+#[tauri::command]
+fn stop_recording() -> Result<RecordingStatus, String> {
+    Ok(keyboard::stop_recording())
+}
+
+#[tauri::command]
+fn pause_recording(duration_secs: u64) -> Result<RecordingStatus, String> {
+    keyboard::pause_recording(std::time::Duration::from_secs(duration_secs))
+}
+
+#[tauri::command]
+fn get_recording_status() -> Result<RecordingStatus, String> {
+    Ok(keyboard::recording_status())
+}
+
+#[tauri::command]
+fn get_database_status() -> Result<DatabaseStatus, String> {
+    let db_path = get_db_path()?;
+    Ok(DatabaseStatus {
+        encrypted: crypto::kdf_path(db_path).exists(),
+        field_encrypted: crypto::key_file_path(db_path).exists(),
+        locked: DATABASE.get().is_none(),
+        encryption_available: Database::encryption_available(),
+    })
+}
+
+/// Open the database with `passphrase`, which unlocks SQLCipher and the field
+/// encryption key file, whichever of the two are in use.
+#[tauri::command]
+fn unlock_database(passphrase: String) -> Result<(), String> {
+    if DATABASE.get().is_some() {
+        return Ok(());
+    }
+    let db_path = get_db_path()?;
+
+    let db = if crypto::kdf_path(db_path).exists() {
+        open_encrypted_database(db_path, &passphrase)?
+    } else {
+        Database::new(db_path.clone()).map_err(|e| e.to_string())?
+    };
+
+    if crypto::key_file_path(db_path).exists() {
+        let key = unwrap_field_key(db_path, &passphrase)?;
+        db.set_field_cipher(crypto::FieldCipher::new(&key));
+    }
+
+    open_database(Arc::new(db));
+    Ok(())
+}
+
+/// Unwrap the field encryption key, trying a key file left pending by an
+/// interrupted passphrase change if the current one does not take it.
+fn unwrap_field_key(db_path: &Path, passphrase: &str) -> Result<crypto::Key, String> {
+    let key_file_path = crypto::key_file_path(db_path);
+    let pending_path = crypto::pending_key_file_path(db_path);
+    let current = crypto::KeyFile::load(&key_file_path)?.unwrap_key(passphrase);
+    if current.is_ok() || !pending_path.exists() {
+        return current;
+    }
+
+    let key = crypto::KeyFile::load(&pending_path)?.unwrap_key(passphrase)?;
+    std::fs::rename(&pending_path, &key_file_path).map_err(|e| e.to_string())?;
+    Ok(key)
+}
+
+fn open_encrypted_database(db_path: &Path, passphrase: &str) -> Result<Database, String> {
+    // A pending file means a passphrase change was interrupted; whichever
+    // settings open the database are the ones to keep
+    let kdf_path = crypto::kdf_path(db_path);
+    let pending_path = crypto::pending_kdf_path(db_path);
+    for path in [&kdf_path, &pending_path] {
+        if !path.exists() {
+            continue;
+        }
+        let key = crypto::derive_key(passphrase, &crypto::KdfParams::load(path)?)?;
+        match Database::open_encrypted(db_path.to_path_buf(), &key) {
+            Ok(db) => {
+                if path == &pending_path {
+                    std::fs::rename(&pending_path, &kdf_path).map_err(|e| e.to_string())?;
+                }
+                return Ok(db);
+            }
+            Err(e) if Database::is_wrong_key(&e) => continue,
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+    Err("Wrong passphrase".to_string())
+}
+
+/// Encrypt the open plaintext database in place.
+#[tauri::command]
+fn encrypt_database(passphrase: String) -> Result<(), String> {
+    let db = get_db()?;
+    let db_path = get_db_path()?;
+    if crypto::kdf_path(db_path).exists() {
+        return Err("Database is already encrypted".to_string());
+    }
+    if !Database::encryption_available() {
+        return Err("This build does not support database encryption".to_string());
+    }
+    crypto::check_passphrase(&passphrase)?;
+    check_same_passphrase(db_path, &passphrase)?;
+
+    // Save the settings first: without the salt an encrypted file is lost
+    let params = crypto::KdfParams::generate()?;
+    let key = crypto::derive_key(&passphrase, &params)?;
+    let kdf_path = crypto::kdf_path(db_path);
+    params.save(&kdf_path)?;
+    if let Err(e) = db.encrypt(&key) {
+        // Once the file has been replaced the settings are the only way back
+        // in, so they are only removed while the database is still plaintext
+        if let Ok(false) = Database::verify_key(db_path, &key) {
+            let _ = std::fs::remove_file(&kdf_path);
+        }
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Encrypt typed text and window titles from now on, and those of the
+/// records already stored. Returns the number of records encrypted.
+#[tauri::command]
+fn enable_field_encryption(passphrase: String) -> Result<usize, String> {
+    let db = get_db()?;
+    let db_path = get_db_path()?;
+    let key_file_path = crypto::key_file_path(db_path);
+    if key_file_path.exists() {
+        return Err("Field encryption is already on".to_string());
+    }
+    crypto::check_passphrase(&passphrase)?;
+    check_same_passphrase(db_path, &passphrase)?;
+
+    let (key_file, key) = crypto::KeyFile::create(&passphrase)?;
+    key_file.save(&key_file_path)?;
+    db.set_field_cipher(crypto::FieldCipher::new(&key));
+    db.encrypt_fields().map_err(|e| e.to_string())
+}
+
+/// One passphrase unlocks everything, so turning on a second kind of
+/// encryption must reuse the passphrase of the first.
+fn check_same_passphrase(db_path: &Path, passphrase: &str) -> Result<(), String> {
+    let kdf_path = crypto::kdf_path(db_path);
+    if kdf_path.exists() {
+        let key = crypto::derive_key(passphrase, &crypto::KdfParams::load(&kdf_path)?)?;
+        if !Database::verify_key(db_path, &key).map_err(|e| e.to_string())? {
+            return Err("Use the passphrase the database is already encrypted with".to_string());
+        }
+    }
+
+    let key_file_path = crypto::key_file_path(db_path);
+    if key_file_path.exists() {
+        crypto::KeyFile::load(&key_file_path)?
+            .unwrap_key(passphrase)
+            .map_err(|_| "Use the passphrase the database is already encrypted with".to_string())?;
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn change_passphrase(old_passphrase: String, new_passphrase: String) -> Result<(), String> {
+    let db = get_db()?;
+    let db_path = get_db_path()?;
+    let kdf_path = crypto::kdf_path(db_path);
+    let key_file_path = crypto::key_file_path(db_path);
+    if !kdf_path.exists() && !key_file_path.exists() {
+        return Err("Database is not encrypted".to_string());
+    }
+    crypto::check_passphrase(&new_passphrase)?;
+
+    // Check the old passphrase against everything before changing anything
+    let key_file = if key_file_path.exists() {
+        Some(crypto::KeyFile::load(&key_file_path)?.rewrap(&old_passphrase, &new_passphrase)?)
+    } else {
+        None
+    };
+
+    // Like the key derivation settings, the rewrapped key is saved aside
+    // first, so unlocking can still find it if the change is interrupted
+    let pending_key_path = crypto::pending_key_file_path(db_path);
+    if let Some(key_file) = &key_file {
+        key_file.save(&pending_key_path)?;
+    }
+
+    if kdf_path.exists() {
+        let old_key = crypto::derive_key(&old_passphrase, &crypto::KdfParams::load(&kdf_path)?)?;
+        if !Database::verify_key(db_path, &old_key).map_err(|e| e.to_string())? {
+            return Err("Wrong passphrase".to_string());
+        }
+
+        // Save the new settings before rekeying, so a crash in between leaves
+        // a way to derive whichever key the database ended up with
+        let params = crypto::KdfParams::generate()?;
+        let new_key = crypto::derive_key(&new_passphrase, &params)?;
+        let pending_path = crypto::pending_kdf_path(db_path);
+        params.save(&pending_path)?;
+        db.rekey(&new_key).map_err(|e| e.to_string())?;
+        std::fs::rename(&pending_path, &kdf_path).map_err(|e| e.to_string())?;
+    }
+
+    if key_file.is_some() {
+        std::fs::rename(&pending_key_path, &key_file_path).map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Hand an opened database to the commands and the keyboard listener, and
+/// apply the settings stored in it.
+fn open_database(db: Arc<Database>) {
+    let _ = DATABASE.set(db.clone());
+
+    // Initialize keyboard listener with database
+    keyboard::init_database(db.clone());
+
+    let key = get_db_path().and_then(|db_path| redact::load_hash_key(&crypto::redaction_key_path(db_path)));
+    match key {
+        Ok(key) => redact::set_hash_key(key),
+        Err(e) => eprintln!("Failed to load the redaction key, using one for this run only: {}", e),
+    }
+
+    // Load settings into the keyboard listener
+    if let Ok(settings) = get_settings() {
+        keyboard::apply_settings(&settings);
+
+        match Zone::parse(&settings.time_zone) {
+            Ok(zone) => db.set_time_zone(zone),
+            Err(e) => eprintln!("{}, using the system time zone", e),
+        }
+
+        if settings.auto_start {
+            if let Err(e) = keyboard::start_recording() {
+                eprintln!("Failed to start recording: {}", e);
+            }
+        }
+    }
+}
+
+/// Replay a recorded event file through the keyboard pipeline on virtual time,
+/// against an in-memory database, and return the resulting records as JSON.
+pub fn replay(path: &str) -> Result<String, String> {
+    let file = std::fs::File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    let events = input::RecordedEvent::parse_lines(std::io::BufReader::new(file))?;
+
+    let db = Arc::new(Database::in_memory().map_err(|e| e.to_string())?);
+    keyboard::replay(db.clone(), &events, keyboard::REPLAY_ORIGIN);
+
+    let filter = SearchFilter {
+        limit: None,
+        offset: None,
+        ..SearchFilter::default()
+    };
+    let mut records = db.get_records(&filter).map_err(|e| e.to_string())?;
+    records.sort_by_key(|r| r.id);
+    // The database fills this in with the real time
+    for record in &mut records {
+        record.created_at = None;
+    }
+
+    serde_json::to_string_pretty(&records)
+        .map_err(|e| e.to_string())
+}
+
 pub fn run() {
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
@@ -123,6 +554,18 @@ pub fn run() {
             }
 
             let db_path: PathBuf = app_dir.join("arkinput.db");
+            let _ = DB_PATH.set(db_path.clone());
+
+            // Let the frontend follow recording state changes
+            let handle = app.handle().clone();
+            keyboard::on_status_change(move |status| {
+                let _ = handle.emit("recording-status-changed", status);
+            });
+
+            // An encrypted database stays closed until the frontend unlocks it
+            if crypto::kdf_path(&db_path).exists() || crypto::key_file_path(&db_path).exists() {
+                return Ok(());
+            }
 
             let db = match Database::new(db_path) {
                 Ok(db) => Arc::new(db),
@@ -131,35 +574,43 @@ pub fn run() {
                     return Err(e.to_string().into());
                 }
             };
-
-            let _ = DATABASE.set(db.clone());
-
-            // Initialize keyboard listener with database
-            keyboard::init_database(db);
-
-            // Load settings and apply excluded apps
-            if let Ok(settings) = get_settings() {
-                keyboard::set_excluded_apps(settings.excluded_apps);
-            }
-
-            // Keyboard listener is disabled for now - enable after testing
-            // TODO: Re-enable after fixing macOS compatibility
-            // std::thread::spawn(|| {
-            //     std::thread::sleep(std::time::Duration::from_secs(1));
-            //     keyboard::start_keyboard_listener();
-            // });
+            open_database(db);
 
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             get_records,
             get_today_stats,
+            get_stats,
+            get_typing_speed,
+            get_correction_stats,
+            get_key_heatmap,
+            get_active_time,
+            get_sessions,
+            get_key_events,
+            get_shortcut_stats,
             get_app_list,
             delete_old_records,
             get_settings,
             save_settings,
             export_records,
+            start_recording,
+            stop_recording,
+            pause_recording,
+            get_recording_status,
+            get_database_status,
+            unlock_database,
+            encrypt_database,
+            enable_field_encryption,
+            change_passphrase,
+            get_writer_metrics,
         ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|_app, event| {
+            if let tauri::RunEvent::Exit = event {
+                // Records still queued must reach the disk before the process ends
+                keyboard::shutdown();
+            }
+        });
 }
@@ -2,5 +2,18 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
 fn main() {
+    // `arkinput --replay <events.jsonl>` runs a recorded script through the
+    // keyboard pipeline and prints the merged records instead of starting the UI
+    if let Some(path) = std::env::args().skip_while(|a| a != "--replay").nth(1) {
+        match arkinput_lib::replay(&path) {
+            Ok(json) => println!("{}", json),
+            Err(e) => {
+                eprintln!("Replay failed: {}", e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
     arkinput_lib::run();
 }
@@ -0,0 +1,644 @@
+use rusqlite::{ffi, params, Connection, Result};
+
+/// One step of the schema history. Databases remember the last step they have
+/// run in `PRAGMA user_version`.
+struct Migration {
+    /// `user_version` once this step has run
+    version: i64,
+    description: &'static str,
+    up: fn(&Connection) -> Result<()>,
+}
+
+/// The full schema history, oldest first. Append new steps; never edit one
+/// that has shipped.
+///
+/// Databases created before versioning was introduced are all at version 0,
+/// whatever tables they happen to have, so steps 1 to 4 tolerate finding
+/// their changes already made.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create inputs and settings",
+        up: create_base_tables,
+    },
+    Migration {
+        version: 2,
+        description: "add composed and final_text to inputs",
+        up: add_final_text,
+    },
+    Migration {
+        version: 3,
+        description: "create key_events",
+        up: create_key_events,
+    },
+    Migration {
+        version: 4,
+        description: "create shortcuts",
+        up: create_shortcuts,
+    },
+    Migration {
+        version: 5,
+        description: "create inputs_fts full-text index",
+        up: create_inputs_fts,
+    },
+    Migration {
+        version: 6,
+        description: "store timestamps as epoch milliseconds",
+        up: timestamps_to_millis,
+    },
+    Migration {
+        version: 7,
+        description: "add duration_ms to inputs",
+        up: add_duration,
+    },
+    Migration {
+        version: 8,
+        description: "create key_frequency",
+        up: create_key_frequency,
+    },
+    Migration {
+        version: 9,
+        description: "create focus_spans",
+        up: create_focus_spans,
+    },
+    Migration {
+        version: 10,
+        description: "keep encrypted text out of inputs_fts",
+        up: skip_encrypted_in_fts,
+    },
+];
+
+pub fn current_version(conn: &Connection) -> Result<i64> {
+    conn.query_row("PRAGMA user_version", [], |row| row.get(0))
+}
+
+/// Bring the schema up to date. Each step commits together with its version
+/// bump, so a failing step rolls back to the version before it and the
+/// error is returned.
+pub fn migrate(conn: &mut Connection) -> Result<()> {
+    run(conn, MIGRATIONS)
+}
+
+fn run(conn: &mut Connection, migrations: &[Migration]) -> Result<()> {
+    let current = current_version(conn)?;
+    let latest = migrations.last().map_or(0, |m| m.version);
+    if current > latest {
+        return Err(rusqlite::Error::SqliteFailure(
+            ffi::Error::new(ffi::SQLITE_ERROR),
+            Some(format!(
+                "database schema version {} is newer than this build supports ({})",
+                current, latest
+            )),
+        ));
+    }
+
+    // Rebuilding a table drops the old one, which the foreign keys pointing
+    // at it would refuse. They can only be switched off outside a
+    // transaction, so they stay off for the whole run.
+    let foreign_keys: bool = conn.query_row("PRAGMA foreign_keys", [], |row| row.get(0))?;
+    conn.pragma_update(None, "foreign_keys", false)?;
+    let result = migrations
+        .iter()
+        .filter(|m| m.version > current)
+        .try_for_each(|migration| {
+            eprintln!("Migrating database to version {}: {}", migration.version, migration.description);
+
+            let tx = conn.transaction()?;
+            (migration.up)(&tx)?;
+            tx.pragma_update(None, "user_version", migration.version)?;
+            tx.commit()
+        });
+    conn.pragma_update(None, "foreign_keys", foreign_keys)?;
+    result
+}
+
+fn create_base_tables(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS inputs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            timestamp DATETIME NOT NULL,
+            app_name TEXT NOT NULL,
+            window_title TEXT,
+            content TEXT NOT NULL,
+            key_count INTEGER DEFAULT 1,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_timestamp ON inputs(timestamp);
+        CREATE INDEX IF NOT EXISTS idx_app_name ON inputs(app_name);
+
+        CREATE TABLE IF NOT EXISTS settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL
+        );
+        ",
+    )
+}
+
+fn add_final_text(conn: &Connection) -> Result<()> {
+    ensure_column(conn, "inputs", "composed", "INTEGER NOT NULL DEFAULT 0")?;
+    ensure_column(conn, "inputs", "final_text", "TEXT")
+}
+
+fn create_key_events(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS key_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            input_id INTEGER NOT NULL REFERENCES inputs(id),
+            time_ms INTEGER NOT NULL,
+            action TEXT NOT NULL,
+            key_code TEXT NOT NULL,
+            modifiers INTEGER NOT NULL DEFAULT 0
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_key_events_input ON key_events(input_id);
+        ",
+    )
+}
+
+fn create_shortcuts(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE IF NOT EXISTS shortcuts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            input_id INTEGER NOT NULL REFERENCES inputs(id),
+            shortcut TEXT NOT NULL
+        );
+
+        CREATE INDEX IF NOT EXISTS idx_shortcuts_input ON shortcuts(input_id);
+        ",
+    )
+}
+
+/// An external-content FTS5 index over `inputs`, kept in step by triggers.
+/// The trigram tokenizer matches any substring of three or more characters,
+/// which also works for CJK text that has no spaces between words.
+fn create_inputs_fts(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        UPDATE inputs SET final_text = content WHERE final_text IS NULL;
+
+        CREATE VIRTUAL TABLE inputs_fts USING fts5(
+            final_text, window_title, app_name,
+            content='inputs', content_rowid='id', tokenize='trigram'
+        );
+        ",
+    )?;
+    create_inputs_fts_triggers(conn)?;
+    conn.execute_batch("INSERT INTO inputs_fts(inputs_fts) VALUES ('rebuild')")
+}
+
+fn create_inputs_fts_triggers(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TRIGGER inputs_fts_insert AFTER INSERT ON inputs BEGIN
+            INSERT INTO inputs_fts(rowid, final_text, window_title, app_name)
+            VALUES (new.id, new.final_text, new.window_title, new.app_name);
+        END;
+
+        CREATE TRIGGER inputs_fts_delete AFTER DELETE ON inputs BEGIN
+            INSERT INTO inputs_fts(inputs_fts, rowid, final_text, window_title, app_name)
+            VALUES ('delete', old.id, old.final_text, old.window_title, old.app_name);
+        END;
+
+        CREATE TRIGGER inputs_fts_update AFTER UPDATE ON inputs BEGIN
+            INSERT INTO inputs_fts(inputs_fts, rowid, final_text, window_title, app_name)
+            VALUES ('delete', old.id, old.final_text, old.window_title, old.app_name);
+            INSERT INTO inputs_fts(rowid, final_text, window_title, app_name)
+            VALUES (new.id, new.final_text, new.window_title, new.app_name);
+        END;
+        ",
+    )
+}
+
+/// Rebuild `inputs` with `timestamp` and `created_at` as integer milliseconds
+/// since the Unix epoch, in place of second-resolution UTC text. Ids are kept,
+/// so `key_events`, `shortcuts` and the full-text index stay valid.
+fn timestamps_to_millis(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE inputs_new (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            timestamp INTEGER NOT NULL,
+            app_name TEXT NOT NULL,
+            window_title TEXT,
+            content TEXT NOT NULL,
+            key_count INTEGER DEFAULT 1,
+            created_at INTEGER NOT NULL DEFAULT (CAST((julianday('now') - 2440587.5) * 86400000 AS INTEGER)),
+            composed INTEGER NOT NULL DEFAULT 0,
+            final_text TEXT
+        );
+
+        -- Text timestamps were written in UTC. One that cannot be parsed
+        -- falls back to when the row was stored rather than being lost.
+        INSERT INTO inputs_new (id, timestamp, app_name, window_title, content, key_count, created_at, composed, final_text)
+        SELECT
+            id,
+            CAST(ROUND((COALESCE(julianday(timestamp), julianday(created_at), 2440587.5) - 2440587.5) * 86400000) AS INTEGER),
+            app_name,
+            window_title,
+            content,
+            key_count,
+            CAST(ROUND((COALESCE(julianday(created_at), julianday(timestamp), 2440587.5) - 2440587.5) * 86400000) AS INTEGER),
+            composed,
+            final_text
+        FROM inputs;
+
+        -- Keep the AUTOINCREMENT counter, so ids of deleted rows are not reused
+        DELETE FROM sqlite_sequence WHERE name = 'inputs_new';
+        UPDATE sqlite_sequence SET name = 'inputs_new' WHERE name = 'inputs';
+
+        DROP TABLE inputs;
+        ALTER TABLE inputs_new RENAME TO inputs;
+
+        CREATE INDEX idx_timestamp ON inputs(timestamp);
+        CREATE INDEX idx_app_name ON inputs(app_name);
+        ",
+    )?;
+    create_inputs_fts_triggers(conn)
+}
+
+fn add_duration(conn: &Connection) -> Result<()> {
+    conn.execute_batch("ALTER TABLE inputs ADD COLUMN duration_ms INTEGER")
+}
+
+/// Press counts per app and hour, added to as records are saved. `hour` is
+/// the epoch milliseconds at the start of the UTC hour; `key` is a key code,
+/// or two joined by a space for a bigram.
+fn create_key_frequency(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE key_frequency (
+            hour INTEGER NOT NULL,
+            app_name TEXT NOT NULL,
+            kind TEXT NOT NULL,
+            key TEXT NOT NULL,
+            count INTEGER NOT NULL,
+            PRIMARY KEY (hour, app_name, kind, key)
+        ) WITHOUT ROWID;
+        ",
+    )
+}
+
+/// Stretches of time an app had focus, in epoch milliseconds
+fn create_focus_spans(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        CREATE TABLE focus_spans (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            app_name TEXT NOT NULL,
+            start_ms INTEGER NOT NULL,
+            end_ms INTEGER NOT NULL
+        );
+
+        CREATE INDEX idx_focus_spans_start ON focus_spans(start_ms);
+        ",
+    )
+}
+
+/// Index only rows whose text is plaintext. `FieldCipher` output can never
+/// match a search and only bloats the index, and encrypted records are
+/// searched once decrypted instead. A row is encrypted as a whole, so
+/// `final_text` tells for all its columns.
+///
+/// The index is external-content, so a row may only be deleted from it if it
+/// was indexed: the `'delete'` commands carry the same condition as the
+/// inserts.
+fn skip_encrypted_in_fts(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        DROP TRIGGER inputs_fts_insert;
+        DROP TRIGGER inputs_fts_delete;
+        DROP TRIGGER inputs_fts_update;
+
+        CREATE TRIGGER inputs_fts_insert AFTER INSERT ON inputs
+        WHEN substr(new.final_text, 1, 5) IS NOT 'enc1:' BEGIN
+            INSERT INTO inputs_fts(rowid, final_text, window_title, app_name)
+            VALUES (new.id, new.final_text, new.window_title, new.app_name);
+        END;
+
+        CREATE TRIGGER inputs_fts_delete AFTER DELETE ON inputs
+        WHEN substr(old.final_text, 1, 5) IS NOT 'enc1:' BEGIN
+            INSERT INTO inputs_fts(inputs_fts, rowid, final_text, window_title, app_name)
+            VALUES ('delete', old.id, old.final_text, old.window_title, old.app_name);
+        END;
+
+        CREATE TRIGGER inputs_fts_update AFTER UPDATE ON inputs BEGIN
+            INSERT INTO inputs_fts(inputs_fts, rowid, final_text, window_title, app_name)
+            SELECT 'delete', old.id, old.final_text, old.window_title, old.app_name
+            WHERE substr(old.final_text, 1, 5) IS NOT 'enc1:';
+            INSERT INTO inputs_fts(rowid, final_text, window_title, app_name)
+            SELECT new.id, new.final_text, new.window_title, new.app_name
+            WHERE substr(new.final_text, 1, 5) IS NOT 'enc1:';
+        END;
+
+        -- 'rebuild' would index every row, ciphertext included
+        INSERT INTO inputs_fts(inputs_fts) VALUES ('delete-all');
+        INSERT INTO inputs_fts(rowid, final_text, window_title, app_name)
+        SELECT id, final_text, window_title, app_name FROM inputs
+        WHERE substr(final_text, 1, 5) IS NOT 'enc1:';
+        ",
+    )
+}
+
+/// Add a column unless an unversioned database already has it
+fn ensure_column(conn: &Connection, table: &str, column: &str, definition: &str) -> Result<()> {
+    let exists = conn
+        .prepare(&format!(
+            "SELECT 1 FROM pragma_table_info('{}') WHERE name = ?",
+            table
+        ))?
+        .exists(params![column])?;
+    if !exists {
+        conn.execute_batch(&format!(
+            "ALTER TABLE {} ADD COLUMN {} {}",
+            table, column, definition
+        ))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The schema of the first release, before versioning
+    const BASELINE: &str = "
+        CREATE TABLE IF NOT EXISTS inputs (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            timestamp DATETIME NOT NULL,
+            app_name TEXT NOT NULL,
+            window_title TEXT,
+            content TEXT NOT NULL,
+            key_count INTEGER DEFAULT 1,
+            created_at DATETIME DEFAULT CURRENT_TIMESTAMP
+        );
+        CREATE INDEX IF NOT EXISTS idx_timestamp ON inputs(timestamp);
+        CREATE INDEX IF NOT EXISTS idx_app_name ON inputs(app_name);
+        CREATE TABLE IF NOT EXISTS settings (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+    ";
+
+    /// What unversioned builds added on top of the baseline
+    const UNVERSIONED_ADDITIONS: &str = "
+        ALTER TABLE inputs ADD COLUMN final_text TEXT;
+        ALTER TABLE inputs ADD COLUMN composed INTEGER NOT NULL DEFAULT 0;
+        CREATE TABLE IF NOT EXISTS key_events (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            input_id INTEGER NOT NULL REFERENCES inputs(id),
+            time_ms INTEGER NOT NULL,
+            action TEXT NOT NULL,
+            key_code TEXT NOT NULL,
+            modifiers INTEGER NOT NULL DEFAULT 0
+        );
+        CREATE INDEX IF NOT EXISTS idx_key_events_input ON key_events(input_id);
+        CREATE TABLE IF NOT EXISTS shortcuts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            input_id INTEGER NOT NULL REFERENCES inputs(id),
+            shortcut TEXT NOT NULL
+        );
+        CREATE INDEX IF NOT EXISTS idx_shortcuts_input ON shortcuts(input_id);
+    ";
+
+    fn latest_version() -> i64 {
+        MIGRATIONS.last().map_or(0, |m| m.version)
+    }
+
+    /// 2026-01-01 10:00:00 UTC
+    const TIMESTAMP_MS: i64 = 1_767_261_600_000;
+
+    /// Every table, index and trigger with its columns, in a form that does
+    /// not depend on how the objects were created
+    fn schema(conn: &Connection) -> Vec<(String, String, Vec<String>)> {
+        let objects: Vec<(String, String)> = conn
+            .prepare("SELECT type, name FROM sqlite_master WHERE name NOT LIKE 'sqlite_%' ORDER BY type, name")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        objects
+            .into_iter()
+            .map(|(kind, name)| {
+                let columns = conn
+                    .prepare(&format!(
+                        "SELECT name FROM pragma_table_info('{}') ORDER BY name",
+                        name
+                    ))
+                    .unwrap()
+                    .query_map([], |row| row.get(0))
+                    .unwrap()
+                    .collect::<Result<_>>()
+                    .unwrap();
+                (kind, name, columns)
+            })
+            .collect()
+    }
+
+    fn fresh_schema() -> Vec<(String, String, Vec<String>)> {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        schema(&conn)
+    }
+
+    fn fts_matches(conn: &Connection, text: &str) -> i64 {
+        conn.query_row(
+            "SELECT count(*) FROM inputs_fts WHERE inputs_fts MATCH ?",
+            params![format!("\"{}\"", text)],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    /// Migrate, then check the schema matches a new database and the record
+    /// stored before survived
+    fn check_upgrade(conn: &mut Connection) {
+        migrate(conn).unwrap();
+        assert_eq!(current_version(conn).unwrap(), latest_version());
+        assert_eq!(schema(conn), fresh_schema());
+
+        let row: (i64, String, String, String, i64) = conn
+            .query_row(
+                "SELECT timestamp, app_name, content, final_text, composed FROM inputs",
+                [],
+                |row| {
+                    Ok((
+                        row.get(0)?,
+                        row.get(1)?,
+                        row.get(2)?,
+                        row.get(3)?,
+                        row.get(4)?,
+                    ))
+                },
+            )
+            .unwrap();
+        assert_eq!(
+            row,
+            (
+                TIMESTAMP_MS,
+                "Editor".into(),
+                "hello".into(),
+                "hello".into(),
+                0
+            )
+        );
+        assert_eq!(fts_matches(conn, "hello"), 1);
+        conn.execute_batch("INSERT INTO inputs_fts(inputs_fts) VALUES ('integrity-check')")
+            .unwrap();
+
+        // Running again changes nothing
+        migrate(conn).unwrap();
+        assert_eq!(schema(conn), fresh_schema());
+    }
+
+    #[test]
+    fn upgrades_baseline_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(BASELINE).unwrap();
+        conn.execute(
+            "INSERT INTO inputs (timestamp, app_name, content) VALUES ('2026-01-01 10:00:00', 'Editor', 'hello')",
+            [],
+        )
+        .unwrap();
+        check_upgrade(&mut conn);
+    }
+
+    #[test]
+    fn upgrades_unversioned_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(BASELINE).unwrap();
+        conn.execute_batch(UNVERSIONED_ADDITIONS).unwrap();
+        conn.execute_batch(
+            "
+            INSERT INTO inputs (timestamp, app_name, content, final_text) VALUES ('2026-01-01 10:00:00', 'Editor', 'hello', 'hello');
+            INSERT INTO key_events (input_id, time_ms, action, key_code) VALUES (1, 5, 'press', 'KeyH');
+            INSERT INTO shortcuts (input_id, shortcut) VALUES (1, 'Ctrl+S');
+            ",
+        )
+        .unwrap();
+
+        check_upgrade(&mut conn);
+        let key: (i64, String) = conn
+            .query_row("SELECT input_id, key_code FROM key_events", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(key, (1, "KeyH".into()));
+        let shortcut: String = conn
+            .query_row(
+                "SELECT shortcut FROM shortcuts WHERE input_id = 1",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(shortcut, "Ctrl+S");
+    }
+
+    #[test]
+    fn upgrades_every_version() {
+        for version in 1..latest_version() {
+            let mut conn = Connection::open_in_memory().unwrap();
+            run(&mut conn, &MIGRATIONS[..version as usize]).unwrap();
+            assert_eq!(current_version(&conn).unwrap(), version);
+
+            let timestamp = if version < 6 {
+                "'2026-01-01 10:00:00'".to_string()
+            } else {
+                TIMESTAMP_MS.to_string()
+            };
+            let (columns, values) = if version < 2 {
+                ("", "")
+            } else {
+                (", final_text", ", 'hello'")
+            };
+            conn.execute_batch(&format!(
+                "INSERT INTO inputs (timestamp, app_name, content{}) VALUES ({}, 'Editor', 'hello'{})",
+                columns, timestamp, values
+            ))
+            .unwrap();
+            if version >= 3 {
+                conn.execute_batch("INSERT INTO key_events (input_id, time_ms, action, key_code) VALUES (1, 5, 'press', 'KeyH')")
+                    .unwrap();
+            }
+            if version >= 4 {
+                conn.execute_batch(
+                    "INSERT INTO shortcuts (input_id, shortcut) VALUES (1, 'Ctrl+S')",
+                )
+                .unwrap();
+            }
+            check_upgrade(&mut conn);
+        }
+    }
+
+    #[test]
+    fn keeps_encrypted_rows_out_of_fts() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        run(&mut conn, &MIGRATIONS[..9]).unwrap();
+        conn.execute_batch(
+            "
+            INSERT INTO inputs (timestamp, app_name, content, final_text) VALUES (1, 'Editor', 'hello', 'hello');
+            INSERT INTO inputs (timestamp, app_name, content, final_text) VALUES (2, 'Editor', 'enc1:c2VjcmV0', 'enc1:c2VjcmV0');
+            ",
+        )
+        .unwrap();
+        assert_eq!(fts_matches(&conn, "enc1:"), 1);
+
+        migrate(&mut conn).unwrap();
+        assert_eq!(fts_matches(&conn, "enc1:"), 0);
+        assert_eq!(fts_matches(&conn, "hello"), 1);
+
+        // Encrypting a row drops it from the index; new ciphertext stays out
+        conn.execute_batch(
+            "
+            UPDATE inputs SET content = 'enc1:aGVsbG8', final_text = 'enc1:aGVsbG8' WHERE id = 1;
+            INSERT INTO inputs (timestamp, app_name, content, final_text) VALUES (3, 'Editor', 'enc1:d29ybGQ', 'enc1:d29ybGQ');
+            INSERT INTO inputs (timestamp, app_name, content, final_text) VALUES (4, 'Editor', 'world', 'world');
+            ",
+        )
+        .unwrap();
+        assert_eq!(fts_matches(&conn, "enc1:"), 0);
+        assert_eq!(fts_matches(&conn, "hello"), 0);
+        assert_eq!(fts_matches(&conn, "world"), 1);
+        conn.execute_batch(
+            "INSERT INTO inputs_fts(inputs_fts, rank) VALUES ('integrity-check', 0)",
+        )
+        .unwrap();
+
+        // Deleting rows that were never indexed leaves the index intact
+        conn.execute_batch("DELETE FROM inputs").unwrap();
+        assert_eq!(fts_matches(&conn, "world"), 0);
+        conn.execute_batch("INSERT INTO inputs_fts(inputs_fts) VALUES ('integrity-check')")
+            .unwrap();
+    }
+
+    #[test]
+    fn failing_step_rolls_back() {
+        let steps = [
+            Migration {
+                version: 1,
+                description: "create inputs and settings",
+                up: create_base_tables,
+            },
+            Migration {
+                version: 2,
+                description: "fails halfway",
+                up: |conn| {
+                    conn.execute_batch("CREATE TABLE partial (x); INSERT INTO missing VALUES (1);")
+                },
+            },
+        ];
+        let mut conn = Connection::open_in_memory().unwrap();
+        assert!(run(&mut conn, &steps).is_err());
+        assert_eq!(current_version(&conn).unwrap(), 1);
+        assert!(!schema(&conn).iter().any(|(_, name, _)| name == "partial"));
+    }
+
+    #[test]
+    fn refuses_newer_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "user_version", latest_version() + 1)
+            .unwrap();
+        assert!(migrate(&mut conn).is_err());
+    }
+}
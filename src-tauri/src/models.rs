@@ -1,5 +1,10 @@
 use chrono::{DateTime, Utc};
+use rdev::Key;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::input::KeyAction;
+use crate::rules::CaptureRule;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct InputRecord {
@@ -8,8 +13,25 @@ pub struct InputRecord {
     pub app_name: String,
     pub window_title: Option<String>,
     pub content: String,
+    /// `content` with Backspace/Delete and cursor movement applied
+    #[serde(default)]
+    pub final_text: String,
     pub key_count: i32,
+    /// Content holds text committed by an input method rather than raw keystrokes
+    #[serde(default)]
+    pub composed: bool,
+    /// Time from the first to the last key of the record, in milliseconds;
+    /// unknown for records saved before it was tracked
+    #[serde(default)]
+    pub duration_ms: Option<i64>,
     pub created_at: Option<DateTime<Utc>>,
+    /// Excerpt of `final_text` around a search match, with matches wrapped in
+    /// `search::MATCH_START` and `search::MATCH_END`
+    #[serde(default)]
+    pub snippet: Option<String>,
+    /// FTS5 relevance of a search match; lower is more relevant
+    #[serde(default)]
+    pub rank: Option<f64>,
 }
 
 impl InputRecord {
@@ -19,13 +41,70 @@ impl InputRecord {
             timestamp: Utc::now(),
             app_name,
             window_title,
+            final_text: content.clone(),
             content,
             key_count: 1,
+            composed: false,
+            duration_ms: None,
             created_at: None,
+            snippet: None,
+            rank: None,
         }
     }
 }
 
+/// A finished record waiting to be written, with the keys and shortcuts
+/// typed into it.
+#[derive(Debug, Clone)]
+pub struct PendingRecord {
+    pub record: InputRecord,
+    pub key_events: Vec<KeyStroke>,
+    pub shortcuts: Vec<String>,
+    /// Presses to add to `key_frequency`
+    pub key_frequency: HashMap<KeyTally, i64>,
+}
+
+/// Something `key_frequency` counts presses of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum KeyTally {
+    /// A key other than a modifier
+    Key(Key),
+    /// A key pressed straight after another one in the same record
+    Bigram(Key, Key),
+    /// A modifier held down while a key was pressed
+    Modifier(Key),
+}
+
+/// Health of the background record writer
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct WriterMetrics {
+    /// Records queued but not yet written
+    pub queue_depth: usize,
+    /// Records dropped because the queue was full
+    pub dropped: u64,
+    pub written: u64,
+    /// Records that could not be written, even on their own
+    pub failed: u64,
+    pub batches: u64,
+    /// Time spent writing the last batch, in microseconds
+    pub last_write_us: u64,
+    pub avg_write_us: u64,
+    pub max_write_us: u64,
+}
+
+/// A single key press or release, kept in `key_events` when enabled in the
+/// settings and linked to the record it was typed into.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyStroke {
+    pub input_id: Option<i64>,
+    /// Unix time in milliseconds
+    pub time_ms: i64,
+    pub action: KeyAction,
+    pub key: Key,
+    /// Held modifiers, as `input::Modifiers::bits`
+    pub modifiers: u8,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct AppStats {
     pub app_name: String,
@@ -33,6 +112,14 @@ pub struct AppStats {
     pub record_count: i64,
 }
 
+/// How often a shortcut was used in an app
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShortcutStats {
+    pub app_name: String,
+    pub shortcut: String,
+    pub count: i64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DailyStats {
     pub date: String,
@@ -41,8 +128,164 @@ pub struct DailyStats {
     pub app_stats: Vec<AppStats>,
 }
 
+/// Key and record counts over time, for trend charts
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Stats {
+    /// Start of each bucket, RFC 3339 in the configured time zone
+    pub buckets: Vec<String>,
+    /// Busiest first
+    pub series: Vec<StatsSeries>,
+}
+
+/// Counts for one group, one entry per bucket of `Stats::buckets`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsSeries {
+    /// App name or window title; `None` for the totals, or records without a title
+    pub group: Option<String>,
+    pub key_count: i64,
+    pub record_count: i64,
+    pub key_counts: Vec<i64>,
+    pub record_counts: Vec<i64>,
+}
+
+/// Typing speed over a date range. Speeds count the characters of the edited
+/// text over the time from a record's first key to its last; a word is five
+/// characters.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TypingSpeed {
+    pub wpm: f64,
+    pub cpm: f64,
+    /// Characters and milliseconds of typing the speeds are computed from
+    pub chars: i64,
+    pub typing_ms: i64,
+    /// Records long enough to be timed
+    pub records: i64,
+    pub longest_burst: Option<Burst>,
+    /// Most typing time first
+    pub apps: Vec<AppSpeed>,
+}
+
+/// One uninterrupted stretch of typing. Records end when typing pauses for
+/// the merge interval, so each timed record is a burst.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Burst {
+    pub input_id: i64,
+    pub app_name: String,
+    pub timestamp: DateTime<Utc>,
+    pub duration_ms: i64,
+    pub chars: i64,
+    pub cpm: f64,
+}
+
+/// Typing speed in one app, with the spread of its per-record speeds
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppSpeed {
+    pub app_name: String,
+    pub wpm: f64,
+    pub cpm: f64,
+    pub chars: i64,
+    pub typing_ms: i64,
+    pub records: i64,
+    /// Characters per minute of its records, by percentile
+    pub cpm_p25: f64,
+    pub cpm_median: f64,
+    pub cpm_p75: f64,
+    pub cpm_p90: f64,
+    pub cpm_max: f64,
+}
+
+/// How much typing was corrected over a date range: Backspace and Delete
+/// presses as a share of printable keys.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorrectionStats {
+    pub corrections: i64,
+    pub printable: i64,
+    pub ratio: f64,
+    /// Most typing first
+    pub apps: Vec<CorrectionCount>,
+    /// Hours of the day, "00" to "23", in the configured time zone
+    pub hours: Vec<CorrectionCount>,
+    /// One per bucket, labelled with its start
+    pub over_time: Vec<CorrectionCount>,
+    /// Most often corrected first
+    pub words: Vec<CorrectedWord>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorrectionCount {
+    pub label: String,
+    pub corrections: i64,
+    pub printable: i64,
+    pub ratio: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CorrectedWord {
+    pub word: String,
+    pub count: i64,
+}
+
+/// Key presses over a date range, for drawing over a keyboard. Keys are rdev
+/// key names such as `KeyA` or `ShiftLeft`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyHeatmap {
+    /// Presses of keys other than modifiers
+    pub total: i64,
+    /// Most pressed first
+    pub keys: Vec<KeyCount>,
+    /// Presses made while each modifier was held
+    pub modifiers: Vec<KeyCount>,
+    /// Most common first, up to a fixed limit
+    pub bigrams: Vec<BigramCount>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeyCount {
+    pub key: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BigramCount {
+    pub first: String,
+    pub second: String,
+    pub count: i64,
+}
+
+/// Time each app spent in the foreground over a date range, split by day
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActiveTime {
+    /// Start of each day, in the configured time zone
+    pub days: Vec<String>,
+    pub total_ms: i64,
+    /// Most time first
+    pub apps: Vec<AppTime>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppTime {
+    pub app_name: String,
+    pub total_ms: i64,
+    /// One per day
+    pub daily_ms: Vec<i64>,
+}
+
+/// A stretch of typing with no pause longer than the idle timeout
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActivitySession {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub duration_ms: i64,
+    pub records: i64,
+    pub key_count: i64,
+    /// Most keys first
+    pub apps: Vec<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SearchFilter {
+    /// Words, `"phrases"`, `AND`/`OR`/`NOT`, `-word`, parentheses and
+    /// `app:`/`title:`/`text:` qualifiers; see `search::Query`
     pub query: Option<String>,
     pub app_name: Option<String>,
     pub start_date: Option<String>,
@@ -64,11 +307,71 @@ impl Default for SearchFilter {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RecordingState {
+    Stopped,
+    Recording,
+    Paused,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordingStatus {
+    pub state: RecordingState,
+    pub paused_until: Option<DateTime<Utc>>,
+    /// A password field or prompt has focus, so keys are being dropped
+    pub secure_input: bool,
+    /// Keystrokes dropped in secure contexts since launch
+    pub suppressed_keys: u64,
+    /// The system input hook is installed. Once started it stays installed
+    /// until the app exits, so while stopped or paused keys still arrive and
+    /// are discarded unrecorded.
+    pub listener_running: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DatabaseStatus {
+    /// The database file is encrypted with SQLCipher
+    pub encrypted: bool,
+    /// Typed text and window titles are encrypted per record
+    pub field_encrypted: bool,
+    /// Encrypted and not unlocked yet, so no command can read it
+    pub locked: bool,
+    /// This build includes SQLCipher
+    pub encryption_available: bool,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, Default)]
 pub struct Settings {
     pub excluded_apps: Vec<String>,
     pub merge_interval_ms: u64,
     pub auto_start: bool,
+    /// Layout used to decode keys when the OS does not report their text: "auto"
+    /// for the system's active layout, or "us", "de", "fr"
+    pub keyboard_layout: String,
+    /// Also store every key press and release in `key_events`
+    #[serde(default)]
+    pub record_key_events: bool,
+    /// What to do with detected secrets: "off", "mask", "hash" or "drop"
+    #[serde(default)]
+    pub redaction_mode: String,
+    /// Extra regular expressions to redact, on top of the built-in detectors
+    #[serde(default)]
+    pub redaction_patterns: Vec<String>,
+    /// Include/exclude rules on app name, window title or executable path
+    #[serde(default)]
+    pub capture_rules: Vec<CaptureRule>,
+    /// IANA time zone for dates and daily statistics; empty for the system zone
+    #[serde(default)]
+    pub time_zone: String,
+    /// Seconds without a key press after which the user counts as away:
+    /// foreground time stops and a new activity session begins
+    #[serde(default = "default_idle_timeout_secs")]
+    pub idle_timeout_secs: u64,
+}
+
+fn default_idle_timeout_secs() -> u64 {
+    300
 }
 
 impl Settings {
@@ -77,6 +380,13 @@ impl Settings {
             excluded_apps: vec![],
             merge_interval_ms: 500,
             auto_start: false,
+            keyboard_layout: "auto".to_string(),
+            record_key_events: false,
+            redaction_mode: "mask".to_string(),
+            redaction_patterns: vec![],
+            capture_rules: vec![],
+            time_zone: String::new(),
+            idle_timeout_secs: default_idle_timeout_secs(),
         }
     }
 }
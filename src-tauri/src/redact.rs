@@ -0,0 +1,366 @@
+use std::io::Write;
+use std::path::Path;
+
+use hmac::{Hmac, Mac};
+use once_cell::sync::{Lazy, OnceCell};
+use regex::Regex;
+use sha2::Sha256;
+
+/// What happens to text that a detector flags.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RedactionMode {
+    /// Store records untouched
+    Off,
+    /// Replace every character of the match with `*`
+    #[default]
+    Mask,
+    /// Replace the match with a short digest, so repeats can still be told apart
+    Hash,
+    /// Do not store the record at all
+    Drop,
+}
+
+impl RedactionMode {
+    /// Parse a settings value, falling back to masking for unknown names.
+    pub fn from_name(name: &str) -> Self {
+        match name.to_ascii_lowercase().as_str() {
+            "off" => Self::Off,
+            "hash" => Self::Hash,
+            "drop" => Self::Drop,
+            _ => Self::Mask,
+        }
+    }
+}
+
+/// Card-number candidates: 13 to 19 digits, optionally grouped by spaces or dashes
+static CARD: Lazy<Regex> = Lazy::new(|| Regex::new(r"\b\d(?:[ -]?\d){12,18}\b").unwrap());
+
+/// Well-known API key and token prefixes
+static TOKEN: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(concat!(
+        r"\b(?:sk|rk|pk)_(?:live|test)_[0-9A-Za-z]{10,}",
+        r"|\bsk-[0-9A-Za-z_-]{20,}",
+        r"|\bgh[pousr]_[0-9A-Za-z]{30,}",
+        r"|\bgithub_pat_[0-9A-Za-z_]{20,}",
+        r"|\bglpat-[0-9A-Za-z_-]{20,}",
+        r"|\bxox[abposr]-[0-9A-Za-z-]{10,}",
+        r"|\bAKIA[0-9A-Z]{16}\b",
+        r"|\bAIza[0-9A-Za-z_-]{35}",
+        r"|-----BEGIN [A-Z ]*PRIVATE KEY-----",
+    ))
+    .unwrap()
+});
+
+static EMAIL: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}").unwrap()
+});
+
+/// Key for `Hash` digests, so a digest cannot be matched by hashing every
+/// possible card number. Set once per install from the settings table.
+static HASH_KEY: OnceCell<[u8; 32]> = OnceCell::new();
+
+/// The result of running a record through the redactor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Redaction {
+    Clean,
+    Replaced { content: String, final_text: String },
+    Drop,
+}
+
+/// Finds sensitive text with the built-in detectors (Luhn-valid card numbers,
+/// API tokens, email addresses) and the user's own patterns.
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    mode: RedactionMode,
+    custom: Vec<Regex>,
+}
+
+impl Redactor {
+    /// Build a redactor. Patterns that fail to compile are skipped; use
+    /// `validate_patterns` to report them to the user first.
+    pub fn new(mode: RedactionMode, patterns: &[String]) -> Self {
+        let custom = patterns
+            .iter()
+            .filter_map(|p| match Regex::new(p) {
+                Ok(re) => Some(re),
+                Err(e) => {
+                    eprintln!("Ignoring invalid redaction pattern {:?}: {}", p, e);
+                    None
+                }
+            })
+            .collect();
+        Self { mode, custom }
+    }
+
+    /// Redact a record's raw keystrokes and final text. Editing keys can split
+    /// a secret across `[Backspace]`-style tokens where no detector sees it,
+    /// so whenever the final text has findings the raw stream is replaced by
+    /// the redacted final text.
+    pub fn redact_record(&self, content: &str, final_text: &str) -> Redaction {
+        if self.mode == RedactionMode::Off {
+            return Redaction::Clean;
+        }
+
+        let content_spans = self.find(content);
+        let final_spans = self.find(final_text);
+        if content_spans.is_empty() && final_spans.is_empty() {
+            return Redaction::Clean;
+        }
+        if self.mode == RedactionMode::Drop {
+            return Redaction::Drop;
+        }
+
+        let final_text = self.replace(final_text, &final_spans);
+        let content = if !final_spans.is_empty() {
+            final_text.clone()
+        } else {
+            self.replace(content, &content_spans)
+        };
+        Redaction::Replaced {
+            content,
+            final_text,
+        }
+    }
+
+    /// Sorted, non-overlapping byte ranges of everything a detector flags
+    fn find(&self, text: &str) -> Vec<(usize, usize)> {
+        let mut spans: Vec<(usize, usize)> = CARD
+            .find_iter(text)
+            .filter(|m| luhn_valid(m.as_str()))
+            .chain(TOKEN.find_iter(text))
+            .chain(EMAIL.find_iter(text))
+            .chain(self.custom.iter().flat_map(|re| re.find_iter(text)))
+            .filter(|m| !m.is_empty())
+            .map(|m| (m.start(), m.end()))
+            .collect();
+        spans.sort_unstable();
+
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(spans.len());
+        for (start, end) in spans {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        merged
+    }
+
+    fn replace(&self, text: &str, spans: &[(usize, usize)]) -> String {
+        let mut out = String::with_capacity(text.len());
+        let mut pos = 0;
+        for &(start, end) in spans {
+            out.push_str(&text[pos..start]);
+            let secret = &text[start..end];
+            match self.mode {
+                RedactionMode::Hash => match hash_key() {
+                    Some(key) => out.push_str(&format!("[{}]", digest(key, secret))),
+                    None => out.extend(secret.chars().map(|_| '*')),
+                },
+                _ => out.extend(secret.chars().map(|_| '*')),
+            }
+            pos = end;
+        }
+        out.push_str(&text[pos..]);
+        out
+    }
+}
+
+/// Use `key` for `Hash` digests. Only the first call has any effect.
+pub fn set_hash_key(key: [u8; 32]) {
+    let _ = HASH_KEY.set(key);
+}
+
+/// Read the digest key from `path`, creating it on first use. The key is kept
+/// out of the database, so someone holding only the database cannot check
+/// guesses at short values such as card numbers against the digests.
+pub fn load_hash_key(path: &Path) -> Result<[u8; 32], String> {
+    match std::fs::read_to_string(path) {
+        Ok(stored) => {
+            return hex::decode(stored.trim())
+                .ok()
+                .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                .ok_or_else(|| format!("{}: invalid key", path.display()));
+        }
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            return Err(format!("{}: {}", path.display(), e));
+        }
+        Err(_) => {}
+    }
+
+    let mut key = [0u8; 32];
+    getrandom::getrandom(&mut key).map_err(|e| e.to_string())?;
+
+    // Only the owner may read it; written aside first, so a crash never
+    // leaves a truncated key
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options
+        .open(&tmp)
+        .and_then(|mut file| file.write_all(hex::encode(key).as_bytes()))
+        .and_then(|_| std::fs::rename(&tmp, path))
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    Ok(key)
+}
+
+/// The digest key, or a random one for this run if none was set. `None`
+/// only when the system has no random source, and then secrets are masked.
+fn hash_key() -> Option<&'static [u8; 32]> {
+    HASH_KEY
+        .get_or_try_init(|| {
+            let mut key = [0u8; 32];
+            getrandom::getrandom(&mut key).map(|_| key)
+        })
+        .ok()
+}
+
+/// Check user patterns before they are saved.
+pub fn validate_patterns(patterns: &[String]) -> Result<(), String> {
+    for pattern in patterns {
+        Regex::new(pattern).map_err(|e| format!("Invalid pattern {:?}: {}", pattern, e))?;
+    }
+    Ok(())
+}
+
+fn luhn_valid(candidate: &str) -> bool {
+    let digits: Vec<u32> = candidate.chars().filter_map(|c| c.to_digit(10)).collect();
+    if !(13..=19).contains(&digits.len()) {
+        return false;
+    }
+
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| {
+            if i % 2 == 1 {
+                let doubled = d * 2;
+                if doubled > 9 {
+                    doubled - 9
+                } else {
+                    doubled
+                }
+            } else {
+                d
+            }
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// First 8 hex digits of the HMAC-SHA256 of `secret` under `key`
+fn digest(key: &[u8], secret: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(secret.as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .take(4)
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redact(mode: RedactionMode, content: &str, final_text: &str) -> Redaction {
+        Redactor::new(mode, &[]).redact_record(content, final_text)
+    }
+
+    #[test]
+    fn edited_secret_replaces_content_even_with_other_findings() {
+        // An email typed and deleted, then a card number with one correction:
+        // the raw stream only shows the email, the final text only the card
+        let content = format!(
+            "me@example.com{}4111 1111 1111 1112[Backspace]1",
+            "[Backspace]".repeat(14)
+        );
+        let final_text = "4111 1111 1111 1111";
+
+        match redact(RedactionMode::Mask, &content, final_text) {
+            Redaction::Replaced {
+                content,
+                final_text,
+            } => {
+                assert_eq!(final_text, "*******************");
+                assert_eq!(content, final_text);
+            }
+            other => panic!("expected a replacement, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn secret_only_in_content_is_masked_there() {
+        let content = format!("me@example.com{}", "[Backspace]".repeat(12));
+        match redact(RedactionMode::Mask, &content, "me") {
+            Redaction::Replaced {
+                content,
+                final_text,
+            } => {
+                assert_eq!(
+                    content,
+                    format!("**************{}", "[Backspace]".repeat(12))
+                );
+                assert_eq!(final_text, "me");
+            }
+            other => panic!("expected a replacement, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn clean_and_drop() {
+        assert_eq!(
+            redact(RedactionMode::Mask, "hello", "hello"),
+            Redaction::Clean
+        );
+        assert_eq!(
+            redact(RedactionMode::Off, "4111111111111111", "4111111111111111"),
+            Redaction::Clean
+        );
+        assert_eq!(
+            redact(RedactionMode::Drop, "4111111111111111", "4111111111111111"),
+            Redaction::Drop
+        );
+        // Not Luhn-valid
+        assert_eq!(
+            redact(RedactionMode::Mask, "4111111111111112", "4111111111111112"),
+            Redaction::Clean
+        );
+    }
+
+    #[test]
+    fn digest_matches_rfc_4231() {
+        assert_eq!(digest(b"Jefe", "what do ya want for nothing?"), "5bdcc146");
+    }
+
+    #[test]
+    fn digest_depends_on_key() {
+        let card = "4111 1111 1111 1111";
+        assert_eq!(digest(&[1; 32], card), digest(&[1; 32], card));
+        assert_ne!(digest(&[1; 32], card), digest(&[2; 32], card));
+        assert_eq!(digest(&[1; 32], card).len(), 8);
+    }
+
+    #[test]
+    fn hash_key_is_created_once() {
+        let path = std::env::temp_dir().join(format!("arkinput-redact-{}.key", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let key = load_hash_key(&path).unwrap();
+        assert_eq!(load_hash_key(&path).unwrap(), key);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::write(&path, "not a key").unwrap();
+        assert!(load_hash_key(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
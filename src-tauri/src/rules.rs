@@ -0,0 +1,379 @@
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime, Utc};
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use crate::timezone::Zone;
+use crate::window::WindowInfo;
+
+/// Which part of the focused window a rule looks at.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleTarget {
+    App,
+    Title,
+    /// Full path of the executable; not known on every platform
+    Path,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PatternKind {
+    /// `*` and `?` wildcards over the whole value, ignoring case
+    #[default]
+    Glob,
+    /// A regular expression that may match anywhere in the value
+    Regex,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RuleAction {
+    /// Only record windows matched by some include rule
+    Include,
+    /// Never record matching windows
+    Exclude,
+}
+
+/// A daily window such as 18:00–09:00, in the configured time zone. Windows
+/// whose end is before their start run past midnight.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Schedule {
+    /// "HH:MM"
+    pub start: String,
+    /// "HH:MM"
+    pub end: String,
+    /// ISO weekdays (1 = Monday) the window starts on; empty means every day
+    #[serde(default)]
+    pub days: Vec<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CaptureRule {
+    pub target: RuleTarget,
+    pub pattern: String,
+    #[serde(default)]
+    pub kind: PatternKind,
+    pub action: RuleAction,
+    /// Only in effect during this window
+    #[serde(default)]
+    pub schedule: Option<Schedule>,
+    /// No longer in effect after this time
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug)]
+struct DailyWindow {
+    start: NaiveTime,
+    end: NaiveTime,
+    days: Vec<u32>,
+}
+
+#[derive(Debug)]
+struct Compiled {
+    target: RuleTarget,
+    action: RuleAction,
+    matcher: Regex,
+    schedule: Option<DailyWindow>,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+/// The compiled rule list the listener consults for every key press.
+#[derive(Debug, Clone, Default)]
+pub struct RuleSet {
+    rules: Arc<Vec<Compiled>>,
+    excluded_apps: Vec<String>,
+}
+
+impl RuleSet {
+    /// Compile `rules`, plus the plain `excluded_apps` list (exact app names,
+    /// ignoring case). Rules that do not compile are skipped; use `validate`
+    /// to report them to the user first.
+    pub fn new(rules: &[CaptureRule], excluded_apps: &[String]) -> Self {
+        let compiled = rules
+            .iter()
+            .filter_map(|rule| match compile(rule) {
+                Ok(compiled) => Some(compiled),
+                Err(e) => {
+                    eprintln!("Ignoring capture rule: {}", e);
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            rules: Arc::new(compiled),
+            excluded_apps: excluded_apps.to_vec(),
+        }
+    }
+
+    pub fn validate(rules: &[CaptureRule]) -> Result<(), String> {
+        rules.iter().try_for_each(|rule| compile(rule).map(|_| ()))
+    }
+
+    /// Whether keys typed into `window` at `now` may be recorded, with
+    /// schedules read in `zone`. Any matching exclude rule wins; when include
+    /// rules are in effect, the window must match one of them.
+    pub fn allows(&self, window: &WindowInfo, now: DateTime<Utc>, zone: Zone) -> bool {
+        if self
+            .excluded_apps
+            .iter()
+            .any(|a| a.eq_ignore_ascii_case(&window.app_name))
+        {
+            return false;
+        }
+
+        let local = zone.local_time(now.timestamp_millis());
+        let mut includes = false;
+        let mut included = false;
+        for compiled in self.rules.iter().filter(|c| c.in_effect(now, local)) {
+            let matched = compiled.matches(window);
+            match compiled.action {
+                RuleAction::Exclude if matched => return false,
+                RuleAction::Exclude => {}
+                RuleAction::Include => {
+                    includes = true;
+                    included |= matched;
+                }
+            }
+        }
+        !includes || included
+    }
+}
+
+impl Compiled {
+    /// `local` is `now` as wall-clock time in the configured zone
+    fn in_effect(&self, now: DateTime<Utc>, local: NaiveDateTime) -> bool {
+        if self.expires_at.is_some_and(|at| now >= at) {
+            return false;
+        }
+
+        let window = match self.schedule {
+            Some(ref window) => window,
+            None => return true,
+        };
+        let on_day = |date: NaiveDate| {
+            window.days.is_empty() || window.days.contains(&date.weekday().number_from_monday())
+        };
+
+        let time = local.time();
+        let today = local.date();
+        if window.start <= window.end {
+            on_day(today) && time >= window.start && time < window.end
+        } else if time >= window.start {
+            on_day(today)
+        } else {
+            // Early-morning part of a window that started the day before
+            time < window.end && today.pred_opt().is_some_and(on_day)
+        }
+    }
+
+    fn matches(&self, window: &WindowInfo) -> bool {
+        let value = match self.target {
+            RuleTarget::App => Some(window.app_name.as_str()),
+            RuleTarget::Title => window.window_title.as_deref(),
+            RuleTarget::Path => window.exe_path.as_deref(),
+        };
+        value.is_some_and(|v| self.matcher.is_match(v))
+    }
+}
+
+fn compile(rule: &CaptureRule) -> Result<Compiled, String> {
+    let matcher = match rule.kind {
+        PatternKind::Glob => RegexBuilder::new(&glob_to_regex(&rule.pattern))
+            .case_insensitive(true)
+            .build(),
+        PatternKind::Regex => Regex::new(&rule.pattern),
+    }
+    .map_err(|e| format!("invalid pattern {:?}: {}", rule.pattern, e))?;
+
+    let schedule = match rule.schedule {
+        Some(ref schedule) => {
+            if let Some(day) = schedule.days.iter().find(|d| !(1..=7).contains(*d)) {
+                return Err(format!("invalid weekday {}, expected 1 (Monday) to 7 (Sunday)", day));
+            }
+            Some(DailyWindow {
+                start: parse_time(&schedule.start)?,
+                end: parse_time(&schedule.end)?,
+                days: schedule.days.clone(),
+            })
+        }
+        None => None,
+    };
+
+    Ok(Compiled {
+        target: rule.target,
+        action: rule.action,
+        matcher,
+        schedule,
+        expires_at: rule.expires_at,
+    })
+}
+
+fn parse_time(s: &str) -> Result<NaiveTime, String> {
+    NaiveTime::parse_from_str(s, "%H:%M").map_err(|_| format!("invalid time {:?}, expected HH:MM", s))
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::from("^");
+    for c in glob.chars() {
+        match c {
+            '*' => re.push_str(".*"),
+            '?' => re.push('.'),
+            _ => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+    re.push('$');
+    re
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(app_name: &str, title: &str) -> WindowInfo {
+        WindowInfo {
+            app_name: app_name.to_string(),
+            window_title: Some(title.to_string()),
+            exe_path: None,
+        }
+    }
+
+    fn rule(target: RuleTarget, pattern: &str, action: RuleAction) -> CaptureRule {
+        CaptureRule {
+            target,
+            pattern: pattern.to_string(),
+            kind: PatternKind::Glob,
+            action,
+            schedule: None,
+            expires_at: None,
+        }
+    }
+
+    fn scheduled(start: &str, end: &str, days: &[u32]) -> CaptureRule {
+        CaptureRule {
+            schedule: Some(Schedule {
+                start: start.to_string(),
+                end: end.to_string(),
+                days: days.to_vec(),
+            }),
+            ..rule(RuleTarget::App, "*", RuleAction::Exclude)
+        }
+    }
+
+    fn at(rfc3339: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(rfc3339).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn excludes_win_over_includes() {
+        let rules = RuleSet::new(
+            &[
+                rule(RuleTarget::App, "code*", RuleAction::Include),
+                rule(RuleTarget::App, "firefox", RuleAction::Include),
+                CaptureRule {
+                    kind: PatternKind::Regex,
+                    ..rule(RuleTarget::Title, r"(?i)private browsing", RuleAction::Exclude)
+                },
+            ],
+            &["Slack".to_string()],
+        );
+        let now = at("2024-05-01T12:00:00Z");
+        let zone = Zone::parse("UTC").unwrap();
+
+        assert!(rules.allows(&window("Code - Insiders", "main.rs"), now, zone));
+        assert!(rules.allows(&window("Firefox", "Docs"), now, zone));
+        assert!(!rules.allows(&window("Firefox", "Docs — Private Browsing"), now, zone));
+        assert!(!rules.allows(&window("Terminal", "~"), now, zone));
+        assert!(!rules.allows(&window("slack", ""), now, zone));
+        assert!(RuleSet::default().allows(&window("Terminal", "~"), now, zone));
+    }
+
+    #[test]
+    fn expired_rules_stop_applying() {
+        let rules = RuleSet::new(
+            &[CaptureRule {
+                expires_at: Some(at("2024-05-01T12:00:00Z")),
+                ..rule(RuleTarget::App, "*", RuleAction::Exclude)
+            }],
+            &[],
+        );
+        let zone = Zone::default();
+        assert!(!rules.allows(&window("Editor", ""), at("2024-05-01T11:59:59Z"), zone));
+        assert!(rules.allows(&window("Editor", ""), at("2024-05-01T12:00:00Z"), zone));
+    }
+
+    #[test]
+    fn schedules_follow_the_configured_zone() {
+        // 18:00–09:00 starting on Fridays, in Shanghai (UTC+8)
+        let rules = RuleSet::new(&[scheduled("18:00", "09:00", &[5])], &[]);
+        let shanghai = Zone::parse("Asia/Shanghai").unwrap();
+        let utc = Zone::parse("UTC").unwrap();
+        let editor = window("Editor", "");
+
+        let cases = [
+            // Friday 2024-05-03 17:59 and 18:00 in Shanghai
+            ("2024-05-03T09:59:00Z", true),
+            ("2024-05-03T10:00:00Z", false),
+            // Saturday 08:59 and 09:00, the part of Friday's window after midnight
+            ("2024-05-04T00:59:00Z", false),
+            ("2024-05-04T01:00:00Z", true),
+            // Saturday 18:00 is not a day the window starts on
+            ("2024-05-04T10:00:00Z", true),
+        ];
+        for (instant, allowed) in cases {
+            assert_eq!(rules.allows(&editor, at(instant), shanghai), allowed, "{}", instant);
+        }
+
+        // The same instants read in UTC fall elsewhere in the window
+        assert!(rules.allows(&editor, at("2024-05-03T10:00:00Z"), utc));
+        assert!(!rules.allows(&editor, at("2024-05-03T18:00:00Z"), utc));
+    }
+
+    #[test]
+    fn daytime_schedule_every_day() {
+        let rules = RuleSet::new(&[scheduled("09:00", "17:30", &[])], &[]);
+        let zone = Zone::parse("Europe/Berlin").unwrap();
+        let editor = window("Editor", "");
+
+        // Summer time, UTC+2
+        assert!(rules.allows(&editor, at("2024-07-07T06:59:00Z"), zone));
+        assert!(!rules.allows(&editor, at("2024-07-07T07:00:00Z"), zone));
+        assert!(!rules.allows(&editor, at("2024-07-07T15:29:00Z"), zone));
+        assert!(rules.allows(&editor, at("2024-07-07T15:30:00Z"), zone));
+    }
+
+    #[test]
+    fn validate_reports_bad_rules() {
+        assert!(RuleSet::validate(&[scheduled("18:00", "09:00", &[1, 7])]).is_ok());
+
+        for days in [&[0][..], &[8], &[1, 9]] {
+            let err = RuleSet::validate(&[scheduled("18:00", "09:00", days)]).unwrap_err();
+            assert!(err.contains("weekday"), "{}", err);
+        }
+        assert!(RuleSet::validate(&[scheduled("25:00", "09:00", &[])]).is_err());
+        assert!(RuleSet::validate(&[scheduled("9am", "09:00", &[])]).is_err());
+        assert!(RuleSet::validate(&[CaptureRule {
+            kind: PatternKind::Regex,
+            ..rule(RuleTarget::Title, "(", RuleAction::Exclude)
+        }])
+        .is_err());
+
+        // Invalid rules are skipped rather than failing the whole set
+        let rules = RuleSet::new(&[scheduled("18:00", "09:00", &[0])], &[]);
+        assert!(rules.allows(&window("Editor", ""), at("2024-05-03T20:00:00Z"), Zone::default()));
+    }
+
+    #[test]
+    fn globs_match_the_whole_value() {
+        let re = RegexBuilder::new(&glob_to_regex("*.rs - V?m"))
+            .case_insensitive(true)
+            .build()
+            .unwrap();
+        assert!(re.is_match("main.rs - Vim"));
+        assert!(re.is_match("MAIN.RS - vim"));
+        assert!(!re.is_match("main.rs - Vim (1)"));
+        assert!(!re.is_match("mainXrs - Vim"));
+    }
+}
@@ -0,0 +1,532 @@
+use crate::models::InputRecord;
+
+/// Marks the start and end of a match in snippets. Control characters are
+/// used so that nothing a user typed can be mistaken for a marker.
+pub const MATCH_START: char = '\u{2}';
+pub const MATCH_END: char = '\u{3}';
+
+/// The FTS5 trigram tokenizer cannot match anything shorter than this
+const MIN_INDEXED_CHARS: usize = 3;
+
+/// Characters of context kept on each side of the first match in a snippet
+const SNIPPET_CONTEXT: usize = 24;
+
+/// A record field a term can be limited to, as in `app:code`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Text,
+    Title,
+    App,
+}
+
+impl Field {
+    fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "text" | "content" => Some(Self::Text),
+            "title" => Some(Self::Title),
+            "app" => Some(Self::App),
+            _ => None,
+        }
+    }
+
+    /// Column name in `inputs` and `inputs_fts`
+    fn column(self) -> &'static str {
+        match self {
+            Self::Text => "final_text",
+            Self::Title => "window_title",
+            Self::App => "app_name",
+        }
+    }
+}
+
+/// A parsed search query.
+///
+/// The syntax is words and `"quoted phrases"`, combined with `AND` (implied
+/// between terms), `OR`, `NOT` or a leading `-`, and parentheses. `app:`,
+/// `title:` and `text:` limit a term, phrase or group to one field, and a
+/// trailing `*` marks a prefix. Terms match anywhere inside words, like the
+/// old `LIKE` search did, so a prefix needs no special handling.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Query {
+    Term { field: Option<Field>, text: String },
+    And(Vec<Query>),
+    Or(Vec<Query>),
+    Not(Box<Query>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Term(String),
+    Field(Field),
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+}
+
+/// Parse `input`, returning `None` when it has no terms. Never fails:
+/// unbalanced quotes and parentheses are closed at the end of the input.
+pub fn parse(input: &str) -> Option<Query> {
+    let tokens = tokenize(input);
+    let mut parser = Parser {
+        tokens: &tokens,
+        pos: 0,
+    };
+
+    let mut parts = Vec::new();
+    while parser.pos < tokens.len() {
+        if let Some(query) = parser.or() {
+            parts.push(query);
+        }
+        // A stray closing parenthesis ends `or` early; skip it and go on
+        parser.pos += 1;
+    }
+    combine(parts, Query::And)
+}
+
+fn tokenize(input: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' => {
+                chars.next();
+                let phrase: String = chars.by_ref().take_while(|&c| c != '"').collect();
+                tokens.push(Token::Term(phrase));
+            }
+            '-' => {
+                chars.next();
+                if chars.peek().is_some_and(|c| !c.is_whitespace()) {
+                    tokens.push(Token::Not);
+                }
+            }
+            _ => {
+                let mut word = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == '(' || c == ')' || c == '"' {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+                tokens.extend(word_tokens(word));
+            }
+        }
+    }
+    tokens
+}
+
+fn word_tokens(word: String) -> Vec<Token> {
+    match word.as_str() {
+        "AND" => return vec![Token::And],
+        "OR" => return vec![Token::Or],
+        "NOT" => return vec![Token::Not],
+        _ => {}
+    }
+
+    if let Some((name, rest)) = word.split_once(':') {
+        if let Some(field) = Field::from_name(name) {
+            let mut tokens = vec![Token::Field(field)];
+            tokens.extend(
+                word_tokens(rest.to_string())
+                    .into_iter()
+                    .filter(|t| matches!(t, Token::Term(_))),
+            );
+            return tokens;
+        }
+    }
+
+    let text = word.trim_end_matches('*');
+    if text.is_empty() {
+        return Vec::new();
+    }
+    vec![Token::Term(text.to_string())]
+}
+
+struct Parser<'a> {
+    tokens: &'a [Token],
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn or(&mut self) -> Option<Query> {
+        let mut parts = Vec::new();
+        parts.extend(self.and());
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            parts.extend(self.and());
+        }
+        combine(parts, Query::Or)
+    }
+
+    fn and(&mut self) -> Option<Query> {
+        let mut parts = Vec::new();
+        loop {
+            match self.peek() {
+                None | Some(Token::Close) | Some(Token::Or) => break,
+                Some(Token::And) => self.pos += 1,
+                _ => parts.extend(self.unary()),
+            }
+        }
+        combine(parts, Query::And)
+    }
+
+    fn unary(&mut self) -> Option<Query> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.pos += 1;
+                self.unary().map(|q| Query::Not(Box::new(q)))
+            }
+            _ => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Option<Query> {
+        let token = self.peek()?.clone();
+        self.pos += 1;
+        match token {
+            Token::Open => {
+                let query = self.or();
+                if self.peek() == Some(&Token::Close) {
+                    self.pos += 1;
+                }
+                query
+            }
+            Token::Field(field) => self.unary().map(|q| q.with_field(field)),
+            Token::Term(text) if !text.trim().is_empty() => Some(Query::Term { field: None, text }),
+            _ => None,
+        }
+    }
+}
+
+fn combine(mut parts: Vec<Query>, op: fn(Vec<Query>) -> Query) -> Option<Query> {
+    match parts.len() {
+        0 => None,
+        1 => parts.pop(),
+        _ => Some(op(parts)),
+    }
+}
+
+impl Query {
+    /// Limit every term that has no field yet to `field`
+    fn with_field(self, field: Field) -> Self {
+        match self {
+            Self::Term { field: None, text } => Self::Term {
+                field: Some(field),
+                text,
+            },
+            Self::Term { .. } => self,
+            Self::And(parts) => Self::And(parts.into_iter().map(|q| q.with_field(field)).collect()),
+            Self::Or(parts) => Self::Or(parts.into_iter().map(|q| q.with_field(field)).collect()),
+            Self::Not(q) => Self::Not(Box::new(q.with_field(field))),
+        }
+    }
+
+    /// The query as an FTS5 MATCH expression over `inputs_fts`, or `None`
+    /// when FTS5 cannot answer it: a term is too short for the trigram index,
+    /// or a negation has nothing to be subtracted from.
+    pub fn to_fts(&self) -> Option<String> {
+        match self {
+            Self::Term { field, text } => {
+                if text.chars().count() < MIN_INDEXED_CHARS {
+                    return None;
+                }
+                let phrase = format!("\"{}\"", text.replace('"', "\"\""));
+                Some(match field {
+                    Some(field) => format!("{} : {}", field.column(), phrase),
+                    None => phrase,
+                })
+            }
+            Self::And(parts) => {
+                let (negated, positive): (Vec<&Query>, Vec<&Query>) =
+                    parts.iter().partition(|q| matches!(q, Self::Not(_)));
+                if positive.is_empty() {
+                    return None;
+                }
+
+                let mut expr = positive
+                    .iter()
+                    .map(|q| q.to_fts().map(|e| format!("({})", e)))
+                    .collect::<Option<Vec<_>>>()?
+                    .join(" AND ");
+                for q in negated {
+                    if let Self::Not(inner) = q {
+                        expr = format!("({}) NOT ({})", expr, inner.to_fts()?);
+                    }
+                }
+                Some(expr)
+            }
+            Self::Or(parts) => parts
+                .iter()
+                .map(|q| q.to_fts().map(|e| format!("({})", e)))
+                .collect::<Option<Vec<_>>>()
+                .map(|exprs| exprs.join(" OR ")),
+            Self::Not(_) => None,
+        }
+    }
+
+    /// Evaluate the query against a record directly, for when FTS5 cannot:
+    /// encrypted records, or terms too short for the index.
+    pub fn matches(&self, record: &InputRecord) -> bool {
+        match self {
+            Self::Term { field, text } => {
+                let needle = text.to_lowercase();
+                let found = |value: &str| value.to_lowercase().contains(&needle);
+                match field {
+                    Some(Field::Text) => found(&record.final_text),
+                    Some(Field::Title) => record.window_title.as_deref().is_some_and(found),
+                    Some(Field::App) => found(&record.app_name),
+                    None => {
+                        found(&record.final_text)
+                            || record.window_title.as_deref().is_some_and(found)
+                            || found(&record.app_name)
+                    }
+                }
+            }
+            Self::And(parts) => parts.iter().all(|q| q.matches(record)),
+            Self::Or(parts) => parts.iter().any(|q| q.matches(record)),
+            Self::Not(q) => !q.matches(record),
+        }
+    }
+
+    /// Highlight the query's text terms in `text`, around the first match,
+    /// like FTS5's `snippet()` does. `None` when no term occurs in it.
+    pub fn snippet(&self, text: &str) -> Option<String> {
+        let mut terms = Vec::new();
+        self.text_terms(&mut terms);
+
+        // Lowercase char by char, so positions line up with `chars`
+        let chars: Vec<char> = text.chars().collect();
+        let lower: Vec<char> = chars
+            .iter()
+            .map(|c| c.to_lowercase().next().unwrap_or(*c))
+            .collect();
+        let terms: Vec<Vec<char>> = terms
+            .iter()
+            .map(|t| {
+                t.chars()
+                    .map(|c| c.to_lowercase().next().unwrap_or(c))
+                    .collect()
+            })
+            .filter(|t: &Vec<char>| !t.is_empty())
+            .collect();
+
+        let matches_at = |i: usize| {
+            terms
+                .iter()
+                .find(|t| lower[i..].starts_with(t))
+                .map(|t| t.len())
+        };
+        let first = (0..lower.len()).find(|&i| matches_at(i).is_some())?;
+
+        let start = first.saturating_sub(SNIPPET_CONTEXT);
+        let end = (first + SNIPPET_CONTEXT * 2).min(chars.len());
+        let mut snippet = String::new();
+        if start > 0 {
+            snippet.push('…');
+        }
+        let mut i = start;
+        while i < end {
+            match matches_at(i) {
+                Some(len) => {
+                    snippet.push(MATCH_START);
+                    snippet.extend(&chars[i..i + len]);
+                    snippet.push(MATCH_END);
+                    i += len;
+                }
+                None => {
+                    snippet.push(chars[i]);
+                    i += 1;
+                }
+            }
+        }
+        if i < chars.len() {
+            snippet.push('…');
+        }
+        Some(snippet)
+    }
+
+    /// Terms that are not negated and may match the record text
+    fn text_terms<'a>(&'a self, out: &mut Vec<&'a str>) {
+        match self {
+            Self::Term {
+                field: None | Some(Field::Text),
+                text,
+            } => out.push(text),
+            Self::Term { .. } | Self::Not(_) => {}
+            Self::And(parts) | Self::Or(parts) => parts.iter().for_each(|q| q.text_terms(out)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn term(text: &str) -> Query {
+        Query::Term {
+            field: None,
+            text: text.into(),
+        }
+    }
+
+    fn field_term(field: Field, text: &str) -> Query {
+        Query::Term {
+            field: Some(field),
+            text: text.into(),
+        }
+    }
+
+    fn not(query: Query) -> Query {
+        Query::Not(Box::new(query))
+    }
+
+    #[test]
+    fn parses_terms_and_phrases() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("   "), None);
+        assert_eq!(parse("hello"), Some(term("hello")));
+        assert_eq!(
+            parse("hello world"),
+            Some(Query::And(vec![term("hello"), term("world")]))
+        );
+        assert_eq!(parse("\"hello world\""), Some(term("hello world")));
+        assert_eq!(parse("prefix*"), Some(term("prefix")));
+        assert_eq!(parse("*"), None);
+    }
+
+    #[test]
+    fn parses_operators() {
+        assert_eq!(
+            parse("a AND b OR c"),
+            Some(Query::Or(vec![
+                Query::And(vec![term("a"), term("b")]),
+                term("c")
+            ]))
+        );
+        assert_eq!(
+            parse("a (b OR c)"),
+            Some(Query::And(vec![
+                term("a"),
+                Query::Or(vec![term("b"), term("c")])
+            ]))
+        );
+        assert_eq!(
+            parse("a NOT b"),
+            Some(Query::And(vec![term("a"), not(term("b"))]))
+        );
+        assert_eq!(
+            parse("a -b"),
+            Some(Query::And(vec![term("a"), not(term("b"))]))
+        );
+        // Only uppercase words are operators, and a lone dash is nothing
+        assert_eq!(
+            parse("cats or dogs - birds"),
+            Some(Query::And(vec![
+                term("cats"),
+                term("or"),
+                term("dogs"),
+                term("birds")
+            ]))
+        );
+    }
+
+    #[test]
+    fn parses_fields() {
+        assert_eq!(parse("app:code"), Some(field_term(Field::App, "code")));
+        assert_eq!(
+            parse("Title:\"read me\""),
+            Some(field_term(Field::Title, "read me"))
+        );
+        assert_eq!(
+            parse("text:(a OR app:b)"),
+            Some(Query::Or(vec![
+                field_term(Field::Text, "a"),
+                field_term(Field::App, "b")
+            ]))
+        );
+        assert_eq!(
+            parse("-app:code"),
+            Some(not(field_term(Field::App, "code")))
+        );
+        // An unknown field name is part of the term
+        assert_eq!(parse("http://x"), Some(term("http://x")));
+    }
+
+    #[test]
+    fn recovers_from_unbalanced_input() {
+        assert_eq!(parse("\"open phrase"), Some(term("open phrase")));
+        assert_eq!(
+            parse("(a OR b"),
+            Some(Query::Or(vec![term("a"), term("b")]))
+        );
+        assert_eq!(parse("a) b"), Some(Query::And(vec![term("a"), term("b")])));
+        assert_eq!(parse("OR AND ()"), None);
+        assert_eq!(parse("\"\""), None);
+    }
+
+    #[test]
+    fn builds_fts_expressions() {
+        let fts = |input: &str| parse(input).and_then(|q| q.to_fts());
+        assert_eq!(fts("hello"), Some("\"hello\"".into()));
+        // Terms shorter than a trigram are not in the index
+        assert_eq!(fts("hi there"), None);
+        assert_eq!(
+            fts("hello app:code"),
+            Some("(\"hello\") AND (app_name : \"code\")".into())
+        );
+        assert_eq!(
+            fts("hello OR world"),
+            Some("(\"hello\") OR (\"world\")".into())
+        );
+        assert_eq!(
+            fts("hello -world"),
+            Some("((\"hello\")) NOT (\"world\")".into())
+        );
+        assert_eq!(fts("-world"), None);
+    }
+
+    #[test]
+    fn matches_records_directly() {
+        let record = InputRecord::new("Code".into(), Some("main.rs".into()), "fn main() {}".into());
+        let matches = |input: &str| parse(input).unwrap().matches(&record);
+        assert!(matches("MAIN"));
+        assert!(matches("app:co"));
+        assert!(!matches("app:main"));
+        assert!(matches("title:rs fn"));
+        assert!(!matches("main -code"));
+        assert!(matches("missing OR fn"));
+    }
+
+    #[test]
+    fn highlights_snippets() {
+        let query = parse("world app:hello").unwrap();
+        assert_eq!(
+            query.snippet("hello World"),
+            Some(format!("hello {}World{}", MATCH_START, MATCH_END))
+        );
+        assert_eq!(query.snippet("hello"), None);
+
+        let long = format!("{}needle{}", "a".repeat(40), "b".repeat(60));
+        let snippet = parse("needle").unwrap().snippet(&long).unwrap();
+        assert!(snippet.starts_with('…') && snippet.ends_with('…'));
+        assert!(snippet.contains(&format!("{}needle{}", MATCH_START, MATCH_END)));
+    }
+}
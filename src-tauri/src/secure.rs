@@ -0,0 +1,327 @@
+use std::path::{Path, PathBuf};
+
+use once_cell::sync::Lazy;
+use rdev::Key;
+use regex::Regex;
+
+use crate::window::WindowInfo;
+
+/// What a secure-context rule gets to see for each key press, and for each
+/// text an input method commits.
+pub struct SecureContext<'a> {
+    pub window: &'a WindowInfo,
+    /// `Key::Unknown(0)` for text committed by an input method
+    pub key: Key,
+    /// Text the key produced, if any
+    pub text: Option<&'a str>,
+}
+
+/// Decides whether keystrokes are going into a password field or prompt. Every
+/// rule sees every key press, so rules may keep state between them.
+pub trait SecureRule: Send {
+    fn name(&self) -> &'static str;
+
+    fn is_secure(&mut self, ctx: &SecureContext) -> bool;
+}
+
+pub fn default_rules() -> Vec<Box<dyn SecureRule>> {
+    vec![
+        Box::new(OwnWindow::current()),
+        #[cfg(target_os = "macos")]
+        Box::new(SecureEventInput),
+        Box::new(AuthApp),
+        Box::new(AuthTitle),
+        Box::new(PasswordPrompt::default()),
+    ]
+}
+
+/// macOS turns on secure event input for password fields (and some apps,
+/// such as Terminal's "Secure Keyboard Entry", keep it on). Global hooks stop
+/// seeing most keys then, but whatever still gets through is dropped too.
+#[cfg(target_os = "macos")]
+pub struct SecureEventInput;
+
+#[cfg(target_os = "macos")]
+#[link(name = "Carbon", kind = "framework")]
+extern "C" {
+    fn IsSecureEventInputEnabled() -> u8;
+}
+
+#[cfg(target_os = "macos")]
+impl SecureRule for SecureEventInput {
+    fn name(&self) -> &'static str {
+        "secure-event-input"
+    }
+
+    fn is_secure(&mut self, _ctx: &SecureContext) -> bool {
+        unsafe { IsSecureEventInputEnabled() != 0 }
+    }
+}
+
+/// arkinput's own window, where the database passphrase is typed. It is
+/// matched on the executable, whatever its title says.
+pub struct OwnWindow {
+    exe_path: Option<PathBuf>,
+    /// Lowercase names the window backends may report for this process
+    names: Vec<String>,
+}
+
+/// The identifier from `tauri.conf.json`, which Wayland compositors report as
+/// the app id
+const APP_ID: &str = "com.arkinput.app";
+
+impl OwnWindow {
+    pub fn current() -> Self {
+        let exe_path = std::env::current_exe().ok();
+        let mut names = vec![APP_ID.to_string()];
+        if let Some(path) = &exe_path {
+            // Linux reports the file name, Windows the name without `.exe`
+            names.extend(
+                [path.file_name(), path.file_stem()]
+                    .into_iter()
+                    .flatten()
+                    .map(|name| name.to_string_lossy().to_lowercase()),
+            );
+        }
+        Self { exe_path, names }
+    }
+}
+
+impl SecureRule for OwnWindow {
+    fn name(&self) -> &'static str {
+        "own-window"
+    }
+
+    fn is_secure(&mut self, ctx: &SecureContext) -> bool {
+        let same_exe = match (&ctx.window.exe_path, &self.exe_path) {
+            (Some(path), Some(own)) => Path::new(path) == own,
+            _ => false,
+        };
+        same_exe || self.names.contains(&ctx.window.app_name.to_lowercase())
+    }
+}
+
+/// Password dialogs and password managers, matched on the app name
+pub struct AuthApp;
+
+const AUTH_APPS: &[&str] = &[
+    "pinentry",
+    "polkit",
+    "gcr-prompter",
+    "ssh-askpass",
+    "ksshaskpass",
+    "kwalletd",
+    "securityagent",
+    "credentialuibroker",
+    "keepass",
+    "1password",
+    "bitwarden",
+];
+
+impl SecureRule for AuthApp {
+    fn name(&self) -> &'static str {
+        "auth-app"
+    }
+
+    fn is_secure(&mut self, ctx: &SecureContext) -> bool {
+        let app = ctx.window.app_name.to_lowercase();
+        AUTH_APPS.iter().any(|name| app.contains(name))
+    }
+}
+
+/// Prompt wording, or a title part that is just "Log in" / "Sign in" (not a
+/// file such as `login.rs`)
+static AUTH_TITLE: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(concat!(
+        r"(?i)password:|password for|enter (your |the )?(password|passphrase)|passphrase( for|:)|\[sudo\]|^sudo\s",
+        r"|(^|[-–—:|·]\s*)(log ?in|sign ?in)(\s*([-–—:|·]|$)|\s+to\b)",
+        r"|authentication required|^authenticate(\s|$)|^unlock(\s|$)",
+    ))
+    .unwrap()
+});
+
+/// Login, unlock and sudo prompts, matched on the window title
+pub struct AuthTitle;
+
+impl SecureRule for AuthTitle {
+    fn name(&self) -> &'static str {
+        "auth-title"
+    }
+
+    fn is_secure(&mut self, ctx: &SecureContext) -> bool {
+        ctx.window
+            .window_title
+            .as_deref()
+            .is_some_and(|title| AUTH_TITLE.is_match(title))
+    }
+}
+
+const TERMINALS: &[&str] = &[
+    "terminal",
+    "konsole",
+    "alacritty",
+    "kitty",
+    "wezterm",
+    "xterm",
+    "urxvt",
+    "foot",
+    "tilix",
+    "terminator",
+    "iterm",
+    "powershell",
+    "pwsh",
+    "cmd.exe",
+];
+
+/// Commands that go on to ask for a password on the terminal
+static PROMPTING_COMMAND: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^\s*(sudo|su|doas|passwd|ssh|scp|sftp|ssh-add|gpg|kinit|runas)\b|^\s*(mysql|mariadb)\b.*\s-p\s*$")
+        .unwrap()
+});
+
+/// Follows the line typed into a terminal. After a command that prompts for a
+/// password is entered, the next line is treated as the password. If the
+/// command does not prompt after all (say sudo's credentials were cached),
+/// one ordinary line goes unrecorded.
+#[derive(Default)]
+pub struct PasswordPrompt {
+    line: String,
+    /// The terminal that is waiting for a password
+    awaiting: Option<String>,
+}
+
+impl SecureRule for PasswordPrompt {
+    fn name(&self) -> &'static str {
+        "password-prompt"
+    }
+
+    fn is_secure(&mut self, ctx: &SecureContext) -> bool {
+        let app = &ctx.window.app_name;
+        let lower = app.to_lowercase();
+        if !TERMINALS.iter().any(|name| lower.contains(name)) {
+            return false;
+        }
+
+        if self.awaiting.as_ref() == Some(app) {
+            if ctx.key == Key::Return {
+                self.awaiting = None;
+                self.line.clear();
+            }
+            return true;
+        }
+
+        match ctx.key {
+            Key::Return => {
+                if PROMPTING_COMMAND.is_match(&self.line) {
+                    self.awaiting = Some(app.clone());
+                }
+                self.line.clear();
+            }
+            Key::Backspace => {
+                self.line.pop();
+            }
+            _ => {
+                if let Some(text) = ctx.text {
+                    self.line.push_str(text);
+                }
+            }
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(app_name: &str, title: &str) -> WindowInfo {
+        WindowInfo {
+            app_name: app_name.to_string(),
+            window_title: Some(title.to_string()),
+            exe_path: None,
+        }
+    }
+
+    fn check(rule: &mut dyn SecureRule, window: &WindowInfo, key: Key, text: Option<&str>) -> bool {
+        rule.is_secure(&SecureContext { window, key, text })
+    }
+
+    #[test]
+    fn auth_titles() {
+        let cases = [
+            ("[sudo] password for alice", true),
+            ("Password:", true),
+            ("Enter your password", true),
+            ("Enter passphrase for key '/home/alice/.ssh/id_ed25519'", true),
+            ("Authentication Required", true),
+            ("Authenticate", true),
+            ("Unlock Login Keyring", true),
+            ("Sign in - Google Accounts", true),
+            ("Sign in to GitHub · GitHub — Mozilla Firefox", true),
+            ("Log In | Example", true),
+            ("Mail - Login", true),
+            ("sudo apt upgrade", true),
+            ("login.rs - arkinput - Visual Studio Code", false),
+            ("src/auth/login_form.vue", false),
+            ("authentication.md — Notes", false),
+            ("unlock.py", false),
+            ("How to unlock a phone - Search", false),
+            ("Login Items", false),
+            ("passphrase.rs", false),
+            ("pseudocode.txt", false),
+        ];
+        for (title, expected) in cases {
+            assert_eq!(
+                check(&mut AuthTitle, &window("App", title), Key::KeyA, None),
+                expected,
+                "{}",
+                title
+            );
+        }
+    }
+
+    #[test]
+    fn auth_apps() {
+        assert!(check(&mut AuthApp, &window("pinentry-gnome3", ""), Key::KeyA, None));
+        assert!(check(&mut AuthApp, &window("KeePassXC", ""), Key::KeyA, None));
+        assert!(!check(&mut AuthApp, &window("Firefox", ""), Key::KeyA, None));
+    }
+
+    #[test]
+    fn own_window() {
+        let mut rule = OwnWindow::current();
+        let exe = std::env::current_exe().unwrap();
+        let exe_name = exe.file_name().unwrap().to_string_lossy().to_string();
+
+        assert!(check(&mut rule, &window(&exe_name, "Arkinput - 输入记录"), Key::KeyA, None));
+        assert!(check(&mut rule, &window("com.arkinput.app", ""), Key::KeyA, None));
+        let by_path = WindowInfo {
+            app_name: "Unknown".to_string(),
+            window_title: None,
+            exe_path: Some(exe.to_string_lossy().to_string()),
+        };
+        assert!(check(&mut rule, &by_path, Key::KeyA, None));
+        assert!(!check(&mut rule, &window("Firefox", "Arkinput - 输入记录"), Key::KeyA, None));
+    }
+
+    #[test]
+    fn password_prompt_after_sudo() {
+        let mut rule = PasswordPrompt::default();
+        let terminal = window("Alacritty", "~");
+        let mut type_line = |line: &str| {
+            let mut secure = Vec::new();
+            for c in line.chars() {
+                let mut buf = [0; 4];
+                secure.push(check(&mut rule, &terminal, Key::KeyA, Some(c.encode_utf8(&mut buf))));
+            }
+            secure.push(check(&mut rule, &terminal, Key::Return, None));
+            secure
+        };
+
+        assert!(type_line("sudo ls").iter().all(|secure| !secure));
+        assert!(type_line("hunter2").iter().all(|secure| *secure));
+        assert!(type_line("ls").iter().all(|secure| !secure));
+        // Other apps are never affected
+        assert!(!check(&mut PasswordPrompt::default(), &window("Editor", ""), Key::KeyA, Some("a")));
+    }
+}
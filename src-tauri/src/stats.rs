@@ -0,0 +1,239 @@
+use chrono::{Datelike, Duration, Months, NaiveDateTime, NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
+
+use crate::timezone::Zone;
+
+/// Records typed over less time than this are too short to give a speed
+pub const MIN_TIMED_MS: i64 = 2_000;
+
+/// Characters in a standard word, for words per minute
+const CHARS_PER_WORD: f64 = 5.0;
+
+/// Most buckets one query may produce, so a long range in hours cannot
+/// build an enormous response by accident
+const MAX_BUCKETS: usize = 10_000;
+
+/// Width of one step of a time series. Buckets follow the calendar in the
+/// configured time zone; weeks start on Monday.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Bucket {
+    Hour,
+    Day,
+    Week,
+    Month,
+}
+
+/// What a time series is split by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupBy {
+    #[default]
+    None,
+    App,
+    WindowTitle,
+}
+
+/// A date range in any form `Zone::parse_bound` reads. `end` includes all
+/// of what it names, so "2024-05-01" to "2024-05-07" is a whole week.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StatsRange {
+    pub start: String,
+    pub end: String,
+}
+
+impl Bucket {
+    /// The start of the bucket holding `local`
+    fn truncate(self, local: NaiveDateTime) -> NaiveDateTime {
+        let date = local.date();
+        match self {
+            Self::Hour => date.and_time(NaiveTime::MIN) + Duration::hours(i64::from(local.hour())),
+            Self::Day => date.and_time(NaiveTime::MIN),
+            Self::Week => {
+                let monday =
+                    date - Duration::days(i64::from(date.weekday().num_days_from_monday()));
+                monday.and_time(NaiveTime::MIN)
+            }
+            Self::Month => (date - Duration::days(i64::from(date.day0()))).and_time(NaiveTime::MIN),
+        }
+    }
+
+    fn next(self, start: NaiveDateTime) -> NaiveDateTime {
+        match self {
+            Self::Hour => start + Duration::hours(1),
+            Self::Day => start + Duration::days(1),
+            Self::Week => start + Duration::weeks(1),
+            Self::Month => start
+                .checked_add_months(Months::new(1))
+                .unwrap_or(start + Duration::days(31)),
+        }
+    }
+}
+
+/// Epoch milliseconds at the start of every bucket overlapping
+/// `start..end`. The first may begin before `start`. An hour repeated when
+/// daylight saving ends is counted in the same bucket as its first pass.
+pub fn bucket_starts(zone: Zone, start: i64, end: i64, bucket: Bucket) -> Result<Vec<i64>, String> {
+    let mut starts: Vec<i64> = Vec::new();
+    let mut local = bucket.truncate(zone.local_time(start));
+    loop {
+        let millis = zone.millis_at(local);
+        if millis >= end {
+            break;
+        }
+        // An hour skipped by daylight saving resolves to the one after it
+        if starts.last().copied() < Some(millis) {
+            starts.push(millis);
+        }
+        if starts.len() > MAX_BUCKETS {
+            return Err(format!(
+                "Too many {:?} buckets in this range; choose a wider bucket or a shorter range",
+                bucket
+            ));
+        }
+        local = bucket.next(local);
+    }
+    Ok(starts)
+}
+
+/// Index of the bucket holding `millis`
+pub fn bucket_index(starts: &[i64], millis: i64) -> usize {
+    starts
+        .partition_point(|&start| start <= millis)
+        .saturating_sub(1)
+}
+
+/// Characters per minute
+pub fn cpm(chars: i64, ms: i64) -> f64 {
+    if ms <= 0 {
+        return 0.0;
+    }
+    chars as f64 * 60_000.0 / ms as f64
+}
+
+/// Words per minute, counting five characters as a word
+pub fn wpm(chars: i64, ms: i64) -> f64 {
+    cpm(chars, ms) / CHARS_PER_WORD
+}
+
+/// Nearest-rank percentile `p` (0 to 100) of values sorted in ascending order
+pub fn percentile(sorted: &[f64], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn starts(zone: &str, start: &str, end: &str, bucket: Bucket) -> Vec<String> {
+        let zone = Zone::parse(zone).unwrap();
+        let start = zone.parse_bound(start, false).unwrap();
+        let end = zone.parse_bound(end, true).unwrap();
+        bucket_starts(zone, start, end, bucket)
+            .unwrap()
+            .into_iter()
+            .map(|millis| zone.format(millis))
+            .collect()
+    }
+
+    #[test]
+    fn hours_across_dst_gap() {
+        let hours = starts("America/New_York", "2026-03-08", "2026-03-08", Bucket::Hour);
+        assert_eq!(hours.len(), 23);
+        assert_eq!(hours[1], "2026-03-08T01:00:00-05:00");
+        assert_eq!(hours[2], "2026-03-08T03:00:00-04:00");
+        assert_eq!(hours[22], "2026-03-08T23:00:00-04:00");
+    }
+
+    #[test]
+    fn hours_across_dst_overlap() {
+        let hours = starts("America/New_York", "2026-11-01", "2026-11-01", Bucket::Hour);
+        // The repeated 01:00 hour shares the first pass's bucket
+        assert_eq!(hours.len(), 24);
+        assert_eq!(hours[1], "2026-11-01T01:00:00-04:00");
+        assert_eq!(hours[2], "2026-11-01T02:00:00-05:00");
+
+        let zone = Zone::parse("America/New_York").unwrap();
+        let millis: Vec<i64> = hours.iter().map(|h| zone.parse_bound(h, false).unwrap()).collect();
+        let second_pass = millis[1] + 3_600_000;
+        assert_eq!(bucket_index(&millis, second_pass), 1);
+        assert_eq!(millis[2] - millis[1], 2 * 3_600_000);
+    }
+
+    #[test]
+    fn days_across_dst() {
+        let days = starts("Europe/Berlin", "2026-03-28", "2026-03-30", Bucket::Day);
+        assert_eq!(
+            days,
+            [
+                "2026-03-28T00:00:00+01:00",
+                "2026-03-29T00:00:00+01:00",
+                "2026-03-30T00:00:00+02:00",
+            ]
+        );
+    }
+
+    #[test]
+    fn weeks_start_on_monday() {
+        // 2026-05-03 is a Sunday
+        let weeks = starts("UTC", "2026-05-03", "2026-05-11", Bucket::Week);
+        assert_eq!(
+            weeks,
+            [
+                "2026-04-27T00:00:00+00:00",
+                "2026-05-04T00:00:00+00:00",
+                "2026-05-11T00:00:00+00:00",
+            ]
+        );
+    }
+
+    #[test]
+    fn months_keep_to_the_first() {
+        let months = starts("Asia/Shanghai", "2028-01-31", "2028-04-30", Bucket::Month);
+        assert_eq!(
+            months,
+            [
+                "2028-01-01T00:00:00+08:00",
+                "2028-02-01T00:00:00+08:00",
+                "2028-03-01T00:00:00+08:00",
+                "2028-04-01T00:00:00+08:00",
+            ]
+        );
+    }
+
+    #[test]
+    fn too_many_buckets() {
+        let zone = Zone::parse("UTC").unwrap();
+        let start = zone.parse_bound("2000-01-01", false).unwrap();
+        let end = zone.parse_bound("2026-01-01", true).unwrap();
+        assert!(bucket_starts(zone, start, end, Bucket::Hour).is_err());
+        assert!(bucket_starts(zone, start, end, Bucket::Day).is_ok());
+    }
+
+    #[test]
+    fn bucket_index_edges() {
+        let starts = [0, 10, 20];
+        assert_eq!(bucket_index(&starts, -5), 0);
+        assert_eq!(bucket_index(&starts, 0), 0);
+        assert_eq!(bucket_index(&starts, 9), 0);
+        assert_eq!(bucket_index(&starts, 10), 1);
+        assert_eq!(bucket_index(&starts, 25), 2);
+        assert_eq!(bucket_index(&[], 25), 0);
+    }
+
+    #[test]
+    fn speeds_and_percentiles() {
+        assert_eq!(cpm(300, 60_000), 300.0);
+        assert_eq!(wpm(300, 60_000), 60.0);
+        assert_eq!(cpm(10, 0), 0.0);
+        let sorted = [1.0, 2.0, 3.0, 4.0];
+        assert_eq!(percentile(&sorted, 50.0), 2.0);
+        assert_eq!(percentile(&sorted, 90.0), 4.0);
+        assert_eq!(percentile(&sorted, 0.0), 1.0);
+        assert_eq!(percentile(&[], 50.0), 0.0);
+    }
+}
@@ -0,0 +1,212 @@
+use chrono::{
+    Duration, LocalResult, NaiveDate, NaiveDateTime, NaiveTime, SecondsFormat, TimeZone, Utc,
+};
+use chrono_tz::Tz;
+
+/// The time zone dates are entered and statistics grouped in. Timestamps are
+/// stored as UTC epoch milliseconds and only converted at the edges.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Zone {
+    /// Whatever the system is set to
+    #[default]
+    Local,
+    Named(Tz),
+}
+
+impl Zone {
+    /// An IANA name such as "Asia/Shanghai"; empty or "local" for the system zone
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.trim() {
+            "" | "local" => Ok(Self::Local),
+            name => name
+                .parse::<Tz>()
+                .map(Self::Named)
+                .map_err(|_| format!("Unknown time zone: {}", name)),
+        }
+    }
+
+    pub fn today(&self) -> NaiveDate {
+        self.local_time(Utc::now().timestamp_millis()).date()
+    }
+
+    /// The wall-clock time in this zone at `millis` since the epoch
+    pub fn local_time(&self, millis: i64) -> NaiveDateTime {
+        let utc = chrono::DateTime::<Utc>::from_timestamp_millis(millis).unwrap_or_default();
+        match self {
+            Self::Local => utc.with_timezone(&chrono::Local).naive_local(),
+            Self::Named(tz) => utc.with_timezone(tz).naive_local(),
+        }
+    }
+
+    /// `millis` since the epoch as RFC 3339, with this zone's offset
+    pub fn format(&self, millis: i64) -> String {
+        let utc = chrono::DateTime::<Utc>::from_timestamp_millis(millis).unwrap_or_default();
+        match self {
+            Self::Local => utc
+                .with_timezone(&chrono::Local)
+                .to_rfc3339_opts(SecondsFormat::Secs, false),
+            Self::Named(tz) => utc
+                .with_timezone(tz)
+                .to_rfc3339_opts(SecondsFormat::Secs, false),
+        }
+    }
+
+    /// Epoch milliseconds at the first instant of `date` in this zone
+    pub fn start_of_day(&self, date: NaiveDate) -> i64 {
+        self.millis_at(date.and_time(NaiveTime::MIN))
+    }
+
+    /// Epoch milliseconds for a wall-clock time in this zone. A time skipped
+    /// by a daylight saving change is moved past the gap; an ambiguous one
+    /// takes its first occurrence.
+    pub fn millis_at(&self, naive: NaiveDateTime) -> i64 {
+        let mut naive = naive;
+        // No zone has a gap longer than a day, so this always ends
+        loop {
+            let resolved = match self {
+                Self::Local => earliest(chrono::Local.from_local_datetime(&naive)),
+                Self::Named(tz) => earliest(tz.from_local_datetime(&naive)),
+            };
+            match resolved {
+                Some(millis) => return millis,
+                None => naive += Duration::minutes(15),
+            }
+        }
+    }
+
+    /// Parse a date filter: RFC 3339, or a wall-clock "YYYY-MM-DD",
+    /// "YYYY-MM-DD HH:MM" or "YYYY-MM-DD HH:MM:SS" in this zone.
+    ///
+    /// With `end` set the result is the exclusive end of what was written,
+    /// so "2024-05-01" as an end bound covers the whole day and
+    /// "2024-05-01 23:59:59" the whole of that last second.
+    pub fn parse_bound(&self, value: &str, end: bool) -> Result<i64, String> {
+        let value = value.trim();
+        if let Ok(dt) = chrono::DateTime::parse_from_rfc3339(value) {
+            return Ok(dt.timestamp_millis() + i64::from(end));
+        }
+
+        let (naive, span) =
+            if let Ok(dt) = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M:%S") {
+                (dt, Duration::seconds(1))
+            } else if let Ok(dt) = NaiveDateTime::parse_from_str(value, "%Y-%m-%d %H:%M") {
+                (dt, Duration::minutes(1))
+            } else if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+                (date.and_time(NaiveTime::MIN), Duration::days(1))
+            } else {
+                return Err(format!("Invalid date: {}", value));
+            };
+
+        Ok(self.millis_at(if end { naive + span } else { naive }))
+    }
+}
+
+fn earliest<T: TimeZone>(result: LocalResult<chrono::DateTime<T>>) -> Option<i64> {
+    result.earliest().map(|dt| dt.timestamp_millis())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(rfc3339: &str) -> i64 {
+        chrono::DateTime::parse_from_rfc3339(rfc3339)
+            .unwrap()
+            .timestamp_millis()
+    }
+
+    #[test]
+    fn parses_zones() {
+        assert_eq!(Zone::parse(""), Ok(Zone::Local));
+        assert_eq!(Zone::parse(" local "), Ok(Zone::Local));
+        assert_eq!(
+            Zone::parse("Asia/Shanghai"),
+            Ok(Zone::Named(chrono_tz::Asia::Shanghai))
+        );
+        assert_eq!(
+            Zone::parse("Mars/Olympus"),
+            Err("Unknown time zone: Mars/Olympus".to_string())
+        );
+    }
+
+    #[test]
+    fn bounds() {
+        let zone = Zone::parse("Asia/Shanghai").unwrap();
+        let cases = [
+            ("2026-05-01", false, "2026-04-30T16:00:00Z"),
+            ("2026-05-01", true, "2026-05-01T16:00:00Z"),
+            ("2026-05-01 09:30", false, "2026-05-01T01:30:00Z"),
+            ("2026-05-01 09:30", true, "2026-05-01T01:31:00Z"),
+            ("2026-05-01 09:30:15", false, "2026-05-01T01:30:15Z"),
+            ("2026-05-01 09:30:15", true, "2026-05-01T01:30:16Z"),
+            (" 2026-05-01 ", false, "2026-04-30T16:00:00Z"),
+            // An RFC 3339 time carries its own offset
+            ("2026-05-01T09:30:00+02:00", false, "2026-05-01T07:30:00Z"),
+            (
+                "2026-05-01T09:30:00.500+02:00",
+                false,
+                "2026-05-01T07:30:00.500Z",
+            ),
+        ];
+        for (value, end, expected) in cases {
+            assert_eq!(
+                zone.parse_bound(value, end),
+                Ok(utc(expected)),
+                "{} {}",
+                value,
+                end
+            );
+        }
+        // An exact instant ends one millisecond after it
+        assert_eq!(
+            zone.parse_bound("2026-05-01T09:30:00+02:00", true),
+            Ok(utc("2026-05-01T07:30:00Z") + 1)
+        );
+    }
+
+    #[test]
+    fn bounds_across_daylight_saving() {
+        let zone = Zone::parse("America/New_York").unwrap();
+        let cases = [
+            // Clocks go from 02:00 to 03:00, so the day has 23 hours
+            ("2026-03-08", false, "2026-03-08T05:00:00Z"),
+            ("2026-03-08", true, "2026-03-09T04:00:00Z"),
+            // Times in the gap move forward a quarter hour at a time
+            ("2026-03-08 02:30", false, "2026-03-08T07:00:00Z"),
+            ("2026-03-08 02:30", true, "2026-03-08T07:01:00Z"),
+            ("2026-03-08 03:00", false, "2026-03-08T07:00:00Z"),
+            // 01:30 happens twice when clocks go back; the first is taken
+            ("2026-11-01 01:30", false, "2026-11-01T05:30:00Z"),
+            ("2026-11-01", true, "2026-11-02T05:00:00Z"),
+        ];
+        for (value, end, expected) in cases {
+            assert_eq!(
+                zone.parse_bound(value, end),
+                Ok(utc(expected)),
+                "{} {}",
+                value,
+                end
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_bounds() {
+        let zone = Zone::parse("UTC").unwrap();
+        for value in [
+            "",
+            "yesterday",
+            "2026-13-01",
+            "2026-02-30",
+            "2026-05-01 25:00",
+            "01/05/2026",
+        ] {
+            assert_eq!(
+                zone.parse_bound(value, false),
+                Err(format!("Invalid date: {}", value)),
+                "{}",
+                value
+            );
+        }
+    }
+}
@@ -1,7 +1,20 @@
+#[cfg(target_os = "linux")]
+mod kwin;
+#[cfg(target_os = "linux")]
+mod sway;
+#[cfg(target_os = "linux")]
+mod wlr;
+#[cfg(target_os = "linux")]
+mod x11;
+
+use crate::layout::Layout;
+
 #[derive(Debug, Clone)]
 pub struct WindowInfo {
     pub app_name: String,
     pub window_title: Option<String>,
+    /// Full path of the focused process's executable, where the platform tells
+    pub exe_path: Option<String>,
 }
 
 #[cfg(target_os = "windows")]
@@ -37,32 +50,35 @@ pub fn get_active_window() -> Option<WindowInfo> {
         let mut process_id: u32 = 0;
         GetWindowThreadProcessId(hwnd, Some(&mut process_id));
 
-        // Get process name
-        let app_name = if process_id != 0 {
+        // Get process path
+        let exe_path = if process_id != 0 {
             if let Ok(handle) = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, process_id) {
                 let mut name_buf = [0u16; 512];
                 let mut name_len = name_buf.len() as u32;
                 if QueryFullProcessImageNameW(handle, PROCESS_NAME_WIN32, &mut name_buf, &mut name_len).is_ok() {
-                    let path = OsString::from_wide(&name_buf[..name_len as usize])
+                    Some(OsString::from_wide(&name_buf[..name_len as usize])
                         .to_string_lossy()
-                        .to_string();
-                    std::path::Path::new(&path)
-                        .file_stem()
-                        .map(|s| s.to_string_lossy().to_string())
-                        .unwrap_or_else(|| "Unknown".to_string())
+                        .to_string())
                 } else {
-                    "Unknown".to_string()
+                    None
                 }
             } else {
-                "Unknown".to_string()
+                None
             }
         } else {
-            "Unknown".to_string()
+            None
         };
 
+        let app_name = exe_path
+            .as_deref()
+            .and_then(|path| std::path::Path::new(path).file_stem())
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_else(|| "Unknown".to_string());
+
         Some(WindowInfo {
             app_name,
             window_title,
+            exe_path,
         })
     }
 }
@@ -83,6 +99,7 @@ pub fn get_active_window() -> Option<WindowInfo> {
         return Some(WindowInfo {
             app_name: "Unknown".to_string(),
             window_title: None,
+            exe_path: None,
         });
     }
 
@@ -94,20 +111,179 @@ pub fn get_active_window() -> Option<WindowInfo> {
         return Some(WindowInfo {
             app_name: "Unknown".to_string(),
             window_title: None,
+            exe_path: None,
         });
     }
 
     Some(WindowInfo {
         app_name,
         window_title: None,
+        exe_path: None,
+    })
+}
+
+/// The keyboard layout the focused window's thread types with. Windows keeps
+/// one per thread.
+#[cfg(target_os = "windows")]
+pub fn active_layout() -> Option<Layout> {
+    use windows::Win32::UI::Input::KeyboardAndMouse::GetKeyboardLayout;
+    use windows::Win32::UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowThreadProcessId};
+
+    let hkl = unsafe {
+        let thread = GetWindowThreadProcessId(GetForegroundWindow(), None);
+        GetKeyboardLayout(thread).0 as usize
+    };
+
+    // The high word names the physical layout by its language, unless it is a
+    // handle to a layout variant; the low word is the input language
+    let device = (hkl >> 16) as u16;
+    let id = if device & 0xf000 == 0xf000 {
+        hkl as u16
+    } else {
+        device
+    };
+    Some(match id {
+        // de-DE, de-AT, de-LU, de-LI
+        0x0407 | 0x0c07 | 0x1007 | 0x1407 => Layout::German,
+        // fr-FR, fr-LU, fr-MC
+        0x040c | 0x140c | 0x180c => Layout::French,
+        _ => Layout::Us,
+    })
+}
+
+/// The current keyboard input source, such as "com.apple.keylayout.German".
+#[cfg(target_os = "macos")]
+pub fn active_layout() -> Option<Layout> {
+    use std::process::Command;
+
+    let output = Command::new("defaults")
+        .args([
+            "read",
+            "com.apple.HIToolbox",
+            "AppleCurrentKeyboardLayoutInputSourceID",
+        ])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    let id = String::from_utf8_lossy(&output.stdout);
+    id.trim().rsplit('.').next().map(Layout::from_name)
+}
+
+/// The layout of the keyboard group in effect, as the X server reports it.
+/// Wayland compositors do not share theirs; XWayland's is the best guess.
+#[cfg(target_os = "linux")]
+pub fn active_layout() -> Option<Layout> {
+    x11::active_layout()
+}
+
+/// A source of focused-window information on Linux, where X11 and each Wayland
+/// compositor expose it differently.
+#[cfg(target_os = "linux")]
+trait WindowBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+    fn active_window(&self) -> Option<WindowInfo>;
+}
+
+#[cfg(target_os = "linux")]
+static LINUX_BACKEND: once_cell::sync::OnceCell<Box<dyn WindowBackend>> =
+    once_cell::sync::OnceCell::new();
+
+/// Pick a backend for the current session. `ARKINPUT_WINDOW_BACKEND` forces one
+/// of `x11`, `sway`, `kwin` or `wlr`; otherwise it is derived from
+/// `SWAYSOCK` and `XDG_SESSION_TYPE`.
+#[cfg(target_os = "linux")]
+fn select_backend() -> Box<dyn WindowBackend> {
+    let forced = std::env::var("ARKINPUT_WINDOW_BACKEND").ok();
+    let wanted = |name: &str| forced.as_deref().is_none_or(|f| f == name);
+
+    if wanted("sway") {
+        if let Some(backend) = sway::SwayBackend::from_env() {
+            return Box::new(backend);
+        }
+    }
+
+    let wayland = std::env::var("XDG_SESSION_TYPE")
+        .map(|t| t.eq_ignore_ascii_case("wayland"))
+        .unwrap_or(false);
+
+    if wayland || forced.is_some() {
+        let kde = std::env::var("XDG_CURRENT_DESKTOP")
+            .map(|d| d.split(':').any(|d| d.eq_ignore_ascii_case("KDE")))
+            .unwrap_or(false);
+
+        if wanted("kwin") && (kde || forced.is_some()) {
+            if let Some(backend) = kwin::KWinBackend::start() {
+                return Box::new(backend);
+            }
+        }
+
+        if wanted("wlr") {
+            if let Some(backend) = wlr::WlrBackend::start() {
+                return Box::new(backend);
+            }
+        }
+    }
+
+    // XWayland still reports focus for X clients, which beats nothing
+    Box::new(x11::X11Backend)
+}
+
+#[cfg(target_os = "linux")]
+pub fn get_active_window() -> Option<WindowInfo> {
+    let backend = LINUX_BACKEND.get_or_init(|| {
+        let backend = select_backend();
+        eprintln!("Using {} active-window backend", backend.name());
+        backend
+    });
+
+    // Keep recording as "Unknown" when the backend cannot tell
+    backend.active_window().or_else(|| {
+        Some(WindowInfo {
+            app_name: "Unknown".to_string(),
+            window_title: None,
+            exe_path: None,
+        })
     })
 }
 
-#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+/// Full path of a process's executable.
+#[cfg(target_os = "linux")]
+fn process_path(pid: u32) -> Option<String> {
+    if pid == 0 {
+        return None;
+    }
+
+    std::fs::read_link(format!("/proc/{}/exe", pid))
+        .ok()
+        .map(|path| path.to_string_lossy().to_string())
+}
+
+/// Executable name of a process, matching what the Windows backend reports.
+#[cfg(target_os = "linux")]
+fn process_name(pid: u32) -> Option<String> {
+    if pid == 0 {
+        return None;
+    }
+
+    std::fs::read_link(format!("/proc/{}/exe", pid))
+        .ok()
+        .and_then(|path| path.file_name().map(|s| s.to_string_lossy().to_string()))
+        .or_else(|| {
+            std::fs::read_to_string(format!("/proc/{}/comm", pid))
+                .ok()
+                .map(|s| s.trim().to_string())
+        })
+        .filter(|s| !s.is_empty())
+}
+
+#[cfg(not(any(target_os = "windows", target_os = "macos", target_os = "linux")))]
 pub fn get_active_window() -> Option<WindowInfo> {
-    // Linux support could be added using x11 or wayland APIs
     Some(WindowInfo {
         app_name: "Unknown".to_string(),
         window_title: None,
+        exe_path: None,
     })
 }
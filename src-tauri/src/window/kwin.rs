@@ -0,0 +1,229 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use dbus::blocking::Connection;
+use dbus::channel::{MatchingReceiver, Sender};
+use dbus::message::MatchRule;
+use parking_lot::Mutex;
+
+use super::{process_name, process_path, WindowBackend, WindowInfo};
+
+const PLUGIN_NAME: &str = "arkinput-active-window";
+const REPORT_PATH: &str = "/arkinput";
+const REPORT_INTERFACE: &str = "org.arkinput.ActiveWindow";
+
+/// KWin does not expose the active window over D-Bus directly, so we load a
+/// small KWin script that calls back into our connection whenever focus or the
+/// focused window's caption changes. Handles both the Plasma 5 (`Client`) and
+/// Plasma 6 (`Window`) scripting APIs.
+const SCRIPT: &str = r#"
+const active = () => workspace.activeWindow !== undefined ? workspace.activeWindow : workspace.activeClient;
+const watched = new Set();
+
+function report(w) {
+    callDBus("{service}", "{path}", "{interface}", "Report",
+        w ? String(w.resourceClass) : "",
+        w ? String(w.caption) : "",
+        w ? String(w.pid) : "0");
+}
+
+function onActivated(w) {
+    if (w && !watched.has(w)) {
+        watched.add(w);
+        w.captionChanged.connect(() => { if (active() === w) report(w); });
+    }
+    report(w);
+}
+
+(workspace.windowActivated || workspace.clientActivated).connect(onActivated);
+onActivated(active());
+"#;
+
+/// Caches what the KWin script last reported; lookups never touch D-Bus.
+pub struct KWinBackend {
+    current: Arc<Mutex<Option<WindowInfo>>>,
+}
+
+impl KWinBackend {
+    /// Loads the reporting script into KWin. Returns `None` when KWin is not
+    /// reachable on the session bus.
+    pub fn start() -> Option<Self> {
+        let conn = match Connection::new_session() {
+            Ok(conn) => conn,
+            Err(e) => {
+                eprintln!("Failed to connect to session bus: {}", e);
+                return None;
+            }
+        };
+
+        let current = Arc::new(Mutex::new(None));
+        let current_clone = current.clone();
+
+        let mut rule = MatchRule::new_method_call();
+        rule.path = Some(REPORT_PATH.into());
+        rule.interface = Some(REPORT_INTERFACE.into());
+        conn.start_receive(
+            rule,
+            Box::new(move |msg, conn| {
+                if let Ok((class, caption, pid)) = msg.read3::<String, String, String>() {
+                    *current_clone.lock() = parse_report(class, caption, &pid);
+                }
+                let _ = conn.send(msg.method_return());
+                true
+            }),
+        );
+
+        let script = SCRIPT
+            .replace("{service}", &conn.unique_name())
+            .replace("{path}", REPORT_PATH)
+            .replace("{interface}", REPORT_INTERFACE);
+        // Prefer the per-user runtime dir over the shared temp dir
+        let dir = std::env::var_os("XDG_RUNTIME_DIR")
+            .filter(|d| !d.is_empty())
+            .map(PathBuf::from)
+            .unwrap_or_else(std::env::temp_dir);
+        let script_path = match write_script(&dir, &script) {
+            Ok(path) => path,
+            Err(e) => {
+                eprintln!("Failed to write KWin script: {}", e);
+                return None;
+            }
+        };
+
+        // KWin reads the file when the script starts, so it can go right after
+        let started = load_script(&conn, &script_path);
+        let _ = std::fs::remove_file(&script_path);
+        if let Err(e) = started {
+            eprintln!("{}", e);
+            return None;
+        }
+
+        thread::spawn(move || loop {
+            if let Err(e) = conn.process(Duration::from_secs(1)) {
+                eprintln!("KWin D-Bus connection lost: {}", e);
+                break;
+            }
+        });
+
+        Some(Self { current })
+    }
+}
+
+/// Creates the script file with `O_EXCL`, so a file or symlink planted at the
+/// same path makes this fail rather than being written through.
+fn write_script(dir: &Path, script: &str) -> std::io::Result<PathBuf> {
+    let path = dir.join(format!("{}-{}.js", PLUGIN_NAME, std::process::id()));
+    // Left over from an earlier run with a recycled pid; removing a symlink
+    // removes the link, never its target
+    match std::fs::remove_file(&path) {
+        Ok(()) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e),
+    }
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&path)?;
+    file.write_all(script.as_bytes())?;
+    Ok(path)
+}
+
+fn load_script(conn: &Connection, script_path: &Path) -> Result<(), String> {
+    let scripting = conn.with_proxy("org.kde.KWin", "/Scripting", Duration::from_secs(2));
+    // A previous run may have left the script loaded under the same name
+    let _: Result<(bool,), _> =
+        scripting.method_call("org.kde.kwin.Scripting", "unloadScript", (PLUGIN_NAME,));
+    let loaded: Result<(i32,), _> = scripting.method_call(
+        "org.kde.kwin.Scripting",
+        "loadScript",
+        (script_path.to_string_lossy().to_string(), PLUGIN_NAME),
+    );
+    match loaded {
+        Ok((id,)) if id >= 0 => {}
+        Ok(_) => return Err("KWin refused to load the active-window script".to_string()),
+        Err(e) => return Err(format!("Failed to load KWin script: {}", e)),
+    }
+    scripting
+        .method_call::<(), _, _, _>("org.kde.kwin.Scripting", "start", ())
+        .map_err(|e| format!("Failed to start KWin script: {}", e))
+}
+
+fn parse_report(class: String, caption: String, pid: &str) -> Option<WindowInfo> {
+    if class.is_empty() && caption.is_empty() {
+        return None;
+    }
+
+    let pid: Option<u32> = pid.parse().ok();
+
+    let app_name = pid
+        .and_then(process_name)
+        .or_else(|| Some(class).filter(|s| !s.is_empty()))
+        .unwrap_or_else(|| "Unknown".to_string());
+
+    Some(WindowInfo {
+        app_name,
+        window_title: Some(caption).filter(|s| !s.is_empty()),
+        exe_path: pid.and_then(process_path),
+    })
+}
+
+impl WindowBackend for KWinBackend {
+    fn name(&self) -> &'static str {
+        "kwin"
+    }
+
+    fn active_window(&self) -> Option<WindowInfo> {
+        self.current.lock().clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn script_is_private_and_never_written_through_a_symlink() {
+        let dir = std::env::temp_dir().join(format!("arkinput-kwin-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let victim = dir.join("victim");
+        std::fs::write(&victim, "keep").unwrap();
+        let planted = dir.join(format!("{}-{}.js", PLUGIN_NAME, std::process::id()));
+        std::os::unix::fs::symlink(&victim, &planted).unwrap();
+
+        let path = write_script(&dir, "report();").unwrap();
+        assert_eq!(path, planted);
+        assert_eq!(std::fs::read_to_string(&victim).unwrap(), "keep");
+        assert!(!std::fs::symlink_metadata(&path)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "report();");
+        assert_eq!(
+            std::fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parses_reports() {
+        assert!(parse_report(String::new(), String::new(), "0").is_none());
+
+        let info = parse_report("konsole".to_string(), "~ : bash".to_string(), "0").unwrap();
+        assert_eq!(info.app_name, "konsole");
+        assert_eq!(info.window_title.as_deref(), Some("~ : bash"));
+
+        let info = parse_report(String::new(), "Untitled".to_string(), "not a pid").unwrap();
+        assert_eq!(info.app_name, "Unknown");
+    }
+}
@@ -0,0 +1,405 @@
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use serde_json::Value;
+
+use super::{process_name, process_path, WindowBackend, WindowInfo};
+
+const MAGIC: &[u8; 6] = b"i3-ipc";
+const SUBSCRIBE: u32 = 2;
+const GET_TREE: u32 = 4;
+// Events have the high bit of the message type set
+const EVENT_WORKSPACE: u32 = 0x8000_0000;
+const EVENT_WINDOW: u32 = 0x8000_0003;
+
+/// Follows focus through sway's IPC `window` events, so lookups never touch
+/// the socket.
+pub struct SwayBackend {
+    current: Arc<Mutex<Option<WindowInfo>>>,
+}
+
+impl SwayBackend {
+    /// Subscribes to focus changes and seeds the cache from the current tree.
+    /// Returns `None` when the IPC socket cannot be used.
+    pub fn start(socket_path: &Path) -> Option<Self> {
+        match Self::subscribe(socket_path) {
+            Ok(backend) => Some(backend),
+            Err(e) => {
+                eprintln!("Failed to subscribe to sway events: {}", e);
+                None
+            }
+        }
+    }
+
+    pub fn from_env() -> Option<Self> {
+        std::env::var_os("SWAYSOCK")
+            .filter(|s| !s.is_empty())
+            .and_then(|s| Self::start(Path::new(&s)))
+    }
+
+    fn subscribe(socket_path: &Path) -> std::io::Result<Self> {
+        // Subscribe before reading the tree, so a focus change in between is
+        // queued on the event socket rather than lost
+        let mut events = Ipc::connect(socket_path)?;
+        let reply = events.request(SUBSCRIBE, br#"["window","workspace"]"#)?;
+        if reply["success"].as_bool() != Some(true) {
+            return Err(invalid_data("sway refused the subscription"));
+        }
+
+        let tree = Ipc::connect(socket_path)?.request(GET_TREE, b"")?;
+        let focused = find_focused(&tree);
+        let active = focused.and_then(|node| node["id"].as_u64());
+        let current = Arc::new(Mutex::new(focused.map(window_info)));
+
+        events.stream.set_read_timeout(None)?;
+        let current_clone = current.clone();
+        thread::spawn(move || {
+            if let Err(e) = track_focus(events, active, current_clone) {
+                eprintln!("sway focus tracking stopped: {}", e);
+            }
+        });
+
+        Ok(Self { current })
+    }
+}
+
+impl WindowBackend for SwayBackend {
+    fn name(&self) -> &'static str {
+        "sway"
+    }
+
+    fn active_window(&self) -> Option<WindowInfo> {
+        self.current.lock().clone()
+    }
+}
+
+fn track_focus(
+    mut events: Ipc,
+    mut active: Option<u64>,
+    current: Arc<Mutex<Option<WindowInfo>>>,
+) -> std::io::Result<()> {
+    loop {
+        let (kind, payload) = events.read_message()?;
+        apply_event(kind, &payload, &mut active, &current);
+    }
+}
+
+fn apply_event(
+    kind: u32,
+    event: &Value,
+    active: &mut Option<u64>,
+    current: &Mutex<Option<WindowInfo>>,
+) {
+    let container = &event["container"];
+    let id = container["id"].as_u64();
+
+    match (kind, event["change"].as_str()) {
+        (EVENT_WINDOW, Some("focus")) => {
+            *active = id;
+            *current.lock() = Some(window_info(container));
+        }
+        (EVENT_WINDOW, Some("title")) if id.is_some() && id == *active => {
+            *current.lock() = Some(window_info(container));
+        }
+        (EVENT_WINDOW, Some("close")) if id.is_some() && id == *active => {
+            *active = None;
+            *current.lock() = None;
+        }
+        // Switching to an empty workspace focuses no window at all
+        (EVENT_WORKSPACE, Some("focus")) => {
+            let workspace = &event["current"];
+            let empty = ["nodes", "floating_nodes"].iter().all(|key| {
+                workspace[*key]
+                    .as_array()
+                    .is_none_or(|nodes| nodes.is_empty())
+            });
+            if empty {
+                *active = None;
+                *current.lock() = None;
+            }
+        }
+        _ => {}
+    }
+}
+
+fn window_info(node: &Value) -> WindowInfo {
+    // Native clients carry an app_id, XWayland clients an X11 class
+    let app_id = node["app_id"]
+        .as_str()
+        .or_else(|| node["window_properties"]["class"].as_str())
+        .filter(|s| !s.is_empty())
+        .map(str::to_string);
+
+    let pid = node["pid"].as_u64().map(|pid| pid as u32);
+
+    let app_name = pid
+        .and_then(process_name)
+        .or(app_id)
+        .unwrap_or_else(|| "Unknown".to_string());
+
+    let window_title = node["name"]
+        .as_str()
+        .filter(|s| !s.is_empty())
+        .map(str::to_string);
+
+    WindowInfo {
+        app_name,
+        window_title,
+        exe_path: pid.and_then(process_path),
+    }
+}
+
+fn find_focused(node: &Value) -> Option<&Value> {
+    let is_window = matches!(node["type"].as_str(), Some("con") | Some("floating_con"));
+    if is_window && node["focused"].as_bool() == Some(true) {
+        return Some(node);
+    }
+
+    ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| node[*key].as_array())
+        .flatten()
+        .find_map(find_focused)
+}
+
+fn invalid_data(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+struct Ipc {
+    stream: UnixStream,
+}
+
+impl Ipc {
+    fn connect(path: &Path) -> std::io::Result<Self> {
+        let stream = UnixStream::connect(path)?;
+        stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+        stream.set_write_timeout(Some(Duration::from_secs(1)))?;
+        Ok(Self { stream })
+    }
+
+    fn request(&mut self, kind: u32, payload: &[u8]) -> std::io::Result<Value> {
+        // Header is the magic string, payload length and message type in native byte order
+        let mut message = Vec::with_capacity(14 + payload.len());
+        message.extend_from_slice(MAGIC);
+        message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+        message.extend_from_slice(&kind.to_ne_bytes());
+        message.extend_from_slice(payload);
+        self.stream.write_all(&message)?;
+
+        let (reply_kind, reply) = self.read_message()?;
+        if reply_kind != kind {
+            return Err(invalid_data("unexpected sway IPC reply"));
+        }
+        Ok(reply)
+    }
+
+    fn read_message(&mut self) -> std::io::Result<(u32, Value)> {
+        let mut header = [0u8; 14];
+        self.stream.read_exact(&mut header)?;
+        if &header[..6] != MAGIC {
+            return Err(invalid_data("unexpected sway IPC reply"));
+        }
+        let len = u32::from_ne_bytes([header[6], header[7], header[8], header[9]]) as usize;
+        let kind = u32::from_ne_bytes([header[10], header[11], header[12], header[13]]);
+
+        let mut payload = vec![0u8; len];
+        self.stream.read_exact(&mut payload)?;
+        let value = serde_json::from_slice(&payload)?;
+        Ok((kind, value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::sync::mpsc;
+    use std::time::Instant;
+
+    /// Stands in for sway's end of an IPC connection
+    struct FakeSway {
+        stream: UnixStream,
+    }
+
+    impl FakeSway {
+        fn accept(listener: &UnixListener) -> Self {
+            let (stream, _) = listener.accept().unwrap();
+            Self { stream }
+        }
+
+        fn expect_request(&mut self, kind: u32) {
+            let mut header = [0u8; 14];
+            self.stream.read_exact(&mut header).unwrap();
+            assert_eq!(&header[..6], MAGIC);
+            let len = u32::from_ne_bytes([header[6], header[7], header[8], header[9]]) as usize;
+            assert_eq!(
+                u32::from_ne_bytes([header[10], header[11], header[12], header[13]]),
+                kind
+            );
+            let mut payload = vec![0u8; len];
+            self.stream.read_exact(&mut payload).unwrap();
+        }
+
+        fn send(&mut self, kind: u32, payload: Value) {
+            let payload = payload.to_string();
+            let mut message = MAGIC.to_vec();
+            message.extend_from_slice(&(payload.len() as u32).to_ne_bytes());
+            message.extend_from_slice(&kind.to_ne_bytes());
+            message.extend_from_slice(payload.as_bytes());
+            self.stream.write_all(&message).unwrap();
+        }
+    }
+
+    fn socket_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "arkinput-sway-{}-{}.sock",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    fn window(id: u64, app_id: &str, title: &str, focused: bool) -> Value {
+        json!({ "id": id, "type": "con", "app_id": app_id, "name": title, "focused": focused })
+    }
+
+    fn tree(windows: Vec<Value>) -> Value {
+        json!({
+            "type": "root",
+            "nodes": [{ "type": "output", "nodes": [{ "type": "workspace", "nodes": windows }] }]
+        })
+    }
+
+    fn wait_for(backend: &SwayBackend, expected: Option<(&str, &str)>) {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            let window = backend.active_window();
+            let actual = window
+                .as_ref()
+                .map(|w| (w.app_name.as_str(), w.window_title.as_deref().unwrap_or("")));
+            if actual == expected {
+                return;
+            }
+            assert!(
+                Instant::now() < deadline,
+                "still {:?}, expected {:?}",
+                actual,
+                expected
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn seeds_from_tree_then_follows_events() {
+        let path = socket_path("events");
+        let listener = UnixListener::bind(&path).unwrap();
+        let (go, wait) = mpsc::channel::<()>();
+
+        let server = thread::spawn(move || {
+            let mut events = FakeSway::accept(&listener);
+            events.expect_request(SUBSCRIBE);
+            events.send(SUBSCRIBE, json!({ "success": true }));
+
+            let mut query = FakeSway::accept(&listener);
+            query.expect_request(GET_TREE);
+            query.send(
+                GET_TREE,
+                tree(vec![
+                    window(1, "editor", "main.rs", true),
+                    window(2, "browser", "Docs", false),
+                ]),
+            );
+
+            wait.recv().unwrap();
+            events.send(
+                EVENT_WINDOW,
+                json!({ "change": "focus", "container": window(2, "browser", "Docs", true) }),
+            );
+            // Titles of windows that are not focused are ignored
+            events.send(
+                EVENT_WINDOW,
+                json!({ "change": "title", "container": window(1, "editor", "lib.rs", false) }),
+            );
+            events.send(
+                EVENT_WINDOW,
+                json!({ "change": "title", "container": window(2, "browser", "Search", true) }),
+            );
+            wait.recv().unwrap();
+            events.send(
+                EVENT_WINDOW,
+                json!({ "change": "close", "container": window(1, "editor", "lib.rs", false) }),
+            );
+            events.send(
+                EVENT_WINDOW,
+                json!({ "change": "close", "container": window(2, "browser", "Search", true) }),
+            );
+            wait.recv().unwrap();
+            events.send(
+                EVENT_WINDOW,
+                json!({ "change": "focus", "container": window(1, "editor", "lib.rs", true) }),
+            );
+            wait.recv().unwrap();
+            events.send(
+                EVENT_WORKSPACE,
+                json!({ "change": "focus", "current": { "type": "workspace", "nodes": [], "floating_nodes": [] } }),
+            );
+            wait.recv().unwrap();
+        });
+
+        let backend = SwayBackend::start(&path).expect("subscribe");
+        assert_eq!(backend.active_window().unwrap().app_name, "editor");
+
+        go.send(()).unwrap();
+        wait_for(&backend, Some(("browser", "Search")));
+        go.send(()).unwrap();
+        wait_for(&backend, None);
+        go.send(()).unwrap();
+        wait_for(&backend, Some(("editor", "lib.rs")));
+        go.send(()).unwrap();
+        wait_for(&backend, None);
+
+        go.send(()).unwrap();
+        server.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn refused_subscription_falls_back() {
+        let path = socket_path("refused");
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let server = thread::spawn(move || {
+            let mut events = FakeSway::accept(&listener);
+            events.expect_request(SUBSCRIBE);
+            events.send(SUBSCRIBE, json!({ "success": false }));
+        });
+
+        assert!(SwayBackend::start(&path).is_none());
+        server.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn picks_the_focused_node() {
+        let mut root = tree(vec![window(1, "editor", "main.rs", false)]);
+        root["nodes"][0]["nodes"][0]["floating_nodes"] = json!([{
+            "id": 3, "type": "floating_con", "name": "Picker", "focused": true,
+            "window_properties": { "class": "Gimp" }
+        }]);
+
+        let info = window_info(find_focused(&root).unwrap());
+        assert_eq!(info.app_name, "Gimp");
+        assert_eq!(info.window_title.as_deref(), Some("Picker"));
+        assert!(find_focused(&tree(vec![])).is_none());
+    }
+}
@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use super::{WindowBackend, WindowInfo};
+
+// Only the handful of wire messages this backend needs; the protocol is simple
+// enough that a full wayland-client binding is not worth the dependency.
+const DISPLAY_ID: u32 = 1;
+const REGISTRY_ID: u32 = 2;
+const SYNC_CALLBACK_ID: u32 = 3;
+const MANAGER_ID: u32 = 4;
+
+const DISPLAY_SYNC: u16 = 0;
+const DISPLAY_GET_REGISTRY: u16 = 1;
+const DISPLAY_EVENT_ERROR: u16 = 0;
+const REGISTRY_BIND: u16 = 0;
+const REGISTRY_EVENT_GLOBAL: u16 = 0;
+const CALLBACK_EVENT_DONE: u16 = 0;
+const MANAGER_EVENT_TOPLEVEL: u16 = 0;
+const MANAGER_EVENT_FINISHED: u16 = 1;
+const HANDLE_DESTROY: u16 = 7;
+const HANDLE_EVENT_TITLE: u16 = 0;
+const HANDLE_EVENT_APP_ID: u16 = 1;
+const HANDLE_EVENT_STATE: u16 = 4;
+const HANDLE_EVENT_DONE: u16 = 5;
+const HANDLE_EVENT_CLOSED: u16 = 6;
+
+const MANAGER_INTERFACE: &str = "zwlr_foreign_toplevel_manager_v1";
+const MANAGER_VERSION: u32 = 3;
+const STATE_ACTIVATED: u32 = 2;
+
+/// Tracks toplevels through wlr-foreign-toplevel-management, supported by
+/// wlroots compositors (sway, Hyprland, river, labwc, ...).
+pub struct WlrBackend {
+    current: Arc<Mutex<Option<WindowInfo>>>,
+}
+
+impl WlrBackend {
+    /// Binds the toplevel manager. Returns `None` when there is no Wayland
+    /// socket or the compositor does not offer the protocol.
+    pub fn start() -> Option<Self> {
+        let mut conn = Wire::connect(socket_path()?).ok()?;
+
+        conn.send(DISPLAY_ID, DISPLAY_GET_REGISTRY, &[Arg::Uint(REGISTRY_ID)]).ok()?;
+        conn.send(DISPLAY_ID, DISPLAY_SYNC, &[Arg::Uint(SYNC_CALLBACK_ID)]).ok()?;
+
+        // Collect globals until the sync callback fires
+        let mut manager = None;
+        loop {
+            let (object, opcode, body) = conn.read_event().ok()?;
+            let mut body = Body::new(&body);
+            match (object, opcode) {
+                (REGISTRY_ID, REGISTRY_EVENT_GLOBAL) => {
+                    let name = body.uint()?;
+                    let interface = body.string()?;
+                    let version = body.uint()?;
+                    if interface == MANAGER_INTERFACE {
+                        manager = Some((name, version.min(MANAGER_VERSION)));
+                    }
+                }
+                (SYNC_CALLBACK_ID, CALLBACK_EVENT_DONE) => break,
+                (DISPLAY_ID, DISPLAY_EVENT_ERROR) => return None,
+                _ => {}
+            }
+        }
+
+        let (name, version) = manager?;
+        conn.send(
+            REGISTRY_ID,
+            REGISTRY_BIND,
+            &[
+                Arg::Uint(name),
+                Arg::Str(MANAGER_INTERFACE),
+                Arg::Uint(version),
+                Arg::Uint(MANAGER_ID),
+            ],
+        )
+        .ok()?;
+        conn.stream.set_read_timeout(None).ok()?;
+
+        let current = Arc::new(Mutex::new(None));
+        let current_clone = current.clone();
+        thread::spawn(move || {
+            if let Err(e) = track_toplevels(conn, current_clone) {
+                eprintln!("wlr toplevel tracking stopped: {}", e);
+            }
+        });
+
+        Some(Self { current })
+    }
+}
+
+impl WindowBackend for WlrBackend {
+    fn name(&self) -> &'static str {
+        "wlr"
+    }
+
+    fn active_window(&self) -> Option<WindowInfo> {
+        self.current.lock().clone()
+    }
+}
+
+#[derive(Default)]
+struct Toplevel {
+    title: Option<String>,
+    app_id: Option<String>,
+    activated: bool,
+}
+
+fn track_toplevels(
+    mut conn: Wire,
+    current: Arc<Mutex<Option<WindowInfo>>>,
+) -> std::io::Result<()> {
+    let mut toplevels: HashMap<u32, Toplevel> = HashMap::new();
+    let mut active: Option<u32> = None;
+
+    loop {
+        let (object, opcode, body) = conn.read_event()?;
+        let mut body = Body::new(&body);
+
+        if object == DISPLAY_ID {
+            if opcode == DISPLAY_EVENT_ERROR {
+                return Err(invalid_data("compositor reported a protocol error"));
+            }
+            continue;
+        }
+
+        if object == MANAGER_ID {
+            match opcode {
+                MANAGER_EVENT_TOPLEVEL => {
+                    let id = body.uint().ok_or_else(|| invalid_data("truncated toplevel event"))?;
+                    toplevels.insert(id, Toplevel::default());
+                }
+                MANAGER_EVENT_FINISHED => return Ok(()),
+                _ => {}
+            }
+            continue;
+        }
+
+        let Some(toplevel) = toplevels.get_mut(&object) else {
+            continue;
+        };
+
+        // title/app_id/state are double-buffered until the matching done event
+        match opcode {
+            HANDLE_EVENT_TITLE => toplevel.title = body.string(),
+            HANDLE_EVENT_APP_ID => toplevel.app_id = body.string(),
+            HANDLE_EVENT_STATE => {
+                toplevel.activated = body
+                    .array()
+                    .map(|states| {
+                        states
+                            .chunks_exact(4)
+                            .any(|s| u32::from_ne_bytes([s[0], s[1], s[2], s[3]]) == STATE_ACTIVATED)
+                    })
+                    .unwrap_or(false);
+            }
+            HANDLE_EVENT_DONE => {
+                if toplevel.activated {
+                    active = Some(object);
+                    *current.lock() = Some(WindowInfo {
+                        app_name: toplevel
+                            .app_id
+                            .clone()
+                            .filter(|s| !s.is_empty())
+                            .unwrap_or_else(|| "Unknown".to_string()),
+                        window_title: toplevel.title.clone().filter(|s| !s.is_empty()),
+                        // The protocol does not tell which process owns a toplevel
+                        exe_path: None,
+                    });
+                } else if active == Some(object) {
+                    active = None;
+                    *current.lock() = None;
+                }
+            }
+            HANDLE_EVENT_CLOSED => {
+                toplevels.remove(&object);
+                if active == Some(object) {
+                    active = None;
+                    *current.lock() = None;
+                }
+                conn.send(object, HANDLE_DESTROY, &[])?;
+            }
+            _ => {}
+        }
+    }
+}
+
+fn socket_path() -> Option<PathBuf> {
+    let display = std::env::var_os("WAYLAND_DISPLAY").unwrap_or_else(|| "wayland-0".into());
+    let display = PathBuf::from(display);
+    if display.is_absolute() {
+        return Some(display);
+    }
+    std::env::var_os("XDG_RUNTIME_DIR").map(|dir| PathBuf::from(dir).join(display))
+}
+
+fn invalid_data(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+enum Arg<'a> {
+    Uint(u32),
+    Str(&'a str),
+}
+
+struct Wire {
+    stream: UnixStream,
+}
+
+impl Wire {
+    fn connect(path: PathBuf) -> std::io::Result<Self> {
+        let stream = UnixStream::connect(path)?;
+        stream.set_read_timeout(Some(Duration::from_secs(1)))?;
+        Ok(Self { stream })
+    }
+
+    fn send(&mut self, object: u32, opcode: u16, args: &[Arg]) -> std::io::Result<()> {
+        let mut body = Vec::new();
+        for arg in args {
+            match arg {
+                Arg::Uint(v) => body.extend_from_slice(&v.to_ne_bytes()),
+                Arg::Str(s) => {
+                    // Length includes the NUL terminator; contents are padded to 32 bits
+                    body.extend_from_slice(&(s.len() as u32 + 1).to_ne_bytes());
+                    body.extend_from_slice(s.as_bytes());
+                    body.push(0);
+                    while body.len() % 4 != 0 {
+                        body.push(0);
+                    }
+                }
+            }
+        }
+
+        let size = (8 + body.len()) as u32;
+        let mut message = Vec::with_capacity(size as usize);
+        message.extend_from_slice(&object.to_ne_bytes());
+        message.extend_from_slice(&((size << 16) | opcode as u32).to_ne_bytes());
+        message.extend_from_slice(&body);
+        self.stream.write_all(&message)
+    }
+
+    fn read_event(&mut self) -> std::io::Result<(u32, u16, Vec<u8>)> {
+        let mut header = [0u8; 8];
+        self.stream.read_exact(&mut header)?;
+        let object = u32::from_ne_bytes([header[0], header[1], header[2], header[3]]);
+        let word = u32::from_ne_bytes([header[4], header[5], header[6], header[7]]);
+        let size = (word >> 16) as usize;
+        if size < 8 {
+            return Err(invalid_data("malformed wayland message"));
+        }
+
+        let mut body = vec![0u8; size - 8];
+        self.stream.read_exact(&mut body)?;
+        Ok((object, (word & 0xffff) as u16, body))
+    }
+}
+
+struct Body<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Body<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn uint(&mut self) -> Option<u32> {
+        let bytes = self.data.get(self.pos..self.pos + 4)?;
+        self.pos += 4;
+        Some(u32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn array(&mut self) -> Option<&'a [u8]> {
+        let len = self.uint()? as usize;
+        let bytes = self.data.get(self.pos..self.pos + len)?;
+        self.pos += (len + 3) & !3;
+        Some(bytes)
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let bytes = self.array()?;
+        let bytes = bytes.strip_suffix(&[0]).unwrap_or(bytes);
+        Some(String::from_utf8_lossy(bytes).to_string())
+    }
+}
@@ -0,0 +1,398 @@
+use parking_lot::Mutex;
+use std::cell::RefCell;
+use std::ffi::CString;
+use std::os::raw::{c_int, c_uchar, c_uint, c_ulong};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use ::x11::xlib;
+
+use super::{process_name, process_path, WindowBackend, WindowInfo};
+use crate::layout::Layout;
+
+/// `XkbUseCoreKbd`: whichever device is the core keyboard
+const XKB_USE_CORE_KBD: c_uint = 0x0100;
+
+type ErrorHandler = unsafe extern "C" fn(*mut xlib::Display, *mut xlib::XErrorEvent) -> c_int;
+
+/// Held while an `ErrorTrap` is installed, so traps on different threads
+/// do not restore each other's handlers
+static TRAP: Mutex<()> = Mutex::new(());
+/// The display whose errors the trap swallows
+static TRAPPED_DISPLAY: AtomicPtr<xlib::Display> = AtomicPtr::new(ptr::null_mut());
+/// The handler the trap replaced, which still gets every other display's
+/// errors (GTK's among them)
+static PREVIOUS_HANDLER: Mutex<Option<ErrorHandler>> = Mutex::new(None);
+
+thread_local! {
+    // Each thread keeps its own connection; Xlib displays must not be shared across threads
+    static DISPLAY: RefCell<Option<Display>> = const { RefCell::new(None) };
+}
+
+struct Display(*mut xlib::Display);
+
+impl Drop for Display {
+    fn drop(&mut self) {
+        unsafe {
+            xlib::XCloseDisplay(self.0);
+        }
+    }
+}
+
+/// The active window can disappear between two property reads, which makes the
+/// server answer with BadWindow. The default Xlib handler exits the process, so
+/// errors on our display are swallowed and the failed read falls back instead.
+unsafe extern "C" fn ignore_x_error(
+    display: *mut xlib::Display,
+    event: *mut xlib::XErrorEvent,
+) -> c_int {
+    if display == TRAPPED_DISPLAY.load(Ordering::SeqCst) {
+        return 0;
+    }
+    match *PREVIOUS_HANDLER.lock() {
+        Some(handler) => handler(display, event),
+        None => 0,
+    }
+}
+
+/// Swallows X errors on one display while it lives. Xlib has a single
+/// process-wide error handler, so ours is only installed around our own
+/// requests, and the one it replaced is put back afterwards.
+struct ErrorTrap {
+    display: *mut xlib::Display,
+    _lock: parking_lot::MutexGuard<'static, ()>,
+}
+
+impl ErrorTrap {
+    unsafe fn new(display: *mut xlib::Display) -> Self {
+        let lock = TRAP.lock();
+        // Errors from requests sent before the trap are not ours to swallow
+        xlib::XSync(display, xlib::False);
+        TRAPPED_DISPLAY.store(display, Ordering::SeqCst);
+        *PREVIOUS_HANDLER.lock() = xlib::XSetErrorHandler(Some(ignore_x_error));
+        Self {
+            display,
+            _lock: lock,
+        }
+    }
+}
+
+impl Drop for ErrorTrap {
+    fn drop(&mut self) {
+        unsafe {
+            // Collect the errors of our requests before letting go
+            xlib::XSync(self.display, xlib::False);
+            xlib::XSetErrorHandler(PREVIOUS_HANDLER.lock().take());
+        }
+        TRAPPED_DISPLAY.store(ptr::null_mut(), Ordering::SeqCst);
+    }
+}
+
+/// Queries the focused window from the X server named by `$DISPLAY`.
+pub struct X11Backend;
+
+impl WindowBackend for X11Backend {
+    fn name(&self) -> &'static str {
+        "x11"
+    }
+
+    fn active_window(&self) -> Option<WindowInfo> {
+        with_display(|display| unsafe { query_active_window(display) })
+    }
+}
+
+/// The layout of the keyboard group in effect
+pub fn active_layout() -> Option<Layout> {
+    with_display(|display| unsafe {
+        let mut state: xlib::XkbStateRec = std::mem::zeroed();
+        if xlib::XkbGetState(display, XKB_USE_CORE_KBD, &mut state) != xlib::Success as c_int {
+            return None;
+        }
+        let root = xlib::XDefaultRootWindow(display);
+        let names = get_property(display, root, "_XKB_RULES_NAMES", xlib::XA_STRING)?;
+        group_layout(&names.to_string_lossy(), state.group.into())
+    })
+}
+
+/// Run `f` against this thread's connection, with X errors trapped
+fn with_display<T>(f: impl FnOnce(*mut xlib::Display) -> Option<T>) -> Option<T> {
+    DISPLAY.with(|cell| {
+        let mut cell = cell.borrow_mut();
+        if cell.is_none() {
+            let display = unsafe { xlib::XOpenDisplay(ptr::null()) };
+            if display.is_null() {
+                return None;
+            }
+            *cell = Some(Display(display));
+        }
+        let display = cell.as_ref()?.0;
+        let _trap = unsafe { ErrorTrap::new(display) };
+        f(display)
+    })
+}
+
+/// `_XKB_RULES_NAMES` holds the rules, model, layouts, variants and options
+/// the keymap was built from, NUL-separated. Layouts are comma-separated, one
+/// per group.
+fn group_layout(rules_names: &str, group: usize) -> Option<Layout> {
+    let layouts = rules_names.split('\0').nth(2)?;
+    let name = layouts.split(',').nth(group)?.trim();
+    if name.is_empty() {
+        return None;
+    }
+    Some(Layout::from_name(name))
+}
+
+unsafe fn query_active_window(display: *mut xlib::Display) -> Option<WindowInfo> {
+    let root = xlib::XDefaultRootWindow(display);
+
+    let window = get_property(display, root, "_NET_ACTIVE_WINDOW", xlib::XA_WINDOW)
+        .and_then(|p| p.first_long())
+        .filter(|&w| w != 0)?;
+
+    // Prefer the EWMH UTF-8 title, fall back to the legacy WM_NAME
+    let utf8_string = intern_atom(display, "UTF8_STRING");
+    let window_title = get_property(display, window, "_NET_WM_NAME", utf8_string)
+        .or_else(|| get_property(display, window, "WM_NAME", xlib::AnyPropertyType as c_ulong))
+        .map(|p| p.to_string_lossy())
+        .filter(|s| !s.is_empty());
+
+    let pid = get_property(display, window, "_NET_WM_PID", xlib::XA_CARDINAL)
+        .and_then(|p| p.first_long())
+        .map(|pid| pid as u32);
+
+    let app_name = pid
+        .and_then(process_name)
+        .or_else(|| get_wm_class(display, window))
+        .unwrap_or_else(|| "Unknown".to_string());
+
+    Some(WindowInfo {
+        app_name,
+        window_title,
+        exe_path: pid.and_then(process_path),
+    })
+}
+
+struct Property {
+    data: *mut c_uchar,
+    format: c_int,
+    nitems: c_ulong,
+}
+
+impl Property {
+    fn first_long(&self) -> Option<c_ulong> {
+        // Format 32 properties are returned as an array of C longs
+        if self.format != 32 || self.nitems == 0 {
+            return None;
+        }
+        Some(unsafe { *(self.data as *const c_ulong) })
+    }
+
+    fn to_string_lossy(&self) -> String {
+        if self.format != 8 {
+            return String::new();
+        }
+        let bytes = unsafe { std::slice::from_raw_parts(self.data, self.nitems as usize) };
+        String::from_utf8_lossy(bytes)
+            .trim_end_matches('\0')
+            .to_string()
+    }
+}
+
+impl Drop for Property {
+    fn drop(&mut self) {
+        unsafe {
+            xlib::XFree(self.data as *mut _);
+        }
+    }
+}
+
+unsafe fn intern_atom(display: *mut xlib::Display, name: &str) -> xlib::Atom {
+    let name = CString::new(name).unwrap();
+    xlib::XInternAtom(display, name.as_ptr(), xlib::False)
+}
+
+unsafe fn get_property(
+    display: *mut xlib::Display,
+    window: xlib::Window,
+    name: &str,
+    req_type: xlib::Atom,
+) -> Option<Property> {
+    let name = CString::new(name).unwrap();
+    let atom = xlib::XInternAtom(display, name.as_ptr(), xlib::True);
+    if atom == 0 {
+        return None;
+    }
+
+    let mut actual_type: xlib::Atom = 0;
+    let mut format: c_int = 0;
+    let mut nitems: c_ulong = 0;
+    let mut bytes_after: c_ulong = 0;
+    let mut data: *mut c_uchar = ptr::null_mut();
+
+    let status = xlib::XGetWindowProperty(
+        display,
+        window,
+        atom,
+        0,
+        1024,
+        xlib::False,
+        req_type,
+        &mut actual_type,
+        &mut format,
+        &mut nitems,
+        &mut bytes_after,
+        &mut data,
+    );
+
+    if status != xlib::Success as c_int || data.is_null() {
+        return None;
+    }
+
+    let property = Property {
+        data,
+        format,
+        nitems,
+    };
+    if actual_type == 0 || nitems == 0 {
+        return None;
+    }
+    Some(property)
+}
+
+unsafe fn get_wm_class(display: *mut xlib::Display, window: xlib::Window) -> Option<String> {
+    let mut hint = xlib::XClassHint {
+        res_name: ptr::null_mut(),
+        res_class: ptr::null_mut(),
+    };
+    if xlib::XGetClassHint(display, window, &mut hint) == 0 {
+        return None;
+    }
+
+    let take = |p: *mut std::os::raw::c_char| {
+        if p.is_null() {
+            return None;
+        }
+        let s = std::ffi::CStr::from_ptr(p).to_string_lossy().to_string();
+        xlib::XFree(p as *mut _);
+        Some(s).filter(|s| !s.is_empty())
+    };
+
+    // WM_CLASS is "instance\0class"; the class is the application-wide name
+    let name = take(hint.res_name);
+    let class = take(hint.res_class);
+    class.or(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+
+    static OTHER_ERRORS: AtomicUsize = AtomicUsize::new(0);
+
+    unsafe extern "C" fn count_error(_: *mut xlib::Display, _: *mut xlib::XErrorEvent) -> c_int {
+        OTHER_ERRORS.fetch_add(1, Ordering::SeqCst);
+        0
+    }
+
+    unsafe fn open_display() -> Display {
+        let display = xlib::XOpenDisplay(ptr::null());
+        assert!(!display.is_null(), "cannot open $DISPLAY");
+        Display(display)
+    }
+
+    unsafe fn set_active_window(display: *mut xlib::Display, window: xlib::Window) {
+        let root = xlib::XDefaultRootWindow(display);
+        let active = intern_atom(display, "_NET_ACTIVE_WINDOW");
+        xlib::XChangeProperty(
+            display,
+            root,
+            active,
+            xlib::XA_WINDOW,
+            32,
+            xlib::PropModeReplace,
+            &window as *const xlib::Window as *const c_uchar,
+            1,
+        );
+        xlib::XSync(display, xlib::False);
+    }
+
+    #[test]
+    fn picks_the_layout_of_the_group() {
+        let names = "evdev\0pc105\0us,de,fr\0,nodeadkeys,\0grp:alt_shift_toggle";
+        assert_eq!(group_layout(names, 0), Some(Layout::Us));
+        assert_eq!(group_layout(names, 1), Some(Layout::German));
+        assert_eq!(group_layout(names, 2), Some(Layout::French));
+        assert_eq!(group_layout(names, 3), None);
+        assert_eq!(group_layout("evdev\0pc105\0gb\0\0", 0), Some(Layout::Us));
+        assert_eq!(group_layout("evdev\0pc105\0\0\0", 0), None);
+        assert_eq!(group_layout("", 0), None);
+    }
+
+    #[test]
+    #[ignore = "needs an X server, e.g. xvfb-run cargo test -- --ignored"]
+    fn reads_the_active_window() {
+        unsafe {
+            let display = open_display();
+            let root = xlib::XDefaultRootWindow(display.0);
+            let window = xlib::XCreateSimpleWindow(display.0, root, 0, 0, 10, 10, 0, 0, 0);
+
+            let title = "Ünïcode title";
+            xlib::XChangeProperty(
+                display.0,
+                window,
+                intern_atom(display.0, "_NET_WM_NAME"),
+                intern_atom(display.0, "UTF8_STRING"),
+                8,
+                xlib::PropModeReplace,
+                title.as_ptr(),
+                title.len() as c_int,
+            );
+            let class = b"editor\0Editor\0";
+            xlib::XChangeProperty(
+                display.0,
+                window,
+                intern_atom(display.0, "WM_CLASS"),
+                xlib::XA_STRING,
+                8,
+                xlib::PropModeReplace,
+                class.as_ptr(),
+                class.len() as c_int,
+            );
+            set_active_window(display.0, window);
+
+            let info = X11Backend.active_window().unwrap();
+            assert_eq!(info.app_name, "Editor");
+            assert_eq!(info.window_title.as_deref(), Some(title));
+            xlib::XDestroyWindow(display.0, window);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs an X server, e.g. xvfb-run cargo test -- --ignored"]
+    fn traps_only_its_own_errors() {
+        unsafe {
+            let display = open_display();
+            let previous = xlib::XSetErrorHandler(Some(count_error));
+
+            // A window that does not exist answers every read with BadWindow
+            set_active_window(display.0, 0x7ff_fff0);
+            let info = X11Backend.active_window().unwrap();
+            assert_eq!(info.app_name, "Unknown");
+            assert_eq!(OTHER_ERRORS.load(Ordering::SeqCst), 0);
+
+            // The handler in place before is back, and sees other errors
+            let restored = xlib::XSetErrorHandler(previous);
+            assert_eq!(
+                restored.map(|h| h as *const ()),
+                Some(count_error as *const ())
+            );
+            xlib::XSetErrorHandler(Some(count_error));
+            xlib::XDestroyWindow(display.0, 0x7ff_fff0);
+            xlib::XSync(display.0, xlib::False);
+            assert_eq!(OTHER_ERRORS.load(Ordering::SeqCst), 1);
+            xlib::XSetErrorHandler(previous);
+        }
+    }
+}
@@ -0,0 +1,265 @@
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+use crate::activity::FocusWrite;
+use crate::database::Database;
+use crate::models::{PendingRecord, WriterMetrics};
+
+/// Records that can wait for the disk before new ones are dropped
+const QUEUE_CAPACITY: usize = 4096;
+/// Most records written in one transaction
+const MAX_BATCH: usize = 256;
+
+enum Message {
+    Record(Box<PendingRecord>),
+    Focus(FocusWrite),
+    /// Answered once everything queued before it has been written
+    Flush(mpsc::Sender<()>),
+}
+
+#[derive(Default)]
+struct Metrics {
+    queued: AtomicUsize,
+    dropped: AtomicU64,
+    written: AtomicU64,
+    failed: AtomicU64,
+    batches: AtomicU64,
+    total_write_us: AtomicU64,
+    last_write_us: AtomicU64,
+    max_write_us: AtomicU64,
+}
+
+/// Saves records, and foreground time, on a thread of its own, so a slow disk
+/// never holds up key handling. Records are queued without blocking and
+/// written in batches, one transaction each; when the queue is full, new
+/// records are dropped and counted rather than stalling the caller.
+pub struct Writer {
+    /// Taken on shutdown, which lets the thread drain the queue and exit
+    sender: Mutex<Option<SyncSender<Message>>>,
+    thread: Mutex<Option<JoinHandle<()>>>,
+    metrics: Arc<Metrics>,
+}
+
+impl Writer {
+    pub fn spawn(db: Arc<Database>) -> Self {
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let metrics = Arc::new(Metrics::default());
+        let thread_metrics = metrics.clone();
+        let thread = thread::spawn(move || run(&db, receiver, &thread_metrics));
+
+        Self {
+            sender: Mutex::new(Some(sender)),
+            thread: Mutex::new(Some(thread)),
+            metrics,
+        }
+    }
+
+    /// Queue a record for writing. Never blocks.
+    pub fn submit(&self, record: PendingRecord) {
+        let sender = self.sender.lock();
+        let sender = match sender.as_ref() {
+            Some(sender) => sender,
+            None => {
+                self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+                eprintln!("Record writer has shut down, dropping record");
+                return;
+            }
+        };
+
+        self.metrics.queued.fetch_add(1, Ordering::Relaxed);
+        match sender.try_send(Message::Record(Box::new(record))) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => {
+                self.metrics.queued.fetch_sub(1, Ordering::Relaxed);
+                self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+                eprintln!("Record write queue is full, dropping record");
+            }
+            Err(TrySendError::Disconnected(_)) => {
+                self.metrics.queued.fetch_sub(1, Ordering::Relaxed);
+                self.metrics.dropped.fetch_add(1, Ordering::Relaxed);
+                eprintln!("Record writer has stopped, dropping record");
+            }
+        }
+    }
+
+    /// Queue a foreground time change. Never blocks; dropped when the queue
+    /// is full, as the next sample starts a fresh span.
+    pub fn submit_focus(&self, write: FocusWrite) {
+        if let Some(sender) = self.sender.lock().as_ref() {
+            if sender.try_send(Message::Focus(write)).is_err() {
+                eprintln!("Record write queue is full, dropping foreground time");
+            }
+        }
+    }
+
+    /// Wait until every record queued so far has been written.
+    pub fn flush(&self) {
+        let (reply, done) = mpsc::channel();
+        // Sending can block on a full queue, so not while holding the lock
+        let sender = self.sender.lock().clone();
+        let sent = match sender {
+            Some(sender) => sender.send(Message::Flush(reply)).is_ok(),
+            None => false,
+        };
+        if sent {
+            let _ = done.recv();
+        }
+    }
+
+    /// Write everything still queued and stop the thread. Records submitted
+    /// afterwards are dropped.
+    pub fn shutdown(&self) {
+        drop(self.sender.lock().take());
+        if let Some(thread) = self.thread.lock().take() {
+            if thread.join().is_err() {
+                eprintln!("Record writer panicked");
+            }
+        }
+    }
+
+    pub fn metrics(&self) -> WriterMetrics {
+        let m = &self.metrics;
+        let batches = m.batches.load(Ordering::Relaxed);
+        WriterMetrics {
+            queue_depth: m.queued.load(Ordering::Relaxed),
+            dropped: m.dropped.load(Ordering::Relaxed),
+            written: m.written.load(Ordering::Relaxed),
+            failed: m.failed.load(Ordering::Relaxed),
+            batches,
+            last_write_us: m.last_write_us.load(Ordering::Relaxed),
+            avg_write_us: m.total_write_us.load(Ordering::Relaxed) / batches.max(1),
+            max_write_us: m.max_write_us.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Writer thread: wait for a record, take whatever else is already queued,
+/// write it all at once. Ends once the sender is gone and the queue is empty.
+fn run(db: &Database, receiver: Receiver<Message>, metrics: &Metrics) {
+    let mut batch = Vec::new();
+    let mut flushes = Vec::new();
+    let mut focus_span = None;
+
+    while let Ok(first) = receiver.recv() {
+        let mut next = Some(first);
+        while let Some(message) = next {
+            match message {
+                Message::Record(record) => batch.push(*record),
+                Message::Focus(write) => focus_span = write_focus(db, write, focus_span),
+                Message::Flush(reply) => flushes.push(reply),
+            }
+            next = if batch.len() < MAX_BATCH {
+                receiver.try_recv().ok()
+            } else {
+                None
+            };
+        }
+
+        write_batch(db, &batch, metrics);
+        batch.clear();
+        for reply in flushes.drain(..) {
+            let _ = reply.send(());
+        }
+    }
+}
+
+/// Apply a foreground time change, returning the id of the open span
+fn write_focus(db: &Database, write: FocusWrite, open_span: Option<i64>) -> Option<i64> {
+    let result = match write {
+        FocusWrite::Start {
+            app_name,
+            start_ms,
+            end_ms,
+        } => db.start_focus_span(&app_name, start_ms, end_ms).map(Some),
+        FocusWrite::Extend { end_ms } => match open_span {
+            Some(id) => db.extend_focus_span(id, end_ms).map(|_| Some(id)),
+            None => Ok(None),
+        },
+    };
+    result.unwrap_or_else(|e| {
+        eprintln!("Failed to save foreground time: {}", e);
+        None
+    })
+}
+
+fn write_batch(db: &Database, batch: &[PendingRecord], metrics: &Metrics) {
+    if batch.is_empty() {
+        return;
+    }
+
+    let started = Instant::now();
+    let mut written = batch.len() as u64;
+    if let Err(e) = db.insert_batch(batch) {
+        // One bad record should not cost the others; try them one at a time
+        eprintln!(
+            "Failed to save {} input records, retrying one by one: {}",
+            batch.len(),
+            e
+        );
+        written = 0;
+        for record in batch {
+            match db.insert_batch(std::slice::from_ref(record)) {
+                Ok(_) => written += 1,
+                Err(e) => {
+                    metrics.failed.fetch_add(1, Ordering::Relaxed);
+                    eprintln!("Failed to save input record: {}", e);
+                }
+            }
+        }
+    }
+    let elapsed = started.elapsed().as_micros() as u64;
+
+    metrics.queued.fetch_sub(batch.len(), Ordering::Relaxed);
+    metrics.written.fetch_add(written, Ordering::Relaxed);
+    metrics.batches.fetch_add(1, Ordering::Relaxed);
+    metrics.total_write_us.fetch_add(elapsed, Ordering::Relaxed);
+    metrics.last_write_us.store(elapsed, Ordering::Relaxed);
+    metrics.max_write_us.fetch_max(elapsed, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::InputRecord;
+    use crate::stats::StatsRange;
+
+    #[test]
+    fn writes_records_and_focus_spans() {
+        let db = Arc::new(Database::in_memory().unwrap());
+        let writer = Writer::spawn(db.clone());
+        writer.submit(PendingRecord {
+            record: InputRecord::new("Editor".into(), None, "hi".into()),
+            key_events: Vec::new(),
+            shortcuts: Vec::new(),
+            key_frequency: Default::default(),
+        });
+        let now = chrono::Utc::now().timestamp_millis();
+        writer.submit_focus(FocusWrite::Start {
+            app_name: "Editor".into(),
+            start_ms: now - 10_000,
+            end_ms: now - 5_000,
+        });
+        writer.submit_focus(FocusWrite::Extend { end_ms: now });
+        writer.flush();
+
+        assert_eq!(writer.metrics().written, 1);
+        let day = |offset| {
+            (chrono::Local::now() + chrono::Duration::days(offset))
+                .format("%Y-%m-%d")
+                .to_string()
+        };
+        let range = StatsRange {
+            start: day(-1),
+            end: day(1),
+        };
+        assert_eq!(db.get_active_time(&range).unwrap().total_ms, 10_000);
+
+        writer.shutdown();
+        writer.flush();
+        assert_eq!(writer.metrics().written, 1);
+    }
+}